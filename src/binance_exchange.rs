@@ -1,7 +1,6 @@
 use {
-    crate::{exchange::*, token::MaybeToken},
+    crate::{db::ReportingTimezone, exchange::*, token::MaybeToken},
     async_trait::async_trait,
-    chrono::{Local, TimeZone},
     serde::Deserialize,
     solana_sdk::pubkey::Pubkey,
     std::collections::HashMap,
@@ -10,6 +9,7 @@ use {
 
 pub struct BinanceExchangeClient {
     account_client: AccountClient,
+    reporting_timezone: ReportingTimezone,
 }
 
 #[derive(Debug, Deserialize)]
@@ -331,10 +331,9 @@ impl ExchangeClient for BinanceExchangeClient {
             }
         };
 
-        let last_update = Local
-            .timestamp(order.update_time.unwrap_or_default() / 1000, 0)
-            .date()
-            .naive_local();
+        let last_update = self
+            .reporting_timezone
+            .date_of_timestamp(order.update_time.unwrap_or_default() / 1000);
 
         Ok(OrderStatus {
             open,
@@ -377,23 +376,35 @@ fn new_with_url(
         secret,
         subaccount,
     }: ExchangeCredentials,
+    reporting_timezone: ReportingTimezone,
 ) -> Result<BinanceExchangeClient, Box<dyn std::error::Error>> {
     if subaccount.is_some() {
         return Err("subaccounts not supported".into());
     }
     Ok(BinanceExchangeClient {
         account_client: AccountClient::connect(api_key, secret, url)?,
+        reporting_timezone,
     })
 }
 
 pub fn new(
     exchange_credentials: ExchangeCredentials,
+    reporting_timezone: ReportingTimezone,
 ) -> Result<BinanceExchangeClient, Box<dyn std::error::Error>> {
-    new_with_url("https://api.binance.com", exchange_credentials)
+    new_with_url(
+        "https://api.binance.com",
+        exchange_credentials,
+        reporting_timezone,
+    )
 }
 
 pub fn new_us(
     exchange_credentials: ExchangeCredentials,
+    reporting_timezone: ReportingTimezone,
 ) -> Result<BinanceExchangeClient, Box<dyn std::error::Error>> {
-    new_with_url("https://api.binance.us", exchange_credentials)
+    new_with_url(
+        "https://api.binance.us",
+        exchange_credentials,
+        reporting_timezone,
+    )
 }
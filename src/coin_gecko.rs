@@ -1,14 +1,13 @@
 use {
-    crate::token::{MaybeToken, Token},
+    crate::{currency::Currency, token::MaybeToken},
     chrono::prelude::*,
     rust_decimal::prelude::*,
     serde::{Deserialize, Serialize},
+    std::collections::HashMap,
 };
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CurrencyList {
-    usd: f64,
-}
+// Keyed by lowercase currency id, eg "usd"
+type CurrencyList = HashMap<String, f64>;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct MarketData {
@@ -27,55 +26,44 @@ struct HistoryResponse {
 }
 
 fn token_to_coin(token: &MaybeToken) -> Result<&'static str, Box<dyn std::error::Error>> {
-    let coin = match token.token() {
-        None => "solana",
-        Some(token) => match token {
-            Token::USDC => "usd-coin",
-            Token::mSOL => "msol",
-            Token::stSOL => "lido-staked-sol",
-            Token::wSOL => "solana",
-            unsupported_token => {
-                return Err(format!(
-                    "Coin Gecko price data not available for {}",
-                    unsupported_token.name()
-                )
-                .into())
-            }
-        },
-    };
-    Ok(coin)
+    match token.token() {
+        None => Ok("solana"),
+        Some(token) => token.coin_gecko_id().ok_or_else(|| {
+            format!("Coin Gecko price data not available for {}", token.name()).into()
+        }),
+    }
 }
 
-pub async fn get_current_price(token: &MaybeToken) -> Result<Decimal, Box<dyn std::error::Error>> {
+pub async fn get_current_price(
+    token: &MaybeToken,
+    currency: Currency,
+) -> Result<Decimal, Box<dyn std::error::Error>> {
     let coin = token_to_coin(token)?;
+    let vs_currency = currency.coin_gecko_id();
     let url = format!(
-        "https://api.coingecko.com/api/v3/simple/price?ids={}&vs_currencies=usd",
-        coin
+        "https://api.coingecko.com/api/v3/simple/price?ids={}&vs_currencies={}",
+        coin, vs_currency
     );
 
-    #[derive(Debug, Serialize, Deserialize)]
-    struct Coins {
-        solana: Option<CurrencyList>,
-        msol: Option<CurrencyList>,
-        #[serde(rename = "lido-staked-sol")]
-        stsol: Option<CurrencyList>,
-    }
-
-    let coins = reqwest::get(url).await?.json::<Coins>().await?;
+    let coins = reqwest::get(url)
+        .await?
+        .json::<HashMap<String, CurrencyList>>()
+        .await?;
 
     coins
-        .solana
-        .or(coins.msol)
-        .or(coins.stsol)
+        .get(coin)
+        .and_then(|currency_list| currency_list.get(&vs_currency))
         .ok_or_else(|| format!("Simple price data not available for {}", coin).into())
-        .map(|price| Decimal::from_f64(price.usd).unwrap())
+        .map(|price| Decimal::from_f64(*price).unwrap())
 }
 
 pub async fn get_historical_price(
     when: NaiveDate,
     token: &MaybeToken,
+    currency: Currency,
 ) -> Result<Decimal, Box<dyn std::error::Error>> {
     let coin = token_to_coin(token)?;
+    let vs_currency = currency.coin_gecko_id();
 
     let url = format!(
         "https://api.coingecko.com/api/v3/coins/{}/history?date={}-{}-{}&localization=false",
@@ -90,6 +78,41 @@ pub async fn get_historical_price(
         .json::<HistoryResponse>()
         .await?
         .market_data
+        .and_then(|market_data| market_data.current_price.get(&vs_currency).copied())
         .ok_or_else(|| format!("Market data not available for {}", when).into())
-        .map(|market_data| Decimal::from_f64(market_data.current_price.usd).unwrap())
+        .map(|price| Decimal::from_f64(price).unwrap())
+}
+
+// Price of `token` nearest to the UTC time `when`, from CoinGecko's intraday market chart
+pub async fn get_historical_price_at(
+    when: NaiveDateTime,
+    token: &MaybeToken,
+    currency: Currency,
+) -> Result<Decimal, Box<dyn std::error::Error>> {
+    let coin = token_to_coin(token)?;
+    let timestamp = when.timestamp();
+
+    // A range of less than a day yields 5-minute granularity from CoinGecko
+    let url = format!(
+        "https://api.coingecko.com/api/v3/coins/{}/market_chart/range?vs_currency={}&from={}&to={}",
+        coin,
+        currency.coin_gecko_id(),
+        timestamp - 3600,
+        timestamp + 3600,
+    );
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct MarketChart {
+        prices: Vec<(f64, f64)>, // (milliseconds since epoch, price)
+    }
+
+    reqwest::get(url)
+        .await?
+        .json::<MarketChart>()
+        .await?
+        .prices
+        .into_iter()
+        .min_by_key(|(ms, _)| ((*ms / 1000.) as i64 - timestamp).abs())
+        .ok_or_else(|| format!("Market chart data not available for {}", when).into())
+        .map(|(_, price)| Decimal::from_f64(price).unwrap())
 }
@@ -0,0 +1,129 @@
+use {
+    chrono::prelude::*,
+    rust_decimal::prelude::*,
+    serde::{Deserialize, Serialize},
+    std::collections::HashMap,
+    strum::{EnumString, IntoStaticStr},
+};
+
+#[derive(
+    Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize, EnumString, IntoStaticStr,
+)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Currency {
+    USD,
+    EUR,
+    CAD,
+    GBP,
+    AUD,
+    CHF,
+    JPY,
+}
+
+pub const POSSIBLE_CURRENCY_VALUES: &[&str] = &["USD", "EUR", "CAD", "GBP", "AUD", "CHF", "JPY"];
+
+impl Default for Currency {
+    fn default() -> Self {
+        Self::USD
+    }
+}
+
+impl Currency {
+    pub fn name(&self) -> &'static str {
+        self.into()
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Currency::USD => "$",
+            Currency::EUR => "€",
+            Currency::CAD => "C$",
+            Currency::GBP => "£",
+            Currency::AUD => "A$",
+            Currency::CHF => "₣",
+            Currency::JPY => "¥",
+        }
+    }
+
+    pub fn coin_gecko_id(&self) -> String {
+        self.name().to_lowercase()
+    }
+}
+
+impl std::fmt::Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize, EnumString, IntoStaticStr)]
+pub enum FxRateSource {
+    #[strum(serialize = "frankfurter")]
+    Frankfurter,
+    #[strum(serialize = "exchangerate.host")]
+    ExchangeRateHost,
+}
+
+pub const POSSIBLE_FX_RATE_SOURCE_VALUES: &[&str] = &["frankfurter", "exchangerate.host"];
+
+impl Default for FxRateSource {
+    fn default() -> Self {
+        Self::Frankfurter
+    }
+}
+
+impl std::fmt::Display for FxRateSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", <&str>::from(self))
+    }
+}
+
+impl FxRateSource {
+    // Units of `to` per unit of `from` on the given date
+    pub async fn get_historical_rate(
+        &self,
+        from: Currency,
+        to: Currency,
+        when: NaiveDate,
+    ) -> Result<Decimal, Box<dyn std::error::Error>> {
+        if from == to {
+            return Ok(Decimal::from_usize(1).unwrap());
+        }
+
+        let url = match self {
+            FxRateSource::Frankfurter => format!(
+                "https://api.frankfurter.app/{}?from={}&to={}",
+                when, from, to
+            ),
+            FxRateSource::ExchangeRateHost => format!(
+                "https://api.exchangerate.host/{}?base={}&symbols={}",
+                when, from, to
+            ),
+        };
+
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Rates {
+            rates: HashMap<String, f64>,
+        }
+
+        reqwest::get(url)
+            .await?
+            .json::<Rates>()
+            .await?
+            .rates
+            .get(to.name())
+            .ok_or_else(|| format!("{} to {} rate not available for {}", from, to, when).into())
+            .map(|rate| Decimal::from_f64(*rate).unwrap())
+    }
+
+    pub async fn convert(
+        &self,
+        amount: f64,
+        from: Currency,
+        to: Currency,
+        when: NaiveDate,
+    ) -> Result<f64, Box<dyn std::error::Error>> {
+        let rate = self.get_historical_rate(from, to, when).await?;
+        Ok(f64::try_from(Decimal::from_f64(amount).unwrap() * rate).unwrap())
+    }
+}
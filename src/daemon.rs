@@ -0,0 +1,201 @@
+use {
+    crate::{
+        db::LotSelectionMethod, exchange::Exchange, field_as_string, plan::default_lot_selection,
+    },
+    serde::Deserialize,
+    std::{
+        fs,
+        path::{Path, PathBuf},
+    },
+};
+
+// A `sys daemon` job configuration
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub epoch_boundary: Option<EpochBoundary>,
+    #[serde(rename = "job", default)]
+    pub jobs: Vec<DaemonJob>,
+}
+
+// Once the inflation rewards of an epoch are available, `sys account sync` runs (which includes
+// the sweep), a summary of the epoch's rewards is sent, and then the hooks run
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EpochBoundary {
+    // Shell commands, run with `SYS_EPOCH` set to the rewarded epoch
+    #[serde(default)]
+    pub hooks: Vec<String>,
+}
+
+impl DaemonConfig {
+    // Plan paths are relative to the configuration file
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config: Self = toml::from_slice(&fs::read(path)?)
+            .map_err(|err| format!("Invalid daemon configuration {}: {}", path.display(), err))?;
+
+        let base_path = path.parent().unwrap_or_else(|| Path::new("."));
+        for job in config.jobs.iter_mut() {
+            if job.every_minutes.is_none() && !job.at_epoch_boundary {
+                return Err(format!(
+                    "Job \"{}\" needs `every_minutes` or `at_epoch_boundary`",
+                    job.name
+                )
+                .into());
+            }
+            if job.every_minutes == Some(0) {
+                return Err(
+                    format!("Job \"{}\": `every_minutes` must be positive", job.name).into(),
+                );
+            }
+            if let JobAction::Plan { plan } = &mut job.action {
+                *plan = base_path.join(&*plan);
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DaemonJob {
+    pub name: String,
+    pub every_minutes: Option<u64>,
+    #[serde(default)]
+    pub at_epoch_boundary: bool,
+    // Failures are always reported
+    #[serde(default)]
+    pub notify_on_success: bool,
+    #[serde(flatten)]
+    pub action: JobAction,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "run", rename_all = "kebab-case")]
+pub enum JobAction {
+    // Same as `sys sync`
+    Sync,
+    // Same as `sys run`, such as sweeps with an `if_source_balance_exceeds` condition
+    Plan {
+        plan: PathBuf,
+    },
+    // A SOL sell order for each rung that doesn't have an open order at its price yet. A rung is
+    // placed again if its order is cancelled, but once filled only if it repeats
+    SellLadder {
+        #[serde(with = "field_as_string")]
+        exchange: Exchange,
+        pair: String,
+        rungs: Vec<SellLadderRung>,
+        if_balance_exceeds: Option<f64>,
+        #[serde(default = "default_lot_selection", with = "field_as_string")]
+        lot_selection: LotSelectionMethod,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SellLadderRung {
+    pub price: f64,
+    pub amount: f64,
+    // Placed again whenever its previous order has filled
+    #[serde(default)]
+    pub repeat: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::test_utils::temp_path};
+
+    fn load(contents: &str) -> Result<DaemonConfig, Box<dyn std::error::Error>> {
+        let dir = temp_path();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("jobs.toml");
+        fs::write(&path, contents).unwrap();
+        let result = DaemonConfig::load(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        result.map(|mut config| {
+            // Strip the temporary directory from plan paths
+            for job in config.jobs.iter_mut() {
+                if let JobAction::Plan { plan } = &mut job.action {
+                    *plan = plan.strip_prefix(&dir).unwrap().to_path_buf();
+                }
+            }
+            config
+        })
+    }
+
+    #[test]
+    fn test_daemon_config_load() {
+        let config = load(
+            r#"
+            [epoch_boundary]
+            hooks = ["echo $SYS_EPOCH"]
+
+            [[job]]
+            name = "sync"
+            run = "sync"
+            every_minutes = 30
+
+            [[job]]
+            name = "sweep"
+            run = "plan"
+            plan = "sweep.toml"
+            at_epoch_boundary = true
+            notify_on_success = true
+
+            [[job]]
+            name = "ladder"
+            run = "sell-ladder"
+            exchange = "ftx"
+            pair = "SOL/USD"
+            every_minutes = 5
+            rungs = [
+                { price = 150.0, amount = 1.0 },
+                { price = 200.0, amount = 1.0, repeat = true },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.epoch_boundary.unwrap().hooks,
+            vec!["echo $SYS_EPOCH".to_string()]
+        );
+        assert_eq!(config.jobs.len(), 3);
+        assert!(matches!(config.jobs[0].action, JobAction::Sync));
+        assert_eq!(config.jobs[0].every_minutes, Some(30));
+        assert!(!config.jobs[0].at_epoch_boundary);
+
+        match &config.jobs[1].action {
+            JobAction::Plan { plan } => assert_eq!(plan, Path::new("sweep.toml")),
+            action => panic!("Unexpected action: {:?}", action),
+        }
+        assert!(config.jobs[1].at_epoch_boundary);
+        assert!(config.jobs[1].notify_on_success);
+
+        match &config.jobs[2].action {
+            JobAction::SellLadder {
+                exchange,
+                rungs,
+                lot_selection,
+                ..
+            } => {
+                assert_eq!(*exchange, Exchange::Ftx);
+                assert_eq!(rungs.len(), 2);
+                assert!(!rungs[0].repeat);
+                assert!(rungs[1].repeat);
+                assert_eq!(*lot_selection, LotSelectionMethod::FirstInFirstOut);
+            }
+            action => panic!("Unexpected action: {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_daemon_config_load_rejects_invalid_jobs() {
+        // No schedule
+        assert!(load("[[job]]\nname = \"sync\"\nrun = \"sync\"\n").is_err());
+        assert!(load("[[job]]\nname = \"sync\"\nrun = \"sync\"\nevery_minutes = 0\n").is_err());
+        assert!(load("[[job]]\nname = \"sync\"\nrun = \"nap\"\nevery_minutes = 1\n").is_err());
+        assert!(load("[epoch_boundary]\nhook = \"true\"\n").is_err());
+        assert!(load("").unwrap().jobs.is_empty());
+    }
+}
@@ -1,19 +1,25 @@
 use {
-    crate::{exchange::*, field_as_string, token::*},
+    crate::{currency::*, exchange::*, field_as_string, option_field_as_string, token::*},
     chrono::{prelude::*, NaiveDate},
+    chrono_tz::Tz,
     pickledb::{PickleDb, PickleDbDumpPolicy},
     rust_decimal::prelude::*,
     separator::FixedPlaceSeparatable,
     serde::{Deserialize, Serialize},
     solana_sdk::{
+        bs58,
         clock::{Epoch, Slot},
+        hash::Hash,
+        instruction::Instruction,
         pubkey::Pubkey,
+        reward_type::RewardType,
         signature::Signature,
     },
     std::{
-        collections::HashSet,
+        collections::{HashMap, HashSet},
         fmt, fs,
         path::{Path, PathBuf},
+        str::FromStr,
     },
     strum::{EnumString, IntoStaticStr},
     thiserror::Error,
@@ -42,6 +48,9 @@ pub enum DbError {
     #[error("Account has insufficient balance: {0}")]
     AccountHasInsufficientBalance(Pubkey),
 
+    #[error("Lot balance mismatch: {0} ({1}) lots add up to {2}, but its balance is {3}")]
+    LotBalanceMismatch(Pubkey, MaybeToken, u64, u64),
+
     #[error("Open order not exist: {0}")]
     OpenOrderDoesNotExist(String),
 
@@ -54,14 +63,32 @@ pub enum DbError {
     #[error("Lot delete failed: {0}")]
     LotDeleteFailed(String),
 
+    #[error("Lot reprice failed: {0}")]
+    LotRepriceFailed(String),
+
     #[error("Import failed: {0}")]
     ImportFailed(String),
+
+    #[error("Base currency change failed: {0}")]
+    BaseCurrencyChangeFailed(String),
 }
 
 pub type DbResult<T> = std::result::Result<T, DbError>;
 
+// Last valid block height recorded for a transaction signed outside of `sys`, by an offline signer
+// or a multisig, which doesn't expire by block height
+pub const OFFLINE_BLOCK_HEIGHT: u64 = u64::MAX;
+
 pub fn new<P: AsRef<Path>>(db_path: P) -> DbResult<Db> {
-    let db_path = db_path.as_ref();
+    open(db_path.as_ref(), false)
+}
+
+// Opens a throwaway copy of the database. Changes are kept in memory and never written to disk
+pub fn new_dry_run<P: AsRef<Path>>(db_path: P) -> DbResult<Db> {
+    open(db_path.as_ref(), true)
+}
+
+fn open(db_path: &Path, dry_run: bool) -> DbResult<Db> {
     if !db_path.exists() {
         fs::create_dir_all(db_path)?;
     }
@@ -85,6 +112,7 @@ pub fn new<P: AsRef<Path>>(db_path: P) -> DbResult<Db> {
         db,
         credentials_db,
         auto_save: true,
+        dry_run,
     })
 }
 
@@ -92,6 +120,7 @@ pub struct Db {
     db: PickleDb,
     credentials_db: PickleDb,
     auto_save: bool,
+    dry_run: bool,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -131,6 +160,11 @@ pub struct PendingTransfer {
     pub to_token: MaybeToken,
 
     pub lots: Vec<Lot>,
+
+    // The from account is no longer tracked once the transfer is confirmed, such as the source
+    // of a stake account merge
+    #[serde(default)]
+    pub remove_from_account: bool,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -175,10 +209,17 @@ pub enum LotAcquistionKind {
         epoch: Epoch,
         slot: Slot,
     },
+    BlockReward {
+        slot: Slot, // First block of the lot
+        reward_type: RewardType,
+        blocks: usize, // More than one if rewards are aggregated by day
+    },
     Transaction {
         slot: Slot,
         #[serde(with = "field_as_string")]
         signature: Signature,
+        #[serde(default)]
+        income: bool, // Credit from an untracked source, subject to income tax
     },
     Exchange {
         exchange: Exchange,
@@ -201,7 +242,30 @@ impl fmt::Display for LotAcquistionKind {
             LotAcquistionKind::EpochReward { epoch, slot } => {
                 write!(f, "epoch {} reward (slot {})", epoch, slot)
             }
-            LotAcquistionKind::Transaction { signature, .. } => write!(f, "{}", signature),
+            LotAcquistionKind::BlockReward {
+                slot,
+                reward_type,
+                blocks,
+            } => {
+                if *blocks == 1 {
+                    write!(f, "block {} {} reward", slot, reward_type)
+                } else {
+                    write!(
+                        f,
+                        "{} blocks {} reward (from slot {})",
+                        blocks, reward_type, slot
+                    )
+                }
+            }
+            LotAcquistionKind::Transaction {
+                signature, income, ..
+            } => {
+                if *income {
+                    write!(f, "{} (income)", signature)
+                } else {
+                    write!(f, "{}", signature)
+                }
+            }
             LotAcquistionKind::Exchange {
                 exchange,
                 pair,
@@ -239,15 +303,27 @@ impl fmt::Display for LotAcquistionKind {
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct LotAcquistion {
     pub when: NaiveDate,
-    price: Option<f64>,             // USD per SOL/token
+    #[serde(default)]
+    pub time: Option<NaiveDateTime>, // Reporting timezone time of acquisition, if priced with intraday precision
+    price: Option<f64>,             // Base currency per SOL/token
     decimal_price: Option<Decimal>, // Prefer over `price` if Some(_)
     pub kind: LotAcquistionKind,
 }
 
 impl LotAcquistion {
     pub fn new(when: NaiveDate, decimal_price: Decimal, kind: LotAcquistionKind) -> Self {
+        Self::new_with_time(when, None, decimal_price, kind)
+    }
+
+    pub fn new_with_time(
+        when: NaiveDate,
+        time: Option<NaiveDateTime>,
+        decimal_price: Decimal,
+        kind: LotAcquistionKind,
+    ) -> Self {
         Self {
             when,
+            time,
             price: None,
             decimal_price: Some(decimal_price),
             kind,
@@ -258,6 +334,11 @@ impl LotAcquistion {
         self.decimal_price
             .unwrap_or_else(|| Decimal::from_f64(self.price.unwrap_or_default()).unwrap())
     }
+
+    fn set_price(&mut self, decimal_price: Decimal) {
+        self.price = None;
+        self.decimal_price = Some(decimal_price);
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize, EnumString, IntoStaticStr)]
@@ -281,6 +362,126 @@ impl Default for LotSelectionMethod {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, EnumString, IntoStaticStr)]
+pub enum PricePrecision {
+    #[strum(serialize = "day")]
+    Day,
+    #[strum(serialize = "hour")]
+    Hour,
+    #[strum(serialize = "minute")]
+    Minute,
+}
+
+pub const POSSIBLE_PRICE_PRECISION_VALUES: &[&str] = &["day", "hour", "minute"];
+
+impl Default for PricePrecision {
+    fn default() -> Self {
+        Self::Day
+    }
+}
+
+impl PricePrecision {
+    // Truncate `time` to this precision, or `None` if only the date is of interest
+    pub fn truncate(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self {
+            PricePrecision::Day => None,
+            PricePrecision::Hour => Some(time.date().and_hms(time.hour(), 0, 0)),
+            PricePrecision::Minute => Some(time.date().and_hms(time.hour(), time.minute(), 0)),
+        }
+    }
+}
+
+impl fmt::Display for PricePrecision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", <&str>::from(self))
+    }
+}
+
+// Applied to every transaction submitted by `sys`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub struct TransactionSettings {
+    pub compute_unit_price: u64, // Priority fee in micro-lamports per compute unit, 0 = none
+    pub rebroadcast_interval: u64, // Seconds between broadcasts of a pending transaction
+    pub max_blockhash_refreshes: u32, // Attempts with a new blockhash after the first expires
+}
+
+impl Default for TransactionSettings {
+    fn default() -> Self {
+        Self {
+            compute_unit_price: 0,
+            rebroadcast_interval: 2,
+            max_blockhash_refreshes: 2,
+        }
+    }
+}
+
+impl fmt::Display for TransactionSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "priority fee {} micro-lamports/CU, rebroadcast every {}s, up to {} blockhash refreshes",
+            self.compute_unit_price, self.rebroadcast_interval, self.max_blockhash_refreshes
+        )
+    }
+}
+
+// Timezone used to assign dates and times to lots. Defaults to the system's local timezone
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct ReportingTimezone(Option<Tz>);
+
+impl ReportingTimezone {
+    pub fn local_datetime(&self, utc_time: NaiveDateTime) -> NaiveDateTime {
+        match self.0 {
+            None => Local.from_utc_datetime(&utc_time).naive_local(),
+            Some(tz) => tz.from_utc_datetime(&utc_time).naive_local(),
+        }
+    }
+
+    // Inverse of `local_datetime`, picking the earlier instant if `local_time` is ambiguous
+    pub fn utc_datetime(&self, local_time: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self.0 {
+            None => Local
+                .from_local_datetime(&local_time)
+                .earliest()
+                .map(|dt| dt.naive_utc()),
+            Some(tz) => tz
+                .from_local_datetime(&local_time)
+                .earliest()
+                .map(|dt| dt.naive_utc()),
+        }
+    }
+
+    pub fn date_of_timestamp(&self, timestamp: i64) -> NaiveDate {
+        self.local_datetime(NaiveDateTime::from_timestamp(timestamp, 0))
+            .date()
+    }
+
+    pub fn today(&self) -> NaiveDate {
+        self.local_datetime(Utc::now().naive_utc()).date()
+    }
+}
+
+impl FromStr for ReportingTimezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "local" {
+            Ok(Self(None))
+        } else {
+            Tz::from_str(s).map(|tz| Self(Some(tz)))
+        }
+    }
+}
+
+impl fmt::Display for ReportingTimezone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            None => write!(f, "local"),
+            Some(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Lot {
     pub lot_number: usize,
@@ -293,17 +494,18 @@ impl Lot {
     pub fn income(&self, token: MaybeToken) -> f64 {
         match self.acquisition.kind {
             // These lots were acquired pre-tax
-            LotAcquistionKind::EpochReward { .. } | LotAcquistionKind::NotAvailable => {
-                (self.acquisition.price()
-                    * Decimal::from_f64(token.ui_amount(self.amount)).unwrap())
-                .try_into()
-                .unwrap()
-            }
+            LotAcquistionKind::EpochReward { .. }
+            | LotAcquistionKind::BlockReward { .. }
+            | LotAcquistionKind::NotAvailable
+            | LotAcquistionKind::Transaction { income: true, .. } => (self.acquisition.price()
+                * Decimal::from_f64(token.ui_amount(self.amount)).unwrap())
+            .try_into()
+            .unwrap(),
             // Assume these kinds of lots are acquired with post-tax funds
             LotAcquistionKind::Exchange { .. }
             | LotAcquistionKind::Fiat
             | LotAcquistionKind::Swap { .. }
-            | LotAcquistionKind::Transaction { .. } => 0.,
+            | LotAcquistionKind::Transaction { income: false, .. } => 0.,
         }
     }
     // Figure the current cap gain/loss for the Lot
@@ -333,6 +535,14 @@ pub enum LotDisposalKind {
         amount: Option<u64>,
     },
     Fiat,
+    Fee {
+        description: String,
+        // The account that paid the fees, and the slot at which they were observed
+        #[serde(default, with = "option_field_as_string")]
+        payer: Option<Pubkey>,
+        #[serde(default)]
+        slot: Option<Slot>,
+    },
 }
 
 impl LotDisposalKind {
@@ -341,7 +551,8 @@ impl LotDisposalKind {
             LotDisposalKind::Usd { fee, .. } => fee.as_ref(),
             LotDisposalKind::Other { .. }
             | LotDisposalKind::Swap { .. }
-            | LotDisposalKind::Fiat { .. } => None,
+            | LotDisposalKind::Fiat { .. }
+            | LotDisposalKind::Fee { .. } => None,
         }
     }
 }
@@ -386,6 +597,9 @@ impl fmt::Display for LotDisposalKind {
                 }
             }
             LotDisposalKind::Fiat => write!(f, "fiat"),
+            LotDisposalKind::Fee { description, .. } => {
+                write!(f, "Fee expense: {}", description)
+            }
         }
     }
 }
@@ -394,7 +608,9 @@ impl fmt::Display for LotDisposalKind {
 pub struct DisposedLot {
     pub lot: Lot,
     pub when: NaiveDate,
-    price: Option<f64>,             // USD per SOL/token
+    #[serde(default)]
+    pub time: Option<NaiveDateTime>, // Reporting timezone time of disposal, if known with intraday precision
+    price: Option<f64>,             // Base currency per SOL/token
     decimal_price: Option<Decimal>, // Prefer over `price` if Some(_)
     pub kind: LotDisposalKind,
     #[serde(default = "MaybeToken::SOL")]
@@ -419,6 +635,12 @@ pub struct TrackedAccount {
     pub last_update_balance: u64, // lamports/tokens
     pub lots: Vec<Lot>,
     pub no_sync: Option<bool>,
+    #[serde(default, with = "option_field_as_string")]
+    pub last_update_signature: Option<Signature>, // Most recent transaction seen by `sync`
+    #[serde(default, with = "option_field_as_string")]
+    pub last_stake_change_signature: Option<Signature>, // Most recent stake change seen by `sync`
+    #[serde(default, with = "option_field_as_string")]
+    pub token_account: Option<Pubkey>, // Token account to use instead of the associated token account
 }
 
 fn split_lots(
@@ -490,17 +712,35 @@ fn split_lots(
 }
 
 impl TrackedAccount {
-    pub fn assert_lot_balance(&self) {
+    // Address whose balance is tracked: `address` itself for SOL, otherwise its token account
+    pub fn balance_address(&self) -> Pubkey {
+        match self.token.token() {
+            Some(token) => self
+                .token_account
+                .unwrap_or_else(|| token.ata(&self.address)),
+            None => self.address,
+        }
+    }
+
+    // The lot balance, if it differs from the account balance
+    pub fn lot_balance_mismatch(&self) -> Option<u64> {
         let lot_balance: u64 = self.lots.iter().map(|lot| lot.amount).sum();
-        assert_eq!(
-            lot_balance, self.last_update_balance,
-            "Lot balance mismatch: {:?}",
-            self
-        );
+        (lot_balance != self.last_update_balance).then(|| lot_balance)
+    }
+
+    pub fn check_lot_balance(&self) -> DbResult<()> {
+        match self.lot_balance_mismatch() {
+            None => Ok(()),
+            Some(lot_balance) => Err(DbError::LotBalanceMismatch(
+                self.address,
+                self.token,
+                lot_balance,
+                self.last_update_balance,
+            )),
+        }
     }
 
     fn remove_lot(&mut self, lot_number: usize) {
-        self.assert_lot_balance();
         let lots = std::mem::take(&mut self.lots);
         self.lots = lots
             .into_iter()
@@ -515,7 +755,7 @@ impl TrackedAccount {
         lot_selection_method: LotSelectionMethod,
         lot_numbers: Option<HashSet<usize>>,
     ) -> DbResult<Vec<Lot>> {
-        self.assert_lot_balance();
+        self.check_lot_balance()?;
 
         let mut lots = std::mem::take(&mut self.lots);
         lots.sort_by_key(|lot| lot.acquisition.when);
@@ -530,7 +770,6 @@ impl TrackedAccount {
 
         self.lots = remaining_lots;
         self.last_update_balance -= amount;
-        self.assert_lot_balance();
         Ok(extracted_lots)
     }
 
@@ -549,7 +788,6 @@ impl TrackedAccount {
             }
         }
         self.last_update_balance += amount;
-        self.assert_lot_balance();
     }
 
     fn merge_or_add_lot(&mut self, new_lot: Lot) {
@@ -567,7 +805,46 @@ impl TrackedAccount {
 pub struct SweepStakeAccount {
     #[serde(with = "field_as_string")]
     pub address: Pubkey,
-    pub stake_authority: PathBuf,
+    pub stake_authority: String, // Signer URI
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, EnumString, IntoStaticStr)]
+pub enum BlockRewardAggregation {
+    #[strum(serialize = "block")]
+    Block,
+    #[strum(serialize = "day")]
+    Day,
+}
+
+pub const POSSIBLE_BLOCK_REWARD_AGGREGATION_VALUES: &[&str] = &["block", "day"];
+
+impl Default for BlockRewardAggregation {
+    fn default() -> Self {
+        Self::Day
+    }
+}
+
+impl fmt::Display for BlockRewardAggregation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", <&str>::from(self))
+    }
+}
+
+// A tracked account that is a validator identity, whose block rewards are recorded by `sync`
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ValidatorIdentity {
+    #[serde(with = "field_as_string")]
+    pub address: Pubkey,
+    pub aggregation: BlockRewardAggregation,
+    pub last_scanned_slot: Slot,
+}
+
+// A tracked account whose unexpected balance decreases are transaction fees, recorded by `sync` as
+// expenses
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct FeePayer {
+    #[serde(with = "field_as_string")]
+    pub address: Pubkey,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -576,6 +853,120 @@ pub struct TransitorySweepStake {
     pub address: Pubkey,
 }
 
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct DeactivatingStake {
+    #[serde(with = "field_as_string")]
+    pub address: Pubkey,
+    pub epoch: Epoch, // Epoch in which the deactivation was requested
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum RedelegationState {
+    Deactivating, // Waiting for the stake to cool down
+    Inactive,     // Ready to be delegated to the new vote account
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PendingRedelegation {
+    #[serde(with = "field_as_string")]
+    pub address: Pubkey,
+    #[serde(with = "field_as_string")]
+    pub vote_address: Pubkey,
+    pub stake_authority: String, // Signer URI
+    pub new_account: bool,       // Withdraw into a new stake account before delegating
+    pub epoch: Epoch,            // Epoch in which the redelegation was requested
+    pub state: RedelegationState,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ProposalAccount {
+    #[serde(with = "field_as_string")]
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+// An instruction of a multisig proposal payload
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ProposalInstruction {
+    #[serde(with = "field_as_string")]
+    pub program_id: Pubkey,
+    pub accounts: Vec<ProposalAccount>,
+    pub data: String, // base58
+}
+
+impl From<&Instruction> for ProposalInstruction {
+    fn from(instruction: &Instruction) -> Self {
+        Self {
+            program_id: instruction.program_id,
+            accounts: instruction
+                .accounts
+                .iter()
+                .map(|account_meta| ProposalAccount {
+                    pubkey: account_meta.pubkey,
+                    is_signer: account_meta.is_signer,
+                    is_writable: account_meta.is_writable,
+                })
+                .collect(),
+            data: bs58::encode(&instruction.data).into_string(),
+        }
+    }
+}
+
+impl ProposalInstruction {
+    // Whether `instruction`, as executed by the multisig program, is this instruction
+    pub fn matches(&self, instruction: &Instruction) -> bool {
+        self.program_id == instruction.program_id
+            && self.data == bs58::encode(&instruction.data).into_string()
+            && self
+                .accounts
+                .iter()
+                .map(|account| account.pubkey)
+                .eq(instruction.accounts.iter().map(|account| account.pubkey))
+    }
+}
+
+// Instructions emitted for execution by a multisig. The pending deposit, swap or transfer of the
+// instructions, if any, is recorded under `pending_signature` until the proposal executes
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PendingMultisigProposal {
+    #[serde(with = "field_as_string")]
+    pub proposal: Pubkey,
+    #[serde(default, with = "option_field_as_string")]
+    pub pending_signature: Option<Signature>,
+    pub instructions: Vec<ProposalInstruction>,
+}
+
+// A transfer, deposit, swap, delegation or deactivation completed by `sys`, which balance and
+// stake change detection must not apply a second time
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct CompletedTransfer {
+    #[serde(with = "field_as_string")]
+    pub signature: Signature,
+    #[serde(default)]
+    pub when: Option<NaiveDate>, // `None` if completed before completion dates were recorded
+}
+
+// A `sys daemon` sell ladder rung whose order was placed, identified by its price in cents and
+// amount. The record is removed when the order is cancelled without a fill
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PlacedSellLadderRung {
+    pub exchange: Exchange,
+    pub pair: String,
+    pub price_cents: u64,
+    pub amount: u64,
+    pub order_id: String,
+    pub filled: bool,
+}
+
+// Steps of a `sys run` plan completed so far. `plan_hash` detects a plan edited since
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PlanProgress {
+    #[serde(with = "field_as_string")]
+    pub plan_hash: Hash,
+    pub completed_steps: usize,
+}
+
 impl Db {
     pub fn set_exchange_credentials(
         &mut self,
@@ -588,7 +979,7 @@ impl Db {
             .set(&format!("{:?}", exchange), &exchange_credentials)
             .unwrap();
 
-        Ok(self.credentials_db.dump()?)
+        self.save_credentials()
     }
 
     pub fn get_exchange_credentials(&self, exchange: Exchange) -> Option<ExchangeCredentials> {
@@ -598,6 +989,13 @@ impl Db {
     pub fn clear_exchange_credentials(&mut self, exchange: Exchange) -> DbResult<()> {
         if self.get_exchange_credentials(exchange).is_some() {
             self.credentials_db.rem(&format!("{:?}", exchange)).ok();
+            self.save_credentials()?;
+        }
+        Ok(())
+    }
+
+    fn save_credentials(&mut self) -> DbResult<()> {
+        if !self.dry_run {
             self.credentials_db.dump()?;
         }
         Ok(())
@@ -624,7 +1022,7 @@ impl Db {
     }
 
     fn save(&mut self) -> DbResult<()> {
-        if self.auto_save {
+        if self.auto_save && !self.dry_run {
             self.db.dump()?;
         }
         Ok(())
@@ -662,6 +1060,7 @@ impl Db {
                 to_address: deposit_address,
                 to_token: token,
                 lots: from_account.extract_lots(self, amount, lot_selection_method, lot_numbers)?,
+                remove_from_account: false,
             },
         };
         self.db.ladd("deposits", &deposit).unwrap();
@@ -672,7 +1071,7 @@ impl Db {
     fn complete_deposit(
         &mut self,
         signature: Signature,
-        success: Option<NaiveDate>,
+        success: Option<(NaiveDate, Decimal)>,
     ) -> DbResult<()> {
         let mut pending_deposits = self.pending_deposits(None);
 
@@ -688,15 +1087,26 @@ impl Db {
         self.db.lcreate("deposits")?;
         self.db.lextend("deposits", &pending_deposits).unwrap();
 
-        self.complete_transfer_or_deposit(transfer, success, false) // `complete_transfer_or_deposit` calls `save`...
+        self.complete_transfer_or_deposit(
+            transfer,
+            success.map(|(when, _)| when),
+            success.map(|(_, fiat_price)| fiat_price),
+        ) // `complete_transfer_or_deposit` calls `save`...
     }
 
     pub fn cancel_deposit(&mut self, signature: Signature) -> DbResult<()> {
         self.complete_deposit(signature, None)
     }
 
-    pub fn confirm_deposit(&mut self, signature: Signature, when: NaiveDate) -> DbResult<()> {
-        self.complete_deposit(signature, Some(when))
+    // `fiat_price` is the base currency price at which deposited `fiat_fungible()` lots are
+    // disposed of
+    pub fn confirm_deposit(
+        &mut self,
+        signature: Signature,
+        when: NaiveDate,
+        fiat_price: Decimal,
+    ) -> DbResult<()> {
+        self.complete_deposit(signature, Some((when, fiat_price)))
     }
 
     pub fn pending_deposits(&self, exchange: Option<Exchange>) -> Vec<PendingDeposit> {
@@ -757,7 +1167,7 @@ impl Db {
     fn complete_swap(
         &mut self,
         signature: Signature,
-        success: Option<(NaiveDate, u64, u64)>,
+        success: Option<(NaiveDate, Option<NaiveDateTime>, u64, u64)>,
     ) -> DbResult<()> {
         let mut pending_swaps = self.pending_swaps();
         let PendingSwap {
@@ -789,7 +1199,7 @@ impl Db {
             .ok_or(DbError::AccountDoesNotExist(address, to_token))?;
 
         self.auto_save(false)?;
-        if let Some((when, from_amount, to_amount)) = success {
+        if let Some((when, time, from_amount, to_amount)) = success {
             let lots = from_account.extract_lots(self, from_amount, lot_selection_method, None)?;
             let mut disposed_lots = self.disposed_lots();
 
@@ -801,6 +1211,7 @@ impl Db {
                 disposed_lots.push(DisposedLot {
                     lot,
                     when,
+                    time,
                     price: None,
                     decimal_price: Some(from_token_price),
                     kind: LotDisposalKind::Swap {
@@ -812,6 +1223,7 @@ impl Db {
                 });
             }
             self.db.set("disposed-lots", &disposed_lots).unwrap();
+            self.record_completed_transfer(signature, when);
 
             to_account.merge_or_add_lot(Lot {
                 lot_number: self.next_lot_number(),
@@ -819,6 +1231,7 @@ impl Db {
                     price: None,
                     decimal_price: Some(to_token_price),
                     when,
+                    time,
                     kind: LotAcquistionKind::Swap {
                         signature,
                         token: from_token,
@@ -842,10 +1255,11 @@ impl Db {
         &mut self,
         signature: Signature,
         when: NaiveDate,
+        time: Option<NaiveDateTime>,
         from_amount: u64,
         to_amount: u64,
     ) -> DbResult<()> {
-        self.complete_swap(signature, Some((when, from_amount, to_amount)))
+        self.complete_swap(signature, Some((when, time, from_amount, to_amount)))
     }
 
     pub fn pending_swaps(&self) -> Vec<PendingSwap> {
@@ -869,6 +1283,7 @@ impl Db {
         to_address: Pubkey,
         lot_selection_method: LotSelectionMethod,
         lot_numbers: Option<HashSet<usize>>,
+        fiat_price: Decimal, // Base currency price of a `token.fiat_fungible()` lot
     ) -> DbResult<()> {
         if !self.db.lexists("withdrawals") {
             self.db.lcreate("withdrawals")?;
@@ -889,17 +1304,11 @@ impl Db {
             // invent a new lot if `token.fiat_fungible()`
             assert!(from_account.lots.is_empty());
 
-            let today = Local::now().date();
-            let when = NaiveDate::from_ymd(today.year(), today.month(), today.day());
+            let when = self.get_reporting_timezone().today();
 
             vec![Lot {
                 lot_number: self.next_lot_number(),
-                acquisition: LotAcquistion {
-                    price: Some(1.),
-                    decimal_price: None,
-                    when,
-                    kind: LotAcquistionKind::Fiat,
-                },
+                acquisition: LotAcquistion::new(when, fiat_price, LotAcquistionKind::Fiat),
                 amount,
             }]
         } else {
@@ -1078,6 +1487,7 @@ impl Db {
 
         open_orders.retain(|o| o.order_id != order_id);
         self.db.set("orders", &open_orders).unwrap();
+        self.close_placed_sell_ladder_rung(&order_id, filled_amount > 0);
 
         match side {
             OrderSide::Buy => {
@@ -1092,6 +1502,7 @@ impl Db {
                         lot_number: self.next_lot_number(),
                         acquisition: LotAcquistion {
                             when,
+                            time: None,
                             price: Some(price),
                             decimal_price: None,
                             kind: LotAcquistionKind::Exchange {
@@ -1131,6 +1542,7 @@ impl Db {
                         disposed_lots.push(DisposedLot {
                             lot,
                             when,
+                            time: None,
                             price: Some(price),
                             decimal_price: None,
                             kind: LotDisposalKind::Usd {
@@ -1185,6 +1597,31 @@ impl Db {
         Ok(disposed_lots)
     }
 
+    // Disposes of `amount` from the lots of `account` as a fee expense. The caller must call
+    // `update_account()` with `account`...
+    pub fn record_fee_disposal(
+        &mut self,
+        account: &mut TrackedAccount,
+        amount: u64,
+        description: String,
+        slot: Slot,
+        when: NaiveDate,
+        decimal_price: Decimal,
+    ) -> DbResult<Vec<DisposedLot>> {
+        let lots = account.extract_lots(self, amount, LotSelectionMethod::default(), None)?;
+        self.record_lots_disposal(
+            account.token,
+            lots,
+            LotDisposalKind::Fee {
+                description,
+                payer: Some(account.address),
+                slot: Some(slot),
+            },
+            when,
+            decimal_price,
+        )
+    }
+
     // The caller must call `save()`...
     fn record_lots_disposal(
         &mut self,
@@ -1199,6 +1636,7 @@ impl Db {
             disposed_lots.push(DisposedLot {
                 lot,
                 when,
+                time: None,
                 price: None,
                 decimal_price: Some(decimal_price),
                 kind: kind.clone(),
@@ -1229,7 +1667,7 @@ impl Db {
     }
 
     pub fn add_account_no_save(&mut self, account: TrackedAccount) -> DbResult<()> {
-        account.assert_lot_balance();
+        account.check_lot_balance()?;
 
         if !self.db.lexists("accounts") {
             self.db.lcreate("accounts")?;
@@ -1249,7 +1687,7 @@ impl Db {
     }
 
     pub fn update_account(&mut self, account: TrackedAccount) -> DbResult<()> {
-        account.assert_lot_balance();
+        account.check_lot_balance()?;
 
         let position = self
             .get_account_position(account.address, account.token)
@@ -1324,6 +1762,43 @@ impl Db {
         }
     }
 
+    // Mints of the token registry tokens recorded anywhere in the database
+    pub fn registered_token_mints(&self) -> HashSet<Pubkey> {
+        let acquisition_token = |lot: &Lot| match lot.acquisition.kind {
+            LotAcquistionKind::Swap { token, .. } => Some(token),
+            _ => None,
+        };
+
+        let mut tokens = vec![];
+        for account in self.get_accounts() {
+            tokens.push(account.token);
+            tokens.extend(account.lots.iter().filter_map(acquisition_token));
+        }
+        for order in self.open_orders(None, None) {
+            tokens.push(order.token);
+            tokens.extend(order.lots.iter().filter_map(acquisition_token));
+        }
+        for disposed_lot in self.disposed_lots() {
+            tokens.push(disposed_lot.token);
+            tokens.extend(acquisition_token(&disposed_lot.lot));
+            if let LotDisposalKind::Swap { token, .. } = disposed_lot.kind {
+                tokens.push(token);
+            }
+        }
+        for pending_swap in self.pending_swaps() {
+            tokens.push(pending_swap.from_token);
+            tokens.push(pending_swap.to_token);
+        }
+
+        tokens
+            .into_iter()
+            .filter_map(|token| match token.token() {
+                Some(Token::Registered(mint)) => Some(mint),
+                _ => None,
+            })
+            .collect()
+    }
+
     pub fn get_accounts(&self) -> Vec<TrackedAccount> {
         if !self.db.lexists("accounts") {
             return vec![];
@@ -1341,60 +1816,195 @@ impl Db {
         lot_number
     }
 
-    pub fn get_sweep_stake_account(&self) -> Option<SweepStakeAccount> {
-        self.db.get("sweep-stake-account")
+    pub fn get_price_precision(&self) -> PricePrecision {
+        self.db.get("price-precision").unwrap_or_default()
     }
 
-    pub fn set_sweep_stake_account(
+    pub fn set_price_precision(&mut self, price_precision: PricePrecision) -> DbResult<()> {
+        self.db.set("price-precision", &price_precision).unwrap();
+        self.save()
+    }
+
+    pub fn get_transaction_settings(&self) -> TransactionSettings {
+        self.db.get("transaction-settings").unwrap_or_default()
+    }
+
+    pub fn set_transaction_settings(
         &mut self,
-        sweep_stake_account: SweepStakeAccount,
+        transaction_settings: TransactionSettings,
     ) -> DbResult<()> {
-        let _ = self
-            .get_account_position(sweep_stake_account.address, MaybeToken::SOL())
-            .ok_or_else(|| {
-                DbError::AccountDoesNotExist(sweep_stake_account.address, MaybeToken::SOL())
-            })?;
         self.db
-            .set("sweep-stake-account", &sweep_stake_account)
+            .set("transaction-settings", &transaction_settings)
             .unwrap();
         self.save()
     }
 
-    pub fn get_transitory_sweep_stake_addresses(&self) -> HashSet<Pubkey> {
+    pub fn get_base_currency(&self) -> Currency {
+        self.db.get("base-currency").unwrap_or_default()
+    }
+
+    pub fn set_base_currency(&mut self, base_currency: Currency) -> DbResult<()> {
+        let current_base_currency = self.get_base_currency();
+        if base_currency != current_base_currency
+            && self.db.get::<usize>("next_lot_number").unwrap_or(0) > 0
+        {
+            // Existing lots are priced in the current base currency, mixing currencies would
+            // corrupt every gain/loss computation
+            return Err(DbError::BaseCurrencyChangeFailed(format!(
+                "lots priced in {} already exist",
+                current_base_currency
+            )));
+        }
+        self.db.set("base-currency", &base_currency).unwrap();
+        self.save()
+    }
+
+    pub fn get_fx_rate_source(&self) -> FxRateSource {
+        self.db.get("fx-rate-source").unwrap_or_default()
+    }
+
+    pub fn set_fx_rate_source(&mut self, fx_rate_source: FxRateSource) -> DbResult<()> {
+        self.db.set("fx-rate-source", &fx_rate_source).unwrap();
+        self.save()
+    }
+
+    pub fn get_reporting_timezone(&self) -> ReportingTimezone {
         self.db
-            .get::<Vec<TransitorySweepStake>>("transitory-sweep-stake-accounts")
+            .get::<String>("reporting-timezone")
+            .and_then(|timezone| timezone.parse().ok())
             .unwrap_or_default()
-            .into_iter()
-            .map(|tss| tss.address)
-            .collect()
     }
 
-    pub fn add_transitory_sweep_stake_address(
+    pub fn set_reporting_timezone(
         &mut self,
-        address: Pubkey,
-        current_epoch: Epoch,
+        reporting_timezone: ReportingTimezone,
     ) -> DbResult<()> {
-        let mut transitory_sweep_stake_addresses = self.get_transitory_sweep_stake_addresses();
-
-        if transitory_sweep_stake_addresses.contains(&address) {
-            Err(DbError::AccountAlreadyExists(address))
-        } else {
-            transitory_sweep_stake_addresses.insert(address);
-            self.set_transitory_sweep_stake_addresses(transitory_sweep_stake_addresses)
-        }?;
+        self.db
+            .set("reporting-timezone", &reporting_timezone.to_string())
+            .unwrap();
+        self.save()
+    }
 
-        self.add_account_no_save(TrackedAccount {
-            address,
-            token: MaybeToken::SOL(),
-            description: "Transitory stake account".to_string(),
-            last_update_balance: 0,
-            last_update_epoch: current_epoch,
-            lots: vec![],
-            no_sync: None,
-        })
+    pub fn get_validator_identities(&self) -> Vec<ValidatorIdentity> {
+        self.db.get("validator-identities").unwrap_or_default()
     }
 
-    pub fn remove_transitory_sweep_stake_address(&mut self, address: Pubkey) -> DbResult<()> {
+    pub fn set_validator_identity(
+        &mut self,
+        validator_identity: ValidatorIdentity,
+    ) -> DbResult<()> {
+        let _ = self
+            .get_account_position(validator_identity.address, MaybeToken::SOL())
+            .ok_or_else(|| {
+                DbError::AccountDoesNotExist(validator_identity.address, MaybeToken::SOL())
+            })?;
+
+        let mut validator_identities = self.get_validator_identities();
+        validator_identities.retain(|vi| vi.address != validator_identity.address);
+        validator_identities.push(validator_identity);
+        self.db
+            .set("validator-identities", &validator_identities)
+            .unwrap();
+        self.save()
+    }
+
+    pub fn remove_validator_identity(&mut self, address: Pubkey) -> DbResult<()> {
+        let mut validator_identities = self.get_validator_identities();
+        validator_identities.retain(|vi| vi.address != address);
+        self.db
+            .set("validator-identities", &validator_identities)
+            .unwrap();
+        self.save()
+    }
+
+    pub fn get_fee_payers(&self) -> HashSet<Pubkey> {
+        self.db
+            .get::<Vec<FeePayer>>("fee-payers")
+            .unwrap_or_default()
+            .into_iter()
+            .map(|fp| fp.address)
+            .collect()
+    }
+
+    pub fn set_fee_payer(&mut self, address: Pubkey, enabled: bool) -> DbResult<()> {
+        let mut fee_payers = self.get_fee_payers();
+        if enabled {
+            let _ = self
+                .get_account_position(address, MaybeToken::SOL())
+                .ok_or_else(|| DbError::AccountDoesNotExist(address, MaybeToken::SOL()))?;
+            fee_payers.insert(address);
+        } else {
+            fee_payers.remove(&address);
+        }
+        self.db
+            .set(
+                "fee-payers",
+                &fee_payers
+                    .into_iter()
+                    .map(|address| FeePayer { address })
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap();
+        self.save()
+    }
+
+    pub fn get_sweep_stake_account(&self) -> Option<SweepStakeAccount> {
+        self.db.get("sweep-stake-account")
+    }
+
+    pub fn set_sweep_stake_account(
+        &mut self,
+        sweep_stake_account: SweepStakeAccount,
+    ) -> DbResult<()> {
+        let _ = self
+            .get_account_position(sweep_stake_account.address, MaybeToken::SOL())
+            .ok_or_else(|| {
+                DbError::AccountDoesNotExist(sweep_stake_account.address, MaybeToken::SOL())
+            })?;
+        self.db
+            .set("sweep-stake-account", &sweep_stake_account)
+            .unwrap();
+        self.save()
+    }
+
+    pub fn get_transitory_sweep_stake_addresses(&self) -> HashSet<Pubkey> {
+        self.db
+            .get::<Vec<TransitorySweepStake>>("transitory-sweep-stake-accounts")
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tss| tss.address)
+            .collect()
+    }
+
+    pub fn add_transitory_sweep_stake_address(
+        &mut self,
+        address: Pubkey,
+        current_epoch: Epoch,
+    ) -> DbResult<()> {
+        let mut transitory_sweep_stake_addresses = self.get_transitory_sweep_stake_addresses();
+
+        if transitory_sweep_stake_addresses.contains(&address) {
+            Err(DbError::AccountAlreadyExists(address))
+        } else {
+            transitory_sweep_stake_addresses.insert(address);
+            self.set_transitory_sweep_stake_addresses(transitory_sweep_stake_addresses)
+        }?;
+
+        self.add_account_no_save(TrackedAccount {
+            address,
+            token: MaybeToken::SOL(),
+            description: "Transitory stake account".to_string(),
+            last_update_balance: 0,
+            last_update_epoch: current_epoch,
+            lots: vec![],
+            no_sync: None,
+            last_update_signature: None,
+            last_stake_change_signature: None,
+            token_account: None,
+        })
+    }
+
+    pub fn remove_transitory_sweep_stake_address(&mut self, address: Pubkey) -> DbResult<()> {
         let token = MaybeToken::SOL();
         let _ = self.remove_account_no_save(address, token);
 
@@ -1427,6 +2037,175 @@ impl Db {
         self.save()
     }
 
+    pub fn get_deactivating_stakes(&self) -> Vec<DeactivatingStake> {
+        self.db
+            .get("deactivating-stake-accounts")
+            .unwrap_or_default()
+    }
+
+    pub fn add_deactivating_stake(&mut self, address: Pubkey, epoch: Epoch) -> DbResult<()> {
+        let token = MaybeToken::SOL();
+        if self.get_account(address, token).is_none() {
+            return Err(DbError::AccountDoesNotExist(address, token));
+        }
+
+        let mut deactivating_stakes = self.get_deactivating_stakes();
+        deactivating_stakes.retain(|ds| ds.address != address);
+        deactivating_stakes.push(DeactivatingStake { address, epoch });
+        self.db
+            .set("deactivating-stake-accounts", &deactivating_stakes)
+            .unwrap();
+        self.save()
+    }
+
+    pub fn remove_deactivating_stake(&mut self, address: Pubkey) -> DbResult<()> {
+        let mut deactivating_stakes = self.get_deactivating_stakes();
+        deactivating_stakes.retain(|ds| ds.address != address);
+        self.db
+            .set("deactivating-stake-accounts", &deactivating_stakes)
+            .unwrap();
+        self.save()
+    }
+
+    pub fn get_pending_redelegations(&self) -> Vec<PendingRedelegation> {
+        self.db.get("pending-redelegations").unwrap_or_default()
+    }
+
+    pub fn set_pending_redelegation(
+        &mut self,
+        pending_redelegation: PendingRedelegation,
+    ) -> DbResult<()> {
+        let token = MaybeToken::SOL();
+        if self
+            .get_account(pending_redelegation.address, token)
+            .is_none()
+        {
+            return Err(DbError::AccountDoesNotExist(
+                pending_redelegation.address,
+                token,
+            ));
+        }
+
+        let mut pending_redelegations = self.get_pending_redelegations();
+        pending_redelegations.retain(|pr| pr.address != pending_redelegation.address);
+        pending_redelegations.push(pending_redelegation);
+        self.db
+            .set("pending-redelegations", &pending_redelegations)
+            .unwrap();
+        self.save()
+    }
+
+    pub fn get_pending_multisig_proposals(&self) -> Vec<PendingMultisigProposal> {
+        self.db
+            .get("pending-multisig-proposals")
+            .unwrap_or_default()
+    }
+
+    pub fn add_pending_multisig_proposal(
+        &mut self,
+        pending_multisig_proposal: PendingMultisigProposal,
+    ) -> DbResult<()> {
+        let mut pending_multisig_proposals = self.get_pending_multisig_proposals();
+        if pending_multisig_proposals
+            .iter()
+            .any(|pmp| pmp.proposal == pending_multisig_proposal.proposal)
+        {
+            return Err(DbError::AccountAlreadyExists(
+                pending_multisig_proposal.proposal,
+            ));
+        }
+        pending_multisig_proposals.push(pending_multisig_proposal);
+        self.db
+            .set("pending-multisig-proposals", &pending_multisig_proposals)
+            .unwrap();
+        self.save()
+    }
+
+    pub fn remove_pending_multisig_proposal(&mut self, proposal: Pubkey) -> DbResult<()> {
+        let mut pending_multisig_proposals = self.get_pending_multisig_proposals();
+        pending_multisig_proposals.retain(|pmp| pmp.proposal != proposal);
+        self.db
+            .set("pending-multisig-proposals", &pending_multisig_proposals)
+            .unwrap();
+        self.save()
+    }
+
+    // Progress is keyed by the canonical path of the plan file
+    pub fn get_plan_progress(&self, plan: &Path) -> Option<PlanProgress> {
+        self.db
+            .get::<HashMap<PathBuf, PlanProgress>>("plan-progress")
+            .unwrap_or_default()
+            .remove(plan)
+    }
+
+    pub fn set_plan_progress(
+        &mut self,
+        plan: &Path,
+        plan_progress: Option<PlanProgress>,
+    ) -> DbResult<()> {
+        let mut all_plan_progress = self
+            .db
+            .get::<HashMap<PathBuf, PlanProgress>>("plan-progress")
+            .unwrap_or_default();
+        match plan_progress {
+            Some(plan_progress) => all_plan_progress.insert(plan.to_path_buf(), plan_progress),
+            None => all_plan_progress.remove(plan),
+        };
+        self.db.set("plan-progress", &all_plan_progress).unwrap();
+        self.save()
+    }
+
+    pub fn get_placed_sell_ladder_rungs(&self) -> Vec<PlacedSellLadderRung> {
+        self.db.get("placed-sell-ladder-rungs").unwrap_or_default()
+    }
+
+    pub fn add_placed_sell_ladder_rung(
+        &mut self,
+        placed_sell_ladder_rung: PlacedSellLadderRung,
+    ) -> DbResult<()> {
+        let mut placed_sell_ladder_rungs = self.get_placed_sell_ladder_rungs();
+        placed_sell_ladder_rungs.push(placed_sell_ladder_rung);
+        self.db
+            .set("placed-sell-ladder-rungs", &placed_sell_ladder_rungs)
+            .unwrap();
+        self.save()
+    }
+
+    pub fn remove_placed_sell_ladder_rung(&mut self, order_id: &str) -> DbResult<()> {
+        let mut placed_sell_ladder_rungs = self.get_placed_sell_ladder_rungs();
+        placed_sell_ladder_rungs.retain(|rung| rung.order_id != order_id);
+        self.db
+            .set("placed-sell-ladder-rungs", &placed_sell_ladder_rungs)
+            .unwrap();
+        self.save()
+    }
+
+    // The caller must call `save()`...
+    fn close_placed_sell_ladder_rung(&mut self, order_id: &str, filled: bool) {
+        let mut placed_sell_ladder_rungs = self.get_placed_sell_ladder_rungs();
+        if filled {
+            for rung in placed_sell_ladder_rungs.iter_mut() {
+                if rung.order_id == order_id {
+                    rung.filled = true;
+                }
+            }
+        } else {
+            placed_sell_ladder_rungs.retain(|rung| rung.order_id != order_id);
+        }
+        self.db
+            .set("placed-sell-ladder-rungs", &placed_sell_ladder_rungs)
+            .unwrap();
+    }
+
+    pub fn remove_pending_redelegation(&mut self, address: Pubkey) -> DbResult<()> {
+        let mut pending_redelegations = self.get_pending_redelegations();
+        pending_redelegations.retain(|pr| pr.address != address);
+        self.db
+            .set("pending-redelegations", &pending_redelegations)
+            .unwrap();
+        self.save()
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn record_transfer(
         &mut self,
@@ -1464,24 +2243,43 @@ impl Db {
                 lot_selection_method,
                 lot_numbers,
             )?,
+            remove_from_account: false,
         });
 
         self.db.set("transfers", &pending_transfers).unwrap();
         self.update_account(from_account) // `update_account` calls `save`...
     }
 
+    // Deferred until the transfer is confirmed, as a cancelled transfer returns its lots to the
+    // from account
+    pub fn remove_account_on_transfer_confirmation(
+        &mut self,
+        signature: Signature,
+    ) -> DbResult<()> {
+        let mut pending_transfers = self.pending_transfers();
+        pending_transfers
+            .iter_mut()
+            .find(|pt| pt.signature == signature)
+            .ok_or(DbError::PendingTransferDoesNotExist(signature))?
+            .remove_from_account = true;
+        self.db.set("transfers", &pending_transfers).unwrap();
+        self.save()
+    }
+
     fn complete_transfer_or_deposit(
         &mut self,
         pending_transfer: PendingTransfer,
         success: Option<NaiveDate>,
-        track_fiat_lots: bool,
+        fiat_disposal_price: Option<Decimal>, // `None` to keep tracking fiat lots
     ) -> DbResult<()> {
         let PendingTransfer {
+            signature,
             from_address,
             from_token,
             to_address,
             to_token,
             lots,
+            remove_from_account,
             ..
         } = pending_transfer;
 
@@ -1497,11 +2295,13 @@ impl Db {
         if let Some(when) = success {
             assert_eq!(from_token.fiat_fungible(), to_token.fiat_fungible());
 
-            match (from_token.fiat_fungible(), track_fiat_lots) {
-                (false, _) | (true, true) => {
+            self.record_completed_transfer(signature, when);
+
+            match (from_token.fiat_fungible(), fiat_disposal_price) {
+                (false, _) | (true, None) => {
                     to_account.merge_lots(lots);
                 }
-                (true, false) => {
+                (true, Some(fiat_price)) => {
                     let _ = self.record_lots_disposal(
                         from_token,
                         lots,
@@ -1509,7 +2309,7 @@ impl Db {
                             description: "fiat".into(),
                         },
                         when,
-                        Decimal::from_f64(1.).unwrap(),
+                        fiat_price,
                     );
                 }
             }
@@ -1518,7 +2318,11 @@ impl Db {
         }
 
         self.update_account(to_account)?;
-        self.update_account(from_account)?;
+        if remove_from_account && success.is_some() && from_account.lots.is_empty() {
+            self.remove_account_no_save(from_address, from_token)?;
+        } else {
+            self.update_account(from_account)?;
+        }
         self.auto_save(true)
     }
 
@@ -1538,7 +2342,7 @@ impl Db {
         pending_transfers.retain(|pt| pt.signature != signature);
         self.db.set("transfers", &pending_transfers).unwrap();
 
-        self.complete_transfer_or_deposit(transfer, success, true) // `complete_transfer_or_deposit` calls `save`...
+        self.complete_transfer_or_deposit(transfer, success, None) // `complete_transfer_or_deposit` calls `save`...
     }
 
     pub fn cancel_transfer(&mut self, signature: Signature) -> DbResult<()> {
@@ -1549,6 +2353,103 @@ impl Db {
         self.complete_transfer(signature, Some(when))
     }
 
+    // Moves a pending deposit, swap or transfer recorded under a provisional signature to the
+    // signature of the transaction that was actually signed
+    pub fn update_pending_signature(
+        &mut self,
+        signature: Signature,
+        new_signature: Signature,
+    ) -> DbResult<()> {
+        let mut pending_deposits = self.pending_deposits(None);
+        let mut pending_swaps = self.pending_swaps();
+        let mut pending_transfers = self.pending_transfers();
+
+        if let Some(pending_deposit) = pending_deposits
+            .iter_mut()
+            .find(|pd| pd.transfer.signature == signature)
+        {
+            pending_deposit.transfer.signature = new_signature;
+            self.db.lrem_list("deposits")?;
+            self.db.lcreate("deposits")?;
+            self.db.lextend("deposits", &pending_deposits).unwrap();
+        } else if let Some(pending_swap) = pending_swaps
+            .iter_mut()
+            .find(|ps| ps.signature == signature)
+        {
+            pending_swap.signature = new_signature;
+            self.db.lrem_list("swaps")?;
+            self.db.lcreate("swaps")?;
+            self.db.lextend("swaps", &pending_swaps).unwrap();
+        } else if let Some(pending_transfer) = pending_transfers
+            .iter_mut()
+            .find(|pt| pt.signature == signature)
+        {
+            pending_transfer.signature = new_signature;
+            self.db.set("transfers", &pending_transfers).unwrap();
+        } else {
+            return Err(DbError::PendingTransferDoesNotExist(signature));
+        }
+        self.save()
+    }
+
+    // The caller must call `save()`...
+    fn record_completed_transfer(&mut self, signature: Signature, when: NaiveDate) {
+        let mut completed_transfers: Vec<CompletedTransfer> =
+            self.db.get("completed-transfers").unwrap_or_default();
+        completed_transfers.push(CompletedTransfer {
+            signature,
+            when: Some(when),
+        });
+        self.db
+            .set("completed-transfers", &completed_transfers)
+            .unwrap();
+    }
+
+    // A delegation or deactivation submitted by `sys`, which stake change detection must not
+    // report as an outside stake change
+    pub fn record_stake_change_signature(
+        &mut self,
+        signature: Signature,
+        when: NaiveDate,
+    ) -> DbResult<()> {
+        self.record_completed_transfer(signature, when);
+        self.save()
+    }
+
+    // Transfers, deposits and swaps that `sys` has submitted or completed, whose balance changes
+    // are already reflected in the lots
+    pub fn get_known_signatures(&self) -> HashSet<Signature> {
+        let mut signatures = self.get_completed_transfer_signatures();
+        signatures.extend(self.pending_transfers().into_iter().map(|pt| pt.signature));
+        signatures.extend(
+            self.pending_deposits(None)
+                .into_iter()
+                .map(|pd| pd.transfer.signature),
+        );
+        signatures.extend(self.pending_swaps().into_iter().map(|ps| ps.signature));
+        signatures
+    }
+
+    pub fn get_completed_transfer_signatures(&self) -> HashSet<Signature> {
+        self.db
+            .get::<Vec<CompletedTransfer>>("completed-transfers")
+            .unwrap_or_default()
+            .into_iter()
+            .map(|ct| ct.signature)
+            .collect()
+    }
+
+    // Forgets the signatures completed before `before`
+    pub fn prune_completed_transfer_signatures(&mut self, before: NaiveDate) -> DbResult<()> {
+        let mut completed_transfers: Vec<CompletedTransfer> =
+            self.db.get("completed-transfers").unwrap_or_default();
+        completed_transfers.retain(|ct| ct.when.map_or(false, |when| when >= before));
+        self.db
+            .set("completed-transfers", &completed_transfers)
+            .unwrap();
+        self.save()
+    }
+
     pub fn pending_transfers(&self) -> Vec<PendingTransfer> {
         self.db.get("transfers").unwrap_or_default()
     }
@@ -1713,6 +2614,65 @@ impl Db {
         self.update_account(account)
     }
 
+    // Updates the acquisition price and time of held, open order and disposed lots
+    pub fn reprice_lots(
+        &mut self,
+        lot_prices: &HashMap<usize, (Decimal, Option<NaiveDateTime>)>,
+    ) -> DbResult<()> {
+        let mut accounts = self.get_accounts();
+        let mut open_orders = self.open_orders(None, None);
+        let mut disposed_lots = self.disposed_lots();
+
+        let mut repriced_lots = HashSet::new();
+        let mut reprice = |lots: &mut dyn Iterator<Item = &mut Lot>| {
+            let mut updated = false;
+            for lot in lots {
+                if let Some((price, time)) = lot_prices.get(&lot.lot_number) {
+                    lot.acquisition.set_price(*price);
+                    lot.acquisition.time = *time;
+                    repriced_lots.insert(lot.lot_number);
+                    updated = true;
+                }
+            }
+            updated
+        };
+
+        let accounts = accounts
+            .iter_mut()
+            .filter_map(|account| reprice(&mut account.lots.iter_mut()).then(|| account.clone()))
+            .collect::<Vec<_>>();
+        let open_orders_updated = open_orders.iter_mut().fold(false, |updated, open_order| {
+            reprice(&mut open_order.lots.iter_mut()) || updated
+        });
+        let disposed_lots_updated = reprice(
+            &mut disposed_lots
+                .iter_mut()
+                .map(|disposed_lot| &mut disposed_lot.lot),
+        );
+
+        if let Some(lot_number) = lot_prices
+            .keys()
+            .find(|lot_number| !repriced_lots.contains(lot_number))
+        {
+            return Err(DbError::LotRepriceFailed(format!(
+                "Unknown lot: {}",
+                lot_number
+            )));
+        }
+
+        self.auto_save(false)?;
+        for account in accounts {
+            self.update_account(account)?;
+        }
+        if open_orders_updated {
+            self.db.set("orders", &open_orders).unwrap();
+        }
+        if disposed_lots_updated {
+            self.db.set("disposed-lots", &disposed_lots).unwrap();
+        }
+        self.auto_save(true)
+    }
+
     pub fn move_lot(&mut self, lot_number: usize, to_address: Pubkey) -> DbResult<()> {
         self.auto_save(false)?;
 
@@ -1796,3 +2756,239 @@ impl Db {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::test_utils::{add_test_account, test_db},
+    };
+
+    #[test]
+    fn test_price_precision_truncate() {
+        let time = NaiveDate::from_ymd(2022, 3, 4).and_hms(13, 47, 59);
+
+        assert_eq!(PricePrecision::Day.truncate(time), None);
+        assert_eq!(
+            PricePrecision::Hour.truncate(time),
+            Some(NaiveDate::from_ymd(2022, 3, 4).and_hms(13, 0, 0))
+        );
+        assert_eq!(
+            PricePrecision::Minute.truncate(time),
+            Some(NaiveDate::from_ymd(2022, 3, 4).and_hms(13, 47, 0))
+        );
+    }
+
+    #[test]
+    fn test_reporting_timezone() {
+        let new_york = ReportingTimezone::from_str("America/New_York").unwrap();
+        assert_eq!(new_york.to_string(), "America/New_York");
+        assert_eq!(
+            ReportingTimezone::from_str("local").unwrap(),
+            ReportingTimezone::default()
+        );
+        assert!(ReportingTimezone::from_str("Nowhere/Special").is_err());
+
+        // 2022-03-04 02:30 UTC is still the previous day in New York (UTC-5)
+        let utc_time = NaiveDate::from_ymd(2022, 3, 4).and_hms(2, 30, 0);
+        let local_time = NaiveDate::from_ymd(2022, 3, 3).and_hms(21, 30, 0);
+        assert_eq!(new_york.local_datetime(utc_time), local_time);
+        assert_eq!(new_york.utc_datetime(local_time), Some(utc_time));
+        assert_eq!(
+            new_york.date_of_timestamp(utc_time.timestamp()),
+            NaiveDate::from_ymd(2022, 3, 3)
+        );
+
+        // Daylight saving time: 02:30 doesn't exist on 2022-03-13, and 01:30 occurs twice on
+        // 2022-11-06, in which case the earlier instant is picked
+        assert_eq!(
+            new_york.utc_datetime(NaiveDate::from_ymd(2022, 3, 13).and_hms(2, 30, 0)),
+            None
+        );
+        assert_eq!(
+            new_york.utc_datetime(NaiveDate::from_ymd(2022, 11, 6).and_hms(1, 30, 0)),
+            Some(NaiveDate::from_ymd(2022, 11, 6).and_hms(5, 30, 0))
+        );
+
+        let utc = ReportingTimezone::from_str("UTC").unwrap();
+        assert_eq!(utc.local_datetime(utc_time), utc_time);
+        assert_eq!(
+            utc.date_of_timestamp(utc_time.timestamp()),
+            NaiveDate::from_ymd(2022, 3, 4)
+        );
+    }
+
+    fn balance(db: &Db, address: Pubkey) -> Option<u64> {
+        db.get_account(address, MaybeToken::SOL())
+            .map(|account| account.last_update_balance)
+    }
+
+    #[test]
+    fn test_close_placed_sell_ladder_rung() {
+        let (mut db, db_path) = test_db();
+        let address = Pubkey::new_unique();
+        add_test_account(&mut db, address, 100);
+        let when = NaiveDate::from_ymd(2022, 3, 4);
+
+        let place = |db: &mut Db, order_id: &str| {
+            let mut account = db.get_account(address, MaybeToken::SOL()).unwrap();
+            let lots = account
+                .extract_lots(db, 10, LotSelectionMethod::default(), None)
+                .unwrap();
+            db.open_order(
+                OrderSide::Sell,
+                account,
+                Exchange::Ftx,
+                "SOL/USD".into(),
+                100.,
+                order_id.into(),
+                lots,
+                None,
+            )
+            .unwrap();
+            db.add_placed_sell_ladder_rung(PlacedSellLadderRung {
+                exchange: Exchange::Ftx,
+                pair: "SOL/USD".into(),
+                price_cents: 10_000,
+                amount: 10,
+                order_id: order_id.into(),
+                filled: false,
+            })
+            .unwrap();
+        };
+
+        // A cancelled order leaves the rung free to be placed again
+        place(&mut db, "1");
+        db.close_order("1", 10, 0, 100., when, None).unwrap();
+        assert!(db.get_placed_sell_ladder_rungs().is_empty());
+        assert_eq!(balance(&db, address), Some(100));
+
+        // A filled order, even partially, is remembered
+        place(&mut db, "2");
+        db.close_order("2", 10, 4, 100., when, None).unwrap();
+        let rungs = db.get_placed_sell_ladder_rungs();
+        assert_eq!(rungs.len(), 1);
+        assert_eq!(rungs[0].order_id, "2");
+        assert!(rungs[0].filled);
+        assert_eq!(balance(&db, address), Some(96));
+
+        db.remove_placed_sell_ladder_rung("2").unwrap();
+        assert!(db.get_placed_sell_ladder_rungs().is_empty());
+
+        std::fs::remove_dir_all(db_path).unwrap();
+    }
+
+    #[test]
+    fn test_update_pending_signature() {
+        let (mut db, db_path) = test_db();
+        let sol = MaybeToken::SOL();
+        let from_address = Pubkey::new_unique();
+        add_test_account(&mut db, from_address, 100);
+        let to_address = Pubkey::new_unique();
+        add_test_account(&mut db, to_address, 0);
+
+        let provisional_signature = Signature::new(&[1; 64]);
+        let signature = Signature::new(&[2; 64]);
+        db.record_transfer(
+            provisional_signature,
+            OFFLINE_BLOCK_HEIGHT,
+            Some(40),
+            from_address,
+            sol,
+            to_address,
+            sol,
+            LotSelectionMethod::default(),
+            None,
+        )
+        .unwrap();
+
+        db.update_pending_signature(provisional_signature, signature)
+            .unwrap();
+        let pending_transfers = db.pending_transfers();
+        assert_eq!(pending_transfers.len(), 1);
+        assert_eq!(pending_transfers[0].signature, signature);
+        assert_eq!(pending_transfers[0].lots[0].amount, 40);
+        assert!(matches!(
+            db.update_pending_signature(provisional_signature, signature),
+            Err(DbError::PendingTransferDoesNotExist(_))
+        ));
+
+        db.confirm_transfer(signature, NaiveDate::from_ymd(2022, 3, 5))
+            .unwrap();
+        assert!(db.pending_transfers().is_empty());
+        assert_eq!(balance(&db, from_address), Some(60));
+        assert_eq!(balance(&db, to_address), Some(40));
+
+        fs::remove_dir_all(db_path).unwrap();
+    }
+
+    #[test]
+    fn test_remove_account_on_transfer_confirmation() {
+        let (mut db, db_path) = test_db();
+        let sol = MaybeToken::SOL();
+        let from_address = add_test_account(&mut db, 100);
+        let to_address = Pubkey::new_unique();
+        add_test_account(&mut db, to_address, 10);
+
+        let record_merge = |db: &mut Db, signature| {
+            db.record_transfer(
+                signature,
+                OFFLINE_BLOCK_HEIGHT,
+                None,
+                from_address,
+                sol,
+                to_address,
+                sol,
+                LotSelectionMethod::default(),
+                None,
+            )
+            .unwrap();
+            db.remove_account_on_transfer_confirmation(signature)
+                .unwrap();
+        };
+
+        // A cancelled transfer returns the lots to the from account, which remains tracked
+        let signature = Signature::new(&[1; 64]);
+        record_merge(&mut db, signature);
+        assert_eq!(balance(&db, from_address), Some(0));
+        db.cancel_transfer(signature).unwrap();
+        assert_eq!(balance(&db, from_address), Some(100));
+
+        let signature = Signature::new(&[2; 64]);
+        record_merge(&mut db, signature);
+        db.confirm_transfer(signature, NaiveDate::from_ymd(2022, 3, 5))
+            .unwrap();
+        assert_eq!(balance(&db, from_address), None);
+        assert_eq!(balance(&db, to_address), Some(110));
+
+        fs::remove_dir_all(db_path).unwrap();
+    }
+
+    #[test]
+    fn test_proposal_instruction_matches() {
+        let from = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let instruction = solana_sdk::system_instruction::transfer(&from, &to, 42);
+        let proposal_instruction = ProposalInstruction::from(&instruction);
+        assert!(proposal_instruction.matches(&instruction));
+
+        // The multisig signs for itself when executing, so signer and writable flags may differ
+        let mut executed_instruction = instruction.clone();
+        for account_meta in executed_instruction.accounts.iter_mut() {
+            account_meta.is_signer = false;
+            account_meta.is_writable = false;
+        }
+        assert!(proposal_instruction.matches(&executed_instruction));
+
+        let mut other_instruction = solana_sdk::system_instruction::transfer(&from, &to, 43);
+        assert!(!proposal_instruction.matches(&other_instruction));
+        other_instruction = solana_sdk::system_instruction::transfer(&to, &from, 42);
+        assert!(!proposal_instruction.matches(&other_instruction));
+        other_instruction = instruction.clone();
+        other_instruction.program_id = Pubkey::new_unique();
+        assert!(!proposal_instruction.matches(&other_instruction));
+        other_instruction = instruction;
+        other_instruction.accounts.pop();
+        assert!(!proposal_instruction.matches(&other_instruction));
+    }
+}
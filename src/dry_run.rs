@@ -0,0 +1,132 @@
+use {
+    crate::{exchange::*, token::MaybeToken},
+    async_trait::async_trait,
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::HashMap,
+        sync::atomic::{AtomicBool, Ordering},
+    },
+};
+
+// With `--dry-run`, commands run against a throwaway copy of `Db` and nothing is sent to the
+// cluster or an exchange
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    DRY_RUN.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+// Passes queries through to the exchange, while orders, withdrawals and lending offers are only
+// printed
+pub struct DryRunExchangeClient(pub Box<dyn ExchangeClient>);
+
+#[async_trait]
+impl ExchangeClient for DryRunExchangeClient {
+    async fn deposit_address(
+        &self,
+        token: MaybeToken,
+    ) -> Result<Pubkey, Box<dyn std::error::Error>> {
+        self.0.deposit_address(token).await
+    }
+
+    async fn recent_deposits(&self) -> Result<Vec<DepositInfo>, Box<dyn std::error::Error>> {
+        self.0.recent_deposits().await
+    }
+
+    async fn recent_withdrawals(&self) -> Result<Vec<WithdrawalInfo>, Box<dyn std::error::Error>> {
+        self.0.recent_withdrawals().await
+    }
+
+    async fn request_withdraw(
+        &self,
+        address: Pubkey,
+        token: MaybeToken,
+        amount: f64,
+        _withdrawal_password: Option<String>,
+        _withdrawal_code: Option<String>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        println!(
+            "Dry run, withdrawal not requested: {}{} to {}",
+            token.symbol(),
+            amount,
+            address
+        );
+        Ok("dry-run".into())
+    }
+
+    async fn balances(
+        &self,
+    ) -> Result<HashMap<String, ExchangeBalance>, Box<dyn std::error::Error>> {
+        self.0.balances().await
+    }
+
+    async fn print_market_info(
+        &self,
+        pair: &str,
+        format: MarketInfoFormat,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.0.print_market_info(pair, format).await
+    }
+
+    async fn bid_ask(&self, pair: &str) -> Result<BidAsk, Box<dyn std::error::Error>> {
+        self.0.bid_ask(pair).await
+    }
+
+    async fn place_order(
+        &self,
+        pair: &str,
+        side: OrderSide,
+        price: f64,
+        amount: f64,
+    ) -> Result<OrderId, Box<dyn std::error::Error>> {
+        println!(
+            "Dry run, order not placed: {:?} {} {} at ${}",
+            side, amount, pair, price
+        );
+        Ok("dry-run".into())
+    }
+
+    async fn cancel_order(
+        &self,
+        pair: &str,
+        order_id: &OrderId,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        println!("Dry run, order not cancelled: {} {}", pair, order_id);
+        Ok(())
+    }
+
+    async fn order_status(
+        &self,
+        pair: &str,
+        order_id: &OrderId,
+    ) -> Result<OrderStatus, Box<dyn std::error::Error>> {
+        self.0.order_status(pair, order_id).await
+    }
+
+    async fn get_lending_info(
+        &self,
+        coin: &str,
+    ) -> Result<Option<LendingInfo>, Box<dyn std::error::Error>> {
+        self.0.get_lending_info(coin).await
+    }
+
+    async fn get_lending_history(
+        &self,
+        lending_history: LendingHistory,
+    ) -> Result<HashMap<String, f64>, Box<dyn std::error::Error>> {
+        self.0.get_lending_history(lending_history).await
+    }
+
+    async fn submit_lending_offer(
+        &self,
+        coin: &str,
+        size: f64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        println!("Dry run, lending offer not submitted: {} {}", size, coin);
+        Ok(())
+    }
+}
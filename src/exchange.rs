@@ -1,5 +1,5 @@
 use {
-    crate::{binance_exchange, ftx_exchange, token::MaybeToken},
+    crate::{binance_exchange, db::ReportingTimezone, dry_run, ftx_exchange, token::MaybeToken},
     async_trait::async_trait,
     chrono::NaiveDate,
     serde::{Deserialize, Serialize},
@@ -181,12 +181,25 @@ pub trait ExchangeClient {
 pub fn exchange_client_new(
     exchange: Exchange,
     exchange_credentials: ExchangeCredentials,
+    reporting_timezone: ReportingTimezone, // Fill and lending dates are in this timezone
 ) -> Result<Box<dyn ExchangeClient>, Box<dyn std::error::Error>> {
     let exchange_client: Box<dyn ExchangeClient> = match exchange {
-        Exchange::Binance => Box::new(binance_exchange::new(exchange_credentials)?),
-        Exchange::BinanceUs => Box::new(binance_exchange::new_us(exchange_credentials)?),
-        Exchange::Ftx => Box::new(ftx_exchange::new(exchange_credentials)?),
-        Exchange::FtxUs => Box::new(ftx_exchange::new_us(exchange_credentials)?),
+        Exchange::Binance => Box::new(binance_exchange::new(
+            exchange_credentials,
+            reporting_timezone,
+        )?),
+        Exchange::BinanceUs => Box::new(binance_exchange::new_us(
+            exchange_credentials,
+            reporting_timezone,
+        )?),
+        Exchange::Ftx => Box::new(ftx_exchange::new(exchange_credentials, reporting_timezone)?),
+        Exchange::FtxUs => Box::new(ftx_exchange::new_us(
+            exchange_credentials,
+            reporting_timezone,
+        )?),
     };
+    if dry_run::enabled() {
+        return Ok(Box::new(dry_run::DryRunExchangeClient(exchange_client)));
+    }
     Ok(exchange_client)
 }
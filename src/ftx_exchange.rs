@@ -1,5 +1,6 @@
 use {
     crate::{
+        db::ReportingTimezone,
         exchange::*,
         token::{MaybeToken, Token},
     },
@@ -22,6 +23,7 @@ use {
 
 pub struct FtxExchangeClient {
     rest: Rest,
+    reporting_timezone: ReportingTimezone,
 }
 
 fn binance_to_ftx_pair(binance_pair: &str) -> Result<&'static str, Box<dyn std::error::Error>> {
@@ -237,7 +239,8 @@ impl ExchangeClient for FtxExchangeClient {
                 for p in &hourly_prices {
                     println!(
                         "{},{},{},{},{}",
-                        DateTime::<Local>::from(p.start_time),
+                        self.reporting_timezone
+                            .local_datetime(p.start_time.naive_utc()),
                         p.low,
                         p.high,
                         (p.low + p.high).to_f64().unwrap() / 2.,
@@ -337,10 +340,7 @@ impl ExchangeClient for FtxExchangeClient {
         assert_eq!(pair, ftx_to_binance_pair(&order_info.market)?);
 
         // TODO: use `order_info.created_at` instead?
-        let last_update = {
-            let today = Local::now().date();
-            NaiveDate::from_ymd(today.year(), today.month(), today.day())
-        };
+        let last_update = self.reporting_timezone.today();
 
         let fills = self
             .rest
@@ -407,28 +407,40 @@ impl ExchangeClient for FtxExchangeClient {
     ) -> Result<HashMap<String, f64>, Box<dyn std::error::Error>> {
         let one_day = Duration::days(1);
 
+        // Days start at midnight in the reporting timezone
+        let start_of_day = |date: NaiveDate| {
+            self.reporting_timezone
+                .utc_datetime(date.and_hms(0, 0, 0))
+                .map(|utc_time| DateTime::<Utc>::from_utc(utc_time, Utc))
+                .ok_or_else(|| format!("Start of {} is not a valid time", date))
+        };
+
         let (mut start_time, end_time) = match lending_history {
             LendingHistory::Range {
                 start_date,
                 end_date,
             } => {
-                let start_time = Local.from_local_date(&start_date).unwrap().and_hms(0, 0, 0);
-                let end_time = Local
-                    .from_local_date(&end_date)
-                    .unwrap()
-                    .and_hms(23, 59, 59);
+                let start_time = start_of_day(start_date)?;
+                let end_time = start_of_day(end_date)? + one_day - Duration::seconds(1);
                 (start_time, end_time)
             }
             LendingHistory::Previous { days } => {
-                let end_time = Local::now().date().and_hms(0, 0, 0) + one_day;
+                let end_time = start_of_day(self.reporting_timezone.today())? + one_day;
                 let start_time = end_time - one_day * days as i32;
 
                 (start_time, end_time - Duration::seconds(1))
             }
         };
 
-        println!("Start date: {}", start_time);
-        println!("End date:   {}", end_time);
+        println!(
+            "Start date: {}",
+            self.reporting_timezone
+                .local_datetime(start_time.naive_utc())
+        );
+        println!(
+            "End date:   {}",
+            self.reporting_timezone.local_datetime(end_time.naive_utc())
+        );
 
         let mut all_proceeds = HashMap::<String, f64>::default();
         while start_time < end_time {
@@ -480,6 +492,7 @@ pub fn new(
         secret,
         subaccount,
     }: ExchangeCredentials,
+    reporting_timezone: ReportingTimezone,
 ) -> Result<FtxExchangeClient, Box<dyn std::error::Error>> {
     Ok(FtxExchangeClient {
         rest: Rest::new(ftx::options::Options {
@@ -488,6 +501,7 @@ pub fn new(
             secret: Some(secret),
             subaccount,
         }),
+        reporting_timezone,
     })
 }
 
@@ -497,6 +511,7 @@ pub fn new_us(
         secret,
         subaccount,
     }: ExchangeCredentials,
+    reporting_timezone: ReportingTimezone,
 ) -> Result<FtxExchangeClient, Box<dyn std::error::Error>> {
     Ok(FtxExchangeClient {
         rest: Rest::new(ftx::options::Options {
@@ -505,5 +520,6 @@ pub fn new_us(
             secret: Some(secret),
             subaccount,
         }),
+        reporting_timezone,
     })
 }
@@ -0,0 +1,153 @@
+use {
+    crate::rpc_client_utils,
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        clock::{Slot, UnixTimestamp},
+        pubkey::Pubkey,
+        signature::Signature,
+        stake::state::StakeAuthorize,
+    },
+    solana_stake_program::stake_instruction::StakeInstruction,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StakeChange {
+    Split {
+        source: Pubkey,
+        destination: Pubkey,
+        lamports: u64,
+    },
+    Merge {
+        source: Pubkey,
+        destination: Pubkey,
+        lamports: u64, // Balance of `source` before the merge
+    },
+    Withdraw {
+        stake: Pubkey,
+        recipient: Pubkey,
+        lamports: u64,
+    },
+    Deactivate {
+        stake: Pubkey,
+    },
+    Delegate {
+        stake: Pubkey,
+        vote: Pubkey,
+    },
+    Authorize {
+        stake: Pubkey,
+        new_authority: Pubkey,
+        stake_authorize: StakeAuthorize,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct GetStakeChanges {
+    pub slot: Slot,
+    pub block_time: Option<UnixTimestamp>,
+    pub changes: Vec<StakeChange>,
+}
+
+// Decodes the stake program instructions of a successful transaction. Only top-level
+// instructions are considered, stake changes made by other programs through CPI are not reported
+pub fn get_stake_changes(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+) -> Result<GetStakeChanges, Box<dyn std::error::Error>> {
+    let confirmed_transaction = rpc_client_utils::get_transaction(rpc_client, signature)?;
+
+    let slot = confirmed_transaction.slot;
+    let block_time = confirmed_transaction.block_time;
+
+    if confirmed_transaction.meta.err.is_some() {
+        return Err("Transaction was not successful".into());
+    }
+
+    let account_keys = confirmed_transaction.account_keys()?;
+    let meta = &confirmed_transaction.meta;
+    let transaction = &confirmed_transaction.transaction;
+    let account_key = |accounts: &[u8], position: usize| {
+        accounts
+            .get(position)
+            .and_then(|index| account_keys.get(*index as usize))
+            .copied()
+            .ok_or_else(|| format!("Malformed stake instruction in {}", signature))
+    };
+
+    let mut changes = vec![];
+    for instruction in transaction.message.instructions() {
+        if account_keys.get(instruction.program_id_index as usize)
+            != Some(&solana_stake_program::id())
+        {
+            continue;
+        }
+
+        let stake_instruction = match bincode::deserialize::<StakeInstruction>(&instruction.data) {
+            Ok(stake_instruction) => stake_instruction,
+            Err(_) => continue,
+        };
+        let accounts = &instruction.accounts;
+
+        changes.push(match stake_instruction {
+            StakeInstruction::Split(lamports) => StakeChange::Split {
+                source: account_key(accounts, 0)?,
+                destination: account_key(accounts, 1)?,
+                lamports,
+            },
+            StakeInstruction::Merge => {
+                let source_index = *accounts
+                    .get(1)
+                    .ok_or_else(|| format!("Malformed stake instruction in {}", signature))?
+                    as usize;
+                StakeChange::Merge {
+                    source: account_key(accounts, 1)?,
+                    destination: account_key(accounts, 0)?,
+                    lamports: meta
+                        .pre_balances
+                        .get(source_index)
+                        .copied()
+                        .unwrap_or_default(),
+                }
+            }
+            StakeInstruction::Withdraw(lamports) => StakeChange::Withdraw {
+                stake: account_key(accounts, 0)?,
+                recipient: account_key(accounts, 1)?,
+                lamports,
+            },
+            StakeInstruction::Deactivate => StakeChange::Deactivate {
+                stake: account_key(accounts, 0)?,
+            },
+            StakeInstruction::DelegateStake => StakeChange::Delegate {
+                stake: account_key(accounts, 0)?,
+                vote: account_key(accounts, 1)?,
+            },
+            StakeInstruction::Authorize(new_authority, stake_authorize) => StakeChange::Authorize {
+                stake: account_key(accounts, 0)?,
+                new_authority,
+                stake_authorize,
+            },
+            StakeInstruction::AuthorizeWithSeed(args) => StakeChange::Authorize {
+                stake: account_key(accounts, 0)?,
+                new_authority: args.new_authorized_pubkey,
+                stake_authorize: args.stake_authorize,
+            },
+            StakeInstruction::AuthorizeChecked(stake_authorize) => StakeChange::Authorize {
+                stake: account_key(accounts, 0)?,
+                new_authority: account_key(accounts, 3)?,
+                stake_authorize,
+            },
+            StakeInstruction::AuthorizeCheckedWithSeed(args) => StakeChange::Authorize {
+                stake: account_key(accounts, 0)?,
+                new_authority: account_key(accounts, 3)?,
+                stake_authorize: args.stake_authorize,
+            },
+            _ => continue,
+        });
+    }
+
+    Ok(GetStakeChanges {
+        slot,
+        block_time,
+        changes,
+    })
+}
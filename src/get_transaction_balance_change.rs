@@ -1,9 +1,10 @@
 use {
+    crate::rpc_client_utils,
     chrono::prelude::*,
     solana_client::rpc_client::RpcClient,
     solana_sdk::{clock::Slot, pubkey::Pubkey, signature::Signature},
-    solana_transaction_status::UiTransactionEncoding,
-    std::str::FromStr,
+    solana_transaction_status::UiTransactionTokenBalance,
+    std::{collections::HashMap, str::FromStr},
 };
 
 #[derive(Debug, Clone)]
@@ -12,6 +13,19 @@ pub struct GetTransactionAddrssBalanceChange {
     pub post_amount: u64,
     pub slot: Slot,
     pub when: Option<NaiveDateTime>,
+    pub debits: Vec<(Pubkey, u64)>, // Other addresses whose balance decreased, and by how much
+}
+
+fn token_amounts(token_balances: &[UiTransactionTokenBalance]) -> HashMap<usize, u64> {
+    token_balances
+        .iter()
+        .map(|token_balance| {
+            (
+                token_balance.account_index as usize,
+                u64::from_str(&token_balance.ui_token_amount.amount).unwrap_or_default(),
+            )
+        })
+        .collect()
 }
 
 pub fn get_transaction_balance_change(
@@ -20,8 +34,7 @@ pub fn get_transaction_balance_change(
     address: &Pubkey,
     address_is_token: bool,
 ) -> Result<GetTransactionAddrssBalanceChange, Box<dyn std::error::Error>> {
-    let confirmed_transaction =
-        rpc_client.get_transaction(signature, UiTransactionEncoding::Base64)?;
+    let confirmed_transaction = rpc_client_utils::get_transaction(rpc_client, signature)?;
 
     let slot = confirmed_transaction.slot;
     let when = confirmed_transaction
@@ -32,64 +45,50 @@ pub fn get_transaction_balance_change(
         })
         .transpose()?;
 
-    let meta = confirmed_transaction
-        .transaction
-        .meta
-        .ok_or("Transaction metadata not available")?;
-
-    if meta.err.is_some() {
+    if confirmed_transaction.meta.err.is_some() {
         return Err("Transaction was not successful".into());
     }
 
-    let transaction = confirmed_transaction
-        .transaction
-        .transaction
-        .decode()
-        .ok_or("Unable to decode transaction")?;
-
-    let account_index = transaction
-        .message
-        .static_account_keys()
+    // Includes any addresses loaded from lookup tables
+    let account_keys = confirmed_transaction.account_keys()?;
+    let meta = confirmed_transaction.meta;
+    let account_index = account_keys
         .iter()
         .position(|k| k == address)
         .ok_or_else(|| format!("Address {} not referenced in transaction", address))?;
 
-    let pre_amount = if address_is_token {
-        u64::from_str(
-            &meta
-                .pre_token_balances
-                .unwrap()
-                .iter()
-                .find(|ptb| ptb.account_index as usize == account_index)
-                .unwrap()
-                .ui_token_amount
-                .amount,
+    let (pre_amounts, post_amounts) = if address_is_token {
+        (
+            token_amounts(&meta.pre_token_balances.unwrap_or_default()),
+            token_amounts(&meta.post_token_balances.unwrap_or_default()),
         )
-        .unwrap_or_default()
     } else {
-        meta.pre_balances[account_index]
-    };
-
-    let post_amount = if address_is_token {
-        u64::from_str(
-            &meta
-                .post_token_balances
-                .unwrap()
-                .iter()
-                .find(|ptb| ptb.account_index as usize == account_index)
-                .unwrap()
-                .ui_token_amount
-                .amount,
+        (
+            meta.pre_balances.into_iter().enumerate().collect(),
+            meta.post_balances.into_iter().enumerate().collect(),
         )
-        .unwrap_or_default()
-    } else {
-        meta.post_balances[account_index]
     };
 
+    let pre_amount = pre_amounts.get(&account_index).copied().unwrap_or_default();
+    let post_amount = post_amounts
+        .get(&account_index)
+        .copied()
+        .unwrap_or_default();
+
+    let debits = pre_amounts
+        .iter()
+        .filter(|(index, _)| **index != account_index)
+        .filter_map(|(index, pre_amount)| {
+            let post_amount = post_amounts.get(index).copied().unwrap_or_default();
+            (post_amount < *pre_amount).then(|| (account_keys[*index], *pre_amount - post_amount))
+        })
+        .collect();
+
     Ok(GetTransactionAddrssBalanceChange {
         pre_amount,
         post_amount,
         slot,
         when,
+        debits,
     })
 }
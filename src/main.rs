@@ -1,17 +1,36 @@
 mod binance_exchange;
 mod coin_gecko;
+mod currency;
+mod daemon;
 mod db;
+mod dry_run;
 mod exchange;
 mod field_as_string;
 mod ftx_exchange;
+mod get_stake_changes;
 mod get_transaction_balance_change;
 mod notifier;
+mod option_field_as_string;
+mod plan;
+mod pubsub;
 mod rpc_client_utils;
+#[cfg(test)]
+mod test_utils;
 mod token;
+mod token_registry;
+mod transaction_pipeline;
 mod tulip;
 
 use {
-    crate::{get_transaction_balance_change::*, token::*},
+    crate::{
+        daemon::*,
+        get_stake_changes::*,
+        get_transaction_balance_change::*,
+        plan::*,
+        pubsub::{Pubsub, SignatureResult, Subscription, WebsocketPubsub},
+        token::*,
+        transaction_pipeline::*,
+    },
     chrono::prelude::*,
     chrono_humanize::HumanTime,
     clap::{
@@ -19,51 +38,50 @@ use {
         ArgMatches, SubCommand,
     },
     console::{style, Style},
+    currency::*,
     db::*,
     exchange::*,
     itertools::Itertools,
     notifier::*,
     rust_decimal::prelude::*,
     separator::FixedPlaceSeparatable,
-    solana_clap_utils::{self, input_parsers::*, input_validators::*},
+    solana_clap_utils::{self, input_parsers::*, input_validators::*, keypair::SignerSourceKind},
     solana_client::{
-        rpc_client::RpcClient, rpc_config::RpcTransactionConfig, rpc_response::StakeActivationState,
+        rpc_client::RpcClient,
+        rpc_config::{RpcBlockConfig, RpcLeaderScheduleConfig},
+        rpc_response::StakeActivationState,
     },
+    solana_remote_wallet::remote_wallet::RemoteWalletManager,
     solana_sdk::{
-        clock::Slot,
+        account_utils::StateMut,
+        clock::{Epoch, Slot},
         commitment_config::CommitmentConfig,
-        message::Message,
+        epoch_schedule::EpochSchedule,
+        hash::hash,
+        message::VersionedMessage,
         native_token::lamports_to_sol,
         pubkey::Pubkey,
-        signature::{read_keypair_file, Keypair, Signature, Signer},
+        reward_type::RewardType,
+        signature::{Keypair, Signature, Signer},
         signers::Signers,
         system_instruction, system_program,
-        transaction::Transaction,
     },
+    solana_transaction_status::TransactionDetails,
     std::{
-        collections::{BTreeMap, HashSet},
+        collections::{BTreeMap, HashMap, HashSet, VecDeque},
         fs,
-        path::PathBuf,
+        path::{Path, PathBuf},
         process::exit,
         str::FromStr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
     },
 };
 
-fn get_deprecated_fee_calculator(
-    rpc_client: &RpcClient,
-) -> solana_client::client_error::Result<solana_sdk::fee_calculator::FeeCalculator> {
-    // TODO: Rework calls to avoid the use of `FeeCalculator`
-    #[allow(deprecated)]
-    rpc_client.get_fees().map(|fees| fees.fee_calculator)
-}
-
-pub(crate) fn today() -> NaiveDate {
-    let today = Local::now().date();
-    NaiveDate::from_ymd(today.year(), today.month(), today.day())
-}
-
-fn is_long_term_cap_gain(acquisition: NaiveDate, disposal: Option<NaiveDate>) -> bool {
-    let disposal = disposal.unwrap_or_else(today);
+fn is_long_term_cap_gain(acquisition: NaiveDate, disposal: NaiveDate) -> bool {
     let hold_time = disposal - acquisition;
     hold_time >= chrono::Duration::days(356)
 }
@@ -105,68 +123,62 @@ fn app_version() -> String {
 async fn get_block_date(
     rpc_client: &RpcClient,
     slot: Slot,
+    reporting_timezone: ReportingTimezone,
 ) -> Result<NaiveDate, Box<dyn std::error::Error>> {
     let block_time = rpc_client.get_block_time(slot)?;
-    let local_timestamp = Local.timestamp(block_time, 0);
-    Ok(NaiveDate::from_ymd(
-        local_timestamp.year(),
-        local_timestamp.month(),
-        local_timestamp.day(),
-    ))
+    Ok(reporting_timezone.date_of_timestamp(block_time))
+}
+
+// `fiat_fungible()` tokens track USD, so their lots are priced at the USD rate of the base
+// currency on `when`
+async fn get_fiat_price(db: &Db, when: NaiveDate) -> Result<Decimal, Box<dyn std::error::Error>> {
+    db.get_fx_rate_source()
+        .get_historical_rate(Currency::USD, db.get_base_currency(), when)
+        .await
 }
 
 async fn get_signature_date(
     rpc_client: &RpcClient,
     signature: Signature,
+    reporting_timezone: ReportingTimezone,
 ) -> Result<NaiveDate, Box<dyn std::error::Error>> {
     let statuses = rpc_client.get_signature_statuses_with_history(&[signature])?;
     if let Some(Some(ts)) = statuses.value.get(0) {
-        let block_date = get_block_date(rpc_client, ts.slot).await?;
+        let block_date = get_block_date(rpc_client, ts.slot, reporting_timezone).await?;
         Ok(block_date)
     } else {
         Err(format!("Unknown signature: {}", signature).into())
     }
 }
 
+// Returns the block date and, if `price_precision` is finer than a day, the block time in the
+// reporting timezone, and the token price at that precision
 async fn get_block_date_and_price(
     rpc_client: &RpcClient,
     slot: Slot,
     token: MaybeToken,
-) -> Result<(NaiveDate, Decimal), Box<dyn std::error::Error>> {
-    let block_date = get_block_date(rpc_client, slot).await?;
-    Ok((
-        block_date,
-        token.get_historical_price(rpc_client, block_date).await?,
-    ))
-}
-
-fn send_transaction_until_expired(
-    rpc_client: &RpcClient,
-    transaction: &Transaction,
-    last_valid_block_height: u64,
-) -> bool {
-    loop {
-        match rpc_client.get_epoch_info() {
-            Ok(epoch_info) => {
-                if epoch_info.block_height > last_valid_block_height {
-                    return false;
-                }
-                println!(
-                    "Transaction pending for at most {} blocks",
-                    last_valid_block_height.saturating_sub(epoch_info.block_height),
-                );
-            }
-            Err(err) => {
-                println!("Failed to get epoch info: {:?}", err);
-            }
-        };
+    price_precision: PricePrecision,
+    currency: Currency,
+    reporting_timezone: ReportingTimezone,
+) -> Result<(NaiveDate, Option<NaiveDateTime>, Decimal), Box<dyn std::error::Error>> {
+    let block_time = rpc_client.get_block_time(slot)?;
+    let block_date = reporting_timezone.date_of_timestamp(block_time);
 
-        match rpc_client.send_and_confirm_transaction_with_spinner(transaction) {
-            Ok(_) => return true,
-            Err(err) => {
-                println!("Transaction failed to send: {:?}", err);
-            }
-        }
+    match price_precision.truncate(NaiveDateTime::from_timestamp(block_time, 0)) {
+        None => Ok((
+            block_date,
+            None,
+            token
+                .get_historical_price(rpc_client, block_date, currency)
+                .await?,
+        )),
+        Some(utc_time) => Ok((
+            block_date,
+            Some(reporting_timezone.local_datetime(utc_time)),
+            token
+                .get_historical_price_at(rpc_client, utc_time, currency)
+                .await?,
+        )),
     }
 }
 
@@ -187,6 +199,9 @@ fn add_exchange_deposit_address_to_db(
             last_update_balance: 0,
             lots: vec![],
             no_sync: Some(true),
+            last_update_signature: None,
+            last_stake_change_signature: None,
+            token_account: None,
         })?;
     }
     Ok(())
@@ -329,9 +344,18 @@ async fn process_sync_exchange(
                     notifier.send(&format!("{:?}: {}", exchange, msg)).await;
                 }
 
-                let when =
-                    get_signature_date(rpc_client, pending_deposit.transfer.signature).await?;
-                db.confirm_deposit(pending_deposit.transfer.signature, when)?;
+                let when = get_signature_date(
+                    rpc_client,
+                    pending_deposit.transfer.signature,
+                    db.get_reporting_timezone(),
+                )
+                .await?;
+                let fiat_price = if token.fiat_fungible() {
+                    get_fiat_price(db, when).await?
+                } else {
+                    Decimal::from_usize(1).unwrap()
+                };
+                db.confirm_deposit(pending_deposit.transfer.signature, when, fiat_price)?;
 
                 let msg = format!(
                     "{} {}{} deposit successful ({})",
@@ -343,6 +367,16 @@ async fn process_sync_exchange(
                 println!("{}", msg);
                 notifier.send(&format!("{:?}: {}", exchange, msg)).await;
             }
+        } else if !confirmed
+            && pending_deposit.transfer.last_valid_block_height == OFFLINE_BLOCK_HEIGHT
+        {
+            println!(
+                "{} {}{} deposit awaiting submission ({})",
+                token,
+                token.symbol(),
+                token.ui_amount(pending_deposit.amount),
+                pending_deposit.transfer.signature,
+            );
         } else if !confirmed && block_height > pending_deposit.transfer.last_valid_block_height {
             println!(
                 "Pending {} deposit cancelled: {}",
@@ -408,13 +442,40 @@ async fn process_sync_exchange(
                 Some((amount, coin)) if *amount > 0. => format!(" (fee: {} {})", amount, coin),
                 _ => "".into(),
             };
+            // Exchange prices and fees are in USD, lots are recorded in the base currency
+            let base_currency = db.get_base_currency();
+            let fx_rate_source = db.get_fx_rate_source();
+            let price = fx_rate_source
+                .convert(
+                    order_status.price,
+                    Currency::USD,
+                    base_currency,
+                    order_status.last_update,
+                )
+                .await?;
+            let fee = match order_status.fee {
+                Some((amount, coin)) if crate::exchange::USD_COINS.contains(&coin.as_str()) => {
+                    Some((
+                        fx_rate_source
+                            .convert(
+                                amount,
+                                Currency::USD,
+                                base_currency,
+                                order_status.last_update,
+                            )
+                            .await?,
+                        base_currency.name().to_string(),
+                    ))
+                }
+                fee => fee,
+            };
             db.close_order(
                 &order_info.order_id,
                 token.amount(order_status.amount),
                 token.amount(order_status.filled_amount),
-                order_status.price,
+                price,
                 order_status.last_update,
-                order_status.fee,
+                fee,
             )?;
             let msg = if (order_status.amount - order_status.filled_amount).abs() < f64::EPSILON {
                 format!(" Filled {}{}", order_summary, fee_summary)
@@ -473,7 +534,15 @@ async fn process_exchange_deposit<T: Signers>(
         }
     }
 
-    let from_account_balance = token.balance(rpc_client, &from_address)?;
+    // Use the tracked token account of `from_address` if any, otherwise its associated token account
+    let from_balance_address = db
+        .get_account(from_address, token)
+        .map(|account| account.balance_address())
+        .unwrap_or_else(|| match token.token() {
+            Some(token) => token.ata(&from_address),
+            None => from_address,
+        });
+    let from_account_balance = token.balance_at(rpc_client, &from_balance_address)?;
     if let Some(if_source_balance_exceeds) = if_source_balance_exceeds {
         if from_account_balance < if_source_balance_exceeds {
             println!(
@@ -487,9 +556,8 @@ async fn process_exchange_deposit<T: Signers>(
         }
     }
 
-    let (recent_blockhash, last_valid_block_height) =
-        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-    let fee_calculator = get_deprecated_fee_calculator(rpc_client)?;
+    let transaction_pipeline = TransactionPipeline::new(rpc_client, db);
+    let max_fee = transaction_pipeline.get_max_fee(1)?;
 
     let from_account = rpc_client
         .get_account_with_commitment(&from_address, rpc_client.commitment())?
@@ -505,10 +573,10 @@ async fn process_exchange_deposit<T: Signers>(
             .ok_or_else(|| format!("Authority account, {}, does not exist", authority_address))?
     };
 
-    if authority_account.lamports < fee_calculator.lamports_per_signature {
+    if authority_account.lamports < max_fee {
         return Err(format!(
             "Authority has insufficient funds for the transaction fee of {}",
-            lamports_to_sol(fee_calculator.lamports_per_signature)
+            lamports_to_sol(max_fee)
         )
         .into());
     }
@@ -521,7 +589,7 @@ async fn process_exchange_deposit<T: Signers>(
             if from_account.owner == system_program::id() {
                 let amount = amount.unwrap_or_else(|| {
                     if from_address == authority_address {
-                        from_account_balance.saturating_sub(fee_calculator.lamports_per_signature)
+                        from_account_balance.saturating_sub(max_fee)
                     } else {
                         from_account_balance
                     }
@@ -581,28 +649,16 @@ async fn process_exchange_deposit<T: Signers>(
                 .value
                 .is_none()
             {
-                instructions.push(
-                    spl_associated_token_account::create_associated_token_account(
-                        &authority_address,
-                        &deposit_address,
-                        &token.mint(),
-                    ),
-                );
+                instructions
+                    .push(token.create_ata_instruction(&authority_address, &deposit_address));
             }
 
-            instructions.push(
-                spl_token::instruction::transfer_checked(
-                    &spl_token::id(),
-                    &token.ata(&from_address),
-                    &token.mint(),
-                    &token.ata(&deposit_address),
-                    &authority_address,
-                    &[],
-                    amount,
-                    token.decimals(),
-                )
-                .unwrap(),
-            );
+            instructions.push(token.transfer_checked_instruction(
+                &from_balance_address,
+                &token.ata(&deposit_address),
+                &authority_address,
+                amount,
+            ));
 
             (instructions, amount)
         }
@@ -625,37 +681,33 @@ async fn process_exchange_deposit<T: Signers>(
         token, exchange, deposit_address
     );
 
-    let mut message = Message::new(&instructions, Some(&authority_address));
-    message.recent_blockhash = recent_blockhash;
-    if rpc_client.get_fee_for_message(&message)? > authority_account.lamports {
-        return Err("Insufficient funds for transaction fee".into());
-    }
-
-    let mut transaction = Transaction::new_unsigned(message);
-    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
-    if simulation_result.err.is_some() {
-        return Err(format!("Simulation failure: {:?}", simulation_result).into());
-    }
-
-    transaction.try_sign(&signers, recent_blockhash)?;
-    let signature = transaction.signatures[0];
-    println!("Transaction signature: {}", signature);
-
-    db.record_deposit(
-        signature,
-        last_valid_block_height,
-        from_address,
-        amount,
-        exchange,
-        deposit_address,
-        token,
-        lot_selection_method,
-        lot_numbers,
-    )?;
-    if !send_transaction_until_expired(rpc_client, &transaction, last_valid_block_height) {
-        db.cancel_deposit(signature).expect("cancel_deposit");
-        return Err("Deposit failed".into());
-    }
+    transaction_pipeline
+        .execute(
+            db,
+            TransactionPayload::Instructions {
+                instructions: &instructions,
+                payer: &authority_address,
+            },
+            |transaction, recent_blockhash| transaction.try_sign(&signers, recent_blockhash),
+            Some(Pending::new(
+                PendingKind::Deposit,
+                |db, signature, last_valid_block_height| {
+                    db.record_deposit(
+                        signature,
+                        last_valid_block_height,
+                        from_address,
+                        amount,
+                        exchange,
+                        deposit_address,
+                        token,
+                        lot_selection_method,
+                        lot_numbers.clone(),
+                    )
+                },
+            )),
+        )
+        .await
+        .map_err(|err| format!("Deposit failed: {}", err))?;
     Ok(())
 }
 
@@ -681,6 +733,11 @@ async fn process_exchange_withdraw(
         .expect("unknown to address");
 
     let amount = amount.unwrap_or(deposit_account.last_update_balance);
+    let fiat_price = if token.fiat_fungible() {
+        get_fiat_price(db, db.get_reporting_timezone().today()).await?
+    } else {
+        Decimal::from_usize(1).unwrap()
+    };
 
     let tag = exchange_client
         .request_withdraw(
@@ -701,6 +758,7 @@ async fn process_exchange_withdraw(
         to_address,
         lot_selection_method,
         lot_numbers,
+        fiat_price,
     )?;
     Ok(())
 }
@@ -911,18 +969,29 @@ async fn process_exchange_sell(
         lot_selection_method,
         lot_numbers,
     )?;
+    // Exchange prices are in USD, lot basis is in the base currency
+    let base_currency = db.get_base_currency();
+    let today = db.get_reporting_timezone().today();
+    let base_price = db
+        .get_fx_rate_source()
+        .convert(price, Currency::USD, base_currency, today)
+        .await?;
+
     if if_price_over_basis {
         if let Some(basis) = order_lots.iter().find_map(|lot| {
             let basis = lot.acquisition.price();
-            if Decimal::from_f64(price).unwrap() < basis {
+            if Decimal::from_f64(base_price).unwrap() < basis {
                 Some(basis)
             } else {
                 None
             }
         }) {
             let msg = format!(
-                "Order declined because price, ${}, is less than basis ${}",
-                price, basis,
+                "Order declined because price, {}{}, is less than basis {}{}",
+                base_currency.symbol(),
+                base_price,
+                base_currency.symbol(),
+                basis,
             );
             println!("{}", msg);
             notifier.send(&format!("{:?}: {}", exchange, msg)).await;
@@ -940,7 +1009,9 @@ async fn process_exchange_sell(
         println_lot(
             deposit_account.token,
             lot,
-            Decimal::from_f64(price).unwrap(),
+            Decimal::from_f64(base_price).unwrap(),
+            base_currency,
+            today,
             None,
             &mut 0.,
             &mut 0.,
@@ -1059,8 +1130,13 @@ async fn process_jup_swap<T: Signers>(
     })?;
 
     println!("Fetching best {}->{} quote...", from_token, to_token);
-    let from_token_price = from_token.get_current_price(rpc_client).await?;
-    let to_token_price = to_token.get_current_price(rpc_client).await?;
+    let base_currency = db.get_base_currency();
+    let from_token_price = from_token
+        .get_current_price(rpc_client, base_currency)
+        .await?;
+    let to_token_price = to_token
+        .get_current_price(rpc_client, base_currency)
+        .await?;
 
     let quotes = jup_ag::quote(
         from_token.mint(),
@@ -1091,48 +1167,20 @@ async fn process_jup_swap<T: Signers>(
     if swap_transactions.cleanup.is_some() {
         return Err("swap cleanup transaction not supported".into());
     }
-    if let Some(mut transaction) = swap_transactions.setup {
-        let (recent_blockhash, last_valid_block_height) =
-            rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-        transaction.message.recent_blockhash = recent_blockhash;
-
-        let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
-        if simulation_result.err.is_some() {
-            return Err(format!(
-                "Setup transaction simulation failure: {:?}",
-                simulation_result
+    let transaction_pipeline = TransactionPipeline::new(rpc_client, db);
+    if let Some(transaction) = swap_transactions.setup {
+        println!("Sending setup transaction");
+        transaction_pipeline
+            .execute_versioned(
+                db,
+                VersionedMessage::Legacy(transaction.message),
+                &signers,
+                None,
             )
-            .into());
-        }
-
-        transaction.try_sign(&signers, recent_blockhash)?;
-        let signature = transaction.signatures[0];
-        println!("Setup transaction signature: {}", signature);
-
-        if !send_transaction_until_expired(rpc_client, &transaction, last_valid_block_height) {
-            return Err("Setup transaction failed".into());
-        }
-    }
-
-    let mut transaction = swap_transactions.swap;
-
-    let (recent_blockhash, last_valid_block_height) =
-        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-    transaction.message.recent_blockhash = recent_blockhash;
-
-    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
-    if simulation_result.err.is_some() {
-        return Err(format!(
-            "Swap transaction simulation failure: {:?}",
-            simulation_result
-        )
-        .into());
+            .await
+            .map_err(|err| format!("Setup transaction failed: {}", err))?;
     }
 
-    transaction.try_sign(&signers, recent_blockhash)?;
-    let signature = transaction.signatures[0];
-    println!("Transaction signature: {}", signature);
-
     if db.get_account(address, to_token.into()).is_none() {
         let epoch = rpc_client.get_epoch_info()?.epoch;
         db.add_account(TrackedAccount {
@@ -1143,23 +1191,34 @@ async fn process_jup_swap<T: Signers>(
             last_update_balance: 0,
             lots: vec![],
             no_sync: None,
+            last_update_signature: None,
+            last_stake_change_signature: None,
+            token_account: None,
         })?;
     }
-    db.record_swap(
-        signature,
-        last_valid_block_height,
-        address,
-        from_token.into(),
-        from_token_price,
-        to_token.into(),
-        to_token_price,
-        lot_selection_method,
-    )?;
-
-    if !send_transaction_until_expired(rpc_client, &transaction, last_valid_block_height) {
-        db.cancel_swap(signature)?;
-        return Err("Swap failed".into());
-    }
+    transaction_pipeline
+        .execute_versioned(
+            db,
+            VersionedMessage::Legacy(swap_transactions.swap.message),
+            &signers,
+            Some(Pending::new(
+                PendingKind::Swap,
+                |db, signature, last_valid_block_height| {
+                    db.record_swap(
+                        signature,
+                        last_valid_block_height,
+                        address,
+                        from_token.into(),
+                        from_token_price,
+                        to_token.into(),
+                        to_token_price,
+                        lot_selection_method,
+                    )
+                },
+            )),
+        )
+        .await
+        .map_err(|err| format!("Swap failed: {}", err))?;
     Ok(())
 }
 
@@ -1212,15 +1271,21 @@ async fn process_tulip_deposit<T: Signers>(
         return Err("Nothing to deposit".into());
     }
 
-    let liquidity_token_price = liquidity_token.get_current_price(rpc_client).await?;
-    let collateral_token_price = collateral_token.get_current_price(rpc_client).await?;
+    let base_currency = db.get_base_currency();
+    let liquidity_token_price = liquidity_token
+        .get_current_price(rpc_client, base_currency)
+        .await?;
+    let collateral_token_price = collateral_token
+        .get_current_price(rpc_client, base_currency)
+        .await?;
     let liquidity_token_ui_amount = liquidity_token.ui_amount(liquidity_amount);
 
     println!("{}: {} -> {}", address, liquidity_token, collateral_token);
     println!(
-        "Estimated deposit amount: {}{} (${})",
+        "Estimated deposit amount: {}{} ({}{})",
         liquidity_token.symbol(),
         liquidity_token.ui_amount(liquidity_amount),
+        base_currency.symbol(),
         liquidity_token_price * Decimal::from_f64(liquidity_token_ui_amount).unwrap()
     );
 
@@ -1232,21 +1297,13 @@ async fn process_tulip_deposit<T: Signers>(
         liquidity_amount,
     )?;
 
-    let (recent_blockhash, last_valid_block_height) =
-        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-
-    let mut message = Message::new(&instructions, Some(&address));
-    message.recent_blockhash = recent_blockhash;
-
-    let mut transaction = Transaction::new_unsigned(message);
-    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
-    if simulation_result.err.is_some() {
-        return Err(format!("Simulation failure: {:?}", simulation_result).into());
-    }
-
-    transaction.try_sign(&signers, recent_blockhash)?;
-    let signature = transaction.signatures[0];
-    println!("Transaction signature: {}", signature);
+    let transaction_pipeline = TransactionPipeline::new(rpc_client, db);
+    let payload = TransactionPayload::Instructions {
+        instructions: &instructions,
+        payer: &address,
+    };
+    // Simulate before tracking the collateral account
+    transaction_pipeline.prepare(&payload)?;
 
     if db.get_account(address, collateral_token.into()).is_none() {
         let epoch = rpc_client.get_epoch_info()?.epoch;
@@ -1258,23 +1315,34 @@ async fn process_tulip_deposit<T: Signers>(
             last_update_balance: 0,
             lots: vec![],
             no_sync: Some(true),
+            last_update_signature: None,
+            last_stake_change_signature: None,
+            token_account: None,
         })?;
     }
-    db.record_swap(
-        signature,
-        last_valid_block_height,
-        address,
-        liquidity_token,
-        liquidity_token_price,
-        collateral_token.into(),
-        collateral_token_price,
-        lot_selection_method,
-    )?;
-
-    if !send_transaction_until_expired(rpc_client, &transaction, last_valid_block_height) {
-        db.cancel_swap(signature).expect("cancel_swap");
-        return Err("Swap failed".into());
-    }
+    transaction_pipeline
+        .execute(
+            db,
+            payload,
+            |transaction, recent_blockhash| transaction.try_sign(&signers, recent_blockhash),
+            Some(Pending::new(
+                PendingKind::Swap,
+                |db, signature, last_valid_block_height| {
+                    db.record_swap(
+                        signature,
+                        last_valid_block_height,
+                        address,
+                        liquidity_token,
+                        liquidity_token_price,
+                        collateral_token.into(),
+                        collateral_token_price,
+                        lot_selection_method,
+                    )
+                },
+            )),
+        )
+        .await
+        .map_err(|err| format!("Swap failed: {}", err))?;
     Ok(())
 }
 
@@ -1319,15 +1387,21 @@ async fn process_tulip_withdraw<T: Signers>(
         return Err("Nothing to withdraw".into());
     }
 
-    let liquidity_token_price = liquidity_token.get_current_price(rpc_client).await?;
-    let collateral_token_price = collateral_token.get_current_price(rpc_client).await?;
+    let base_currency = db.get_base_currency();
+    let liquidity_token_price = liquidity_token
+        .get_current_price(rpc_client, base_currency)
+        .await?;
+    let collateral_token_price = collateral_token
+        .get_current_price(rpc_client, base_currency)
+        .await?;
     let collateral_token_ui_amount = collateral_token.ui_amount(collateral_amount);
 
     println!("{}: {} -> {}", address, collateral_token, liquidity_token);
     println!(
-        "Estimated withdraw amount: {}{} (${})",
+        "Estimated withdraw amount: {}{} ({}{})",
         collateral_token.symbol(),
         collateral_token.ui_amount(collateral_amount),
+        base_currency.symbol(),
         collateral_token_price * Decimal::from_f64(collateral_token_ui_amount).unwrap()
     );
 
@@ -1339,37 +1413,32 @@ async fn process_tulip_withdraw<T: Signers>(
         collateral_amount,
     )?;
 
-    let (recent_blockhash, last_valid_block_height) =
-        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-
-    let mut message = Message::new(&instructions, Some(&address));
-    message.recent_blockhash = recent_blockhash;
-
-    let mut transaction = Transaction::new_unsigned(message);
-    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
-    if simulation_result.err.is_some() {
-        return Err(format!("Simulation failure: {:?}", simulation_result).into());
-    }
-
-    transaction.try_sign(&signers, recent_blockhash)?;
-    let signature = transaction.signatures[0];
-    println!("Transaction signature: {}", signature);
-
-    db.record_swap(
-        signature,
-        last_valid_block_height,
-        address,
-        collateral_token.into(),
-        collateral_token_price,
-        liquidity_token,
-        liquidity_token_price,
-        lot_selection_method,
-    )?;
-
-    if !send_transaction_until_expired(rpc_client, &transaction, last_valid_block_height) {
-        db.cancel_swap(signature).expect("cancel_swap");
-        return Err("Swap failed".into());
-    }
+    TransactionPipeline::new(rpc_client, db)
+        .execute(
+            db,
+            TransactionPayload::Instructions {
+                instructions: &instructions,
+                payer: &address,
+            },
+            |transaction, recent_blockhash| transaction.try_sign(&signers, recent_blockhash),
+            Some(Pending::new(
+                PendingKind::Swap,
+                |db, signature, last_valid_block_height| {
+                    db.record_swap(
+                        signature,
+                        last_valid_block_height,
+                        address,
+                        collateral_token.into(),
+                        collateral_token_price,
+                        liquidity_token,
+                        liquidity_token_price,
+                        lot_selection_method,
+                    )
+                },
+            )),
+        )
+        .await
+        .map_err(|err| format!("Swap failed: {}", err))?;
 
     Ok(())
 }
@@ -1380,6 +1449,8 @@ async fn process_sync_swaps(
     notifier: &Notifier,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let block_height = rpc_client.get_epoch_info()?.block_height;
+    let price_precision = db.get_price_precision();
+    let reporting_timezone = db.get_reporting_timezone();
 
     for PendingSwap {
         signature,
@@ -1401,22 +1472,18 @@ async fn process_sync_swaps(
             Some(result) => {
                 if result.is_ok() {
                     println!("Pending {} confirmed: {}", swap, signature);
-                    let result = rpc_client.get_transaction_with_config(
-                        &signature,
-                        RpcTransactionConfig {
-                            commitment: Some(rpc_client.commitment()),
-                            ..RpcTransactionConfig::default()
-                        },
-                    )?;
+                    let result = rpc_client_utils::get_transaction(rpc_client, &signature)?;
 
                     let block_time = result
                         .block_time
                         .ok_or("Transaction block time not available")?;
 
-                    let when = Local.timestamp(block_time, 0);
-                    let when = NaiveDate::from_ymd(when.year(), when.month(), when.day());
+                    let when = reporting_timezone.date_of_timestamp(block_time);
+                    let time = price_precision
+                        .truncate(NaiveDateTime::from_timestamp(block_time, 0))
+                        .map(|utc_time| reporting_timezone.local_datetime(utc_time));
 
-                    let transaction_status_meta = result.transaction.meta.unwrap();
+                    let transaction_status_meta = result.meta;
 
                     let pre_token_balances = transaction_status_meta
                         .pre_token_balances
@@ -1507,7 +1574,7 @@ async fn process_sync_swaps(
                             .ui_amount(to_amount)
                             .separated_string_with_fixed_place(2),
                     );
-                    db.confirm_swap(signature, when, from_amount, to_amount)?;
+                    db.confirm_swap(signature, when, time, from_amount, to_amount)?;
                     notifier.send(&msg).await;
                     println!("{}", msg);
                 } else {
@@ -1516,7 +1583,9 @@ async fn process_sync_swaps(
                 }
             }
             None => {
-                if block_height > last_valid_block_height {
+                if last_valid_block_height == OFFLINE_BLOCK_HEIGHT {
+                    println!("{} awaiting submission: {}", swap, signature);
+                } else if block_height > last_valid_block_height {
                     println!("Pending {} cancelled: {}", swap, signature);
                     db.cancel_swap(signature)?;
                 } else {
@@ -1590,6 +1659,8 @@ async fn println_lot(
     token: MaybeToken,
     lot: &Lot,
     current_price: Decimal,
+    currency: Currency,
+    today: NaiveDate,
     liquidity_token_info: Option<&LiquidityTokenInfo>,
     total_income: &mut f64,
     total_cap_gain: &mut f64,
@@ -1620,7 +1691,7 @@ async fn println_lot(
     *total_income += income;
     *total_cap_gain += cap_gain;
     *total_current_value += current_value;
-    *long_term_cap_gain = is_long_term_cap_gain(lot.acquisition.when, None);
+    *long_term_cap_gain = is_long_term_cap_gain(lot.acquisition.when, today);
 
     let ui_amount = token.ui_amount(lot.amount);
     let (liquidity_ui_amount, liquidity_token_cap_gain) = liquidity_token_ui_amount(
@@ -1631,7 +1702,7 @@ async fn println_lot(
     );
 
     let msg = format!(
-        "{:>4}. {} | {}{:<16} at ${:<6} | current value: ${:<14}{} | income: ${:<11} | {} gain: ${:<14}{} | {}",
+        "{:>4}. {} | {}{:<16} at {c}{:<6} | current value: {c}{:<14}{} | income: {c}{:<11} | {} gain: {c}{:<14}{} | {}",
         lot.lot_number,
         lot.acquisition.when,
         token.symbol(),
@@ -1648,6 +1719,7 @@ async fn println_lot(
         cap_gain.separated_string_with_fixed_place(2),
         liquidity_token_cap_gain,
         lot.acquisition.kind,
+        c = currency.symbol(),
     );
 
     if let Some(notifier) = notifier {
@@ -1657,8 +1729,16 @@ async fn println_lot(
     println!("{}", msg);
 }
 
+fn format_acquisition_when(acquisition: &LotAcquistion) -> String {
+    acquisition
+        .time
+        .map(|time| time.to_string())
+        .unwrap_or_else(|| acquisition.when.to_string())
+}
+
 fn format_disposed_lot(
     disposed_lot: &DisposedLot,
+    currency: Currency,
     total_income: &mut f64,
     total_cap_gain: &mut f64,
     long_term_cap_gain: &mut bool,
@@ -1670,13 +1750,13 @@ fn format_disposed_lot(
     let income = disposed_lot.lot.income(disposed_lot.token);
 
     *long_term_cap_gain =
-        is_long_term_cap_gain(disposed_lot.lot.acquisition.when, Some(disposed_lot.when));
+        is_long_term_cap_gain(disposed_lot.lot.acquisition.when, disposed_lot.when);
     *total_income += income;
     *total_current_value += income + cap_gain;
     *total_cap_gain += cap_gain;
 
     format!(
-        "{:>4}. {} | {:<4} | {}{:<17.9} at ${:<6} | income: ${:<11} | sold {} at ${:6} | {} gain: ${:<14} | {} | {}",
+        "{:>4}. {} | {:<4} | {}{:<17.9} at {c}{:<6} | income: {c}{:<11} | sold {} at {c}{:6} | {} gain: {c}{:<14} | {} | {}",
         disposed_lot.lot.lot_number,
         disposed_lot.lot.acquisition.when,
         disposed_lot.token,
@@ -1694,6 +1774,7 @@ fn format_disposed_lot(
         cap_gain.separated_string_with_fixed_place(2),
         disposed_lot.lot.acquisition.kind,
         disposed_lot.kind,
+        c = currency.symbol(),
     )
 }
 
@@ -1709,28 +1790,51 @@ async fn process_account_add(
     income: bool,
     signature: Option<Signature>,
     no_sync: bool,
+    token_account: Option<Pubkey>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let reporting_timezone = db.get_reporting_timezone();
+    let today = reporting_timezone.today();
+
+    let balance_address = match (token.token(), token_account) {
+        (None, Some(_)) => return Err("A token account cannot be specified for SOL".into()),
+        (None, None) => address,
+        (Some(token), None) => token.ata(&address),
+        (Some(token), Some(token_account)) => {
+            let owner = token.get_token_account_owner(rpc_client, &token_account)?;
+            if owner != address {
+                return Err(format!(
+                    "Token account {} is owned by {}, not {}",
+                    token_account, owner, address
+                )
+                .into());
+            }
+            token_account
+        }
+    };
+
     let (when, amount, last_update_epoch, kind) = match signature {
         Some(signature) => {
-            let (address, address_is_token) = match token.token() {
-                Some(token) => (token.ata(&address), true),
-                None => (address, false),
-            };
-
             let GetTransactionAddrssBalanceChange {
                 post_amount,
                 slot,
                 when: block_time,
                 ..
-            } = get_transaction_balance_change(rpc_client, &signature, &address, address_is_token)?;
+            } = get_transaction_balance_change(
+                rpc_client,
+                &signature,
+                &balance_address,
+                token.is_token(),
+            )?;
 
-            let when = block_time.map(|dt| dt.date()).or_else(|| {
-                println!(
-                    "Block time not available for slot {}, using `--when` argument instead",
-                    slot
-                );
-                when
-            });
+            let when = block_time
+                .map(|dt| reporting_timezone.local_datetime(dt).date())
+                .or_else(|| {
+                    println!(
+                        "Block time not available for slot {}, using `--when` argument instead",
+                        slot
+                    );
+                    when
+                });
 
             let epoch_schdule = rpc_client.get_epoch_schedule()?;
             let last_update_epoch = epoch_schdule
@@ -1742,11 +1846,15 @@ async fn process_account_add(
                 when,
                 post_amount,
                 last_update_epoch,
-                LotAcquistionKind::Transaction { slot, signature },
+                LotAcquistionKind::Transaction {
+                    slot,
+                    signature,
+                    income: false,
+                },
             )
         }
         None => {
-            let amount = token.balance(rpc_client, &address)?;
+            let amount = token.balance_at(rpc_client, &balance_address)?;
             let last_update_epoch = rpc_client.get_epoch_info()?.epoch.saturating_sub(1);
             (
                 when,
@@ -1763,11 +1871,16 @@ async fn process_account_add(
 
     println!("Adding {} (token: {})", address, token);
 
-    let current_price = token.get_current_price(rpc_client).await?;
+    let base_currency = db.get_base_currency();
+    let current_price = token.get_current_price(rpc_client, base_currency).await?;
     let decimal_price = match price {
         Some(price) => Decimal::from_f64(price).unwrap(),
         None => match when {
-            Some(when) => token.get_historical_price(rpc_client, when).await?,
+            Some(when) => {
+                token
+                    .get_historical_price(rpc_client, when, base_currency)
+                    .await?
+            }
             None => current_price,
         },
     };
@@ -1776,13 +1889,15 @@ async fn process_account_add(
     if amount > 0 {
         let lot = Lot {
             lot_number: db.next_lot_number(),
-            acquisition: LotAcquistion::new(when.unwrap_or_else(today), decimal_price, kind),
+            acquisition: LotAcquistion::new(when.unwrap_or(today), decimal_price, kind),
             amount,
         };
         println_lot(
             token,
             &lot,
             current_price,
+            base_currency,
+            today,
             None,
             &mut 0.,
             &mut 0.,
@@ -1803,6 +1918,9 @@ async fn process_account_add(
         last_update_balance: amount,
         lots,
         no_sync: Some(no_sync),
+        last_update_signature: None,
+        last_stake_change_signature: None,
+        token_account,
     };
     db.add_account(account)?;
 
@@ -1822,11 +1940,16 @@ async fn process_account_dispose(
     lot_selection_method: LotSelectionMethod,
     lot_numbers: Option<HashSet<usize>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let base_currency = db.get_base_currency();
     let price = match price {
         Some(price) => Decimal::from_f64(price).unwrap(),
         None => match when {
-            Some(when) => token.get_historical_price(rpc_client, when).await?,
-            None => token.get_current_price(rpc_client).await?,
+            Some(when) => {
+                token
+                    .get_historical_price(rpc_client, when, base_currency)
+                    .await?
+            }
+            None => token.get_current_price(rpc_client, base_currency).await?,
         },
     };
 
@@ -1835,7 +1958,7 @@ async fn process_account_dispose(
         token,
         token.amount(ui_amount),
         description,
-        when.unwrap_or_else(today),
+        when.unwrap_or_else(|| db.get_reporting_timezone().today()),
         price,
         lot_selection_method,
         lot_numbers,
@@ -1845,7 +1968,14 @@ async fn process_account_dispose(
         for disposed_lot in disposed_lots {
             println!(
                 "{}",
-                format_disposed_lot(&disposed_lot, &mut 0., &mut 0., &mut false, &mut 0.)
+                format_disposed_lot(
+                    &disposed_lot,
+                    base_currency,
+                    &mut 0.,
+                    &mut 0.,
+                    &mut false,
+                    &mut 0.
+                )
             );
         }
         println!();
@@ -1868,6 +1998,9 @@ async fn process_account_list(
     summary_only: bool,
     notifier: &Notifier,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let base_currency = db.get_base_currency();
+    let c = base_currency.symbol();
+    let today = db.get_reporting_timezone().today();
     let mut annual_realized_gains = BTreeMap::<usize, [RealizedGain; 4]>::default();
     let mut held_tokens = BTreeMap::<MaybeToken, (/*price*/ Decimal, /*amount*/ u64)>::default();
 
@@ -1891,6 +2024,8 @@ async fn process_account_list(
         let mut total_current_value = 0.;
 
         let open_orders = db.open_orders(None, None);
+        let deactivating_stakes = db.get_deactivating_stakes();
+        let pending_redelegations = db.get_pending_redelegations();
 
         for account in accounts {
             if let Some(ref account_filter) = account_filter {
@@ -1901,7 +2036,13 @@ async fn process_account_list(
 
             if let std::collections::btree_map::Entry::Vacant(e) = held_tokens.entry(account.token)
             {
-                e.insert((account.token.get_current_price(rpc_client).await?, 0));
+                e.insert((
+                    account
+                        .token
+                        .get_current_price(rpc_client, base_currency)
+                        .await?,
+                    0,
+                ));
             }
 
             let held_token = held_tokens.get_mut(&account.token).unwrap();
@@ -1929,20 +2070,49 @@ async fn process_account_list(
 
             let (liquidity_ui_amount, _) =
                 liquidity_token_ui_amount(None, ui_amount, liquidity_token_info.as_ref(), true);
+            let deactivating = if account.token.is_sol()
+                && deactivating_stakes
+                    .iter()
+                    .any(|ds| ds.address == account.address)
+            {
+                " (deactivating)".to_string()
+            } else if let Some(pending_redelegation) = pending_redelegations
+                .iter()
+                .find(|pr| account.token.is_sol() && pr.address == account.address)
+            {
+                format!(" (redelegating to {})", pending_redelegation.vote_address)
+            } else if let Some(token_account) = account.token_account {
+                format!(" (token account {})", token_account)
+            } else {
+                String::new()
+            };
             let msg = format!(
-                "{} ({}): {}{}{} - {}",
+                "{} ({}): {}{}{} - {}{}",
                 account.address,
                 account.token,
                 account.token.symbol(),
                 ui_amount.separated_string_with_fixed_place(2),
                 liquidity_ui_amount,
-                account.description
+                account.description,
+                deactivating
             );
             println!("{}", msg);
             if ui_amount > 0.01 {
                 notifier.send(&msg).await;
             }
-            account.assert_lot_balance();
+            if let Some(lot_balance) = account.lot_balance_mismatch() {
+                let msg = format!(
+                    "Warning: {} ({}) lots add up to {}{}, but its balance is {}{}",
+                    account.address,
+                    account.token,
+                    account.token.symbol(),
+                    account.token.ui_amount(lot_balance),
+                    account.token.symbol(),
+                    account.token.ui_amount(account.last_update_balance),
+                );
+                println!("{}", msg);
+                notifier.send(&msg).await;
+            }
 
             if summary_only {
                 continue;
@@ -1969,6 +2139,8 @@ async fn process_account_list(
                         account.token,
                         lot,
                         current_token_price,
+                        base_currency,
+                        today,
                         liquidity_token_info.as_ref(),
                         &mut account_income,
                         &mut account_unrealized_gain,
@@ -2015,6 +2187,8 @@ async fn process_account_list(
                             account.token,
                             lot,
                             current_token_price,
+                            base_currency,
+                            today,
                             liquidity_token_info.as_ref(),
                             &mut account_income,
                             &mut account_unrealized_gain,
@@ -2038,11 +2212,12 @@ async fn process_account_list(
                 }
 
                 println!(
-                    "    Value: ${}, income: ${}, unrealized short-term cap gain: ${}, unrealized long-term cap gain: ${}",
+                    "    Value: {c}{}, income: {c}{}, unrealized short-term cap gain: {c}{}, unrealized long-term cap gain: {c}{}",
                     account_current_value.separated_string_with_fixed_place(2),
                     account_income.separated_string_with_fixed_place(2),
                     account_unrealized_short_term_gain.separated_string_with_fixed_place(2),
                     account_unrealized_long_term_gain.separated_string_with_fixed_place(2),
+                    c = c,
                 );
 
                 total_unrealized_short_term_gain += account_unrealized_short_term_gain;
@@ -2074,6 +2249,7 @@ async fn process_account_list(
                 let mut disposed_cap_gain = 0.;
                 let msg = format_disposed_lot(
                     disposed_lot,
+                    base_currency,
                     &mut disposed_income,
                     &mut disposed_cap_gain,
                     &mut long_term_cap_gain,
@@ -2109,21 +2285,19 @@ async fn process_account_list(
                 }
             }
             println!(
-                "    Disposed value: ${} (income: ${}, short-term cap gain: ${}, long-term cap gain: ${})",
+                "    Disposed value: {c}{} (income: {c}{}, short-term cap gain: {c}{}, long-term cap gain: {c}{})",
                 disposed_value.separated_string_with_fixed_place(2),
                 disposed_income.separated_string_with_fixed_place(2),
                 disposed_short_term_cap_gain.separated_string_with_fixed_place(2),
                 disposed_long_term_cap_gain.separated_string_with_fixed_place(2),
+                c = c,
             );
             println!();
         }
 
         if let Some(sweep_stake_account) = db.get_sweep_stake_account() {
             println!("Sweep stake account: {}", sweep_stake_account.address);
-            println!(
-                "Stake authority: {}",
-                sweep_stake_account.stake_authority.display()
-            );
+            println!("Stake authority: {}", sweep_stake_account.stake_authority);
             println!();
         }
 
@@ -2135,7 +2309,7 @@ async fn process_account_list(
             for (q, realized_gain) in quarters.iter().enumerate() {
                 if *realized_gain != RealizedGain::default() {
                     println!(
-                        "  {} Q{} | ${:15} | ${:18} | ${:18} | ${:18}",
+                        "  {} Q{} | {c}{:15} | {c}{:18} | {c}{:18} | {c}{:18}",
                         year,
                         q + 1,
                         realized_gain.income.separated_string_with_fixed_place(2),
@@ -2149,6 +2323,7 @@ async fn process_account_list(
                             + realized_gain.short_term_cap_gain
                             + realized_gain.long_term_cap_gain)
                             .separated_string_with_fixed_place(2),
+                        c = c,
                     );
                 }
             }
@@ -2158,7 +2333,7 @@ async fn process_account_list(
         println!("Current Holdings Summary");
         for (held_token, (current_token_price, total_held_amount)) in held_tokens {
             println!(
-                "  {: >4}:                {}{} (${} per {}; ${})",
+                "  {: >4}:                {}{} ({c}{} per {}; {c}{})",
                 held_token.to_string(),
                 held_token.symbol(),
                 held_token
@@ -2174,85 +2349,680 @@ async fn process_account_list(
                 )
                 .unwrap()
                 .separated_string_with_fixed_place(2),
+                c = c,
             );
         }
         println!(
-            "  Value:               ${}",
+            "  Value:               {}{}",
+            c,
             total_current_value.separated_string_with_fixed_place(2)
         );
         println!(
-            "  Income:              ${} (realized)",
+            "  Income:              {}{} (realized)",
+            c,
             total_income.separated_string_with_fixed_place(2)
         );
         println!(
-            "  Short-term cap gain: ${} (unrealized)",
+            "  Short-term cap gain: {}{} (unrealized)",
+            c,
             total_unrealized_short_term_gain.separated_string_with_fixed_place(2)
         );
         println!(
-            "  Long-term cap gain:  ${} (unrealized)",
+            "  Long-term cap gain:  {}{} (unrealized)",
+            c,
             total_unrealized_long_term_gain.separated_string_with_fixed_place(2)
         );
     }
     Ok(())
 }
 
-async fn process_account_xls(
+fn format_sparkline(values: &[f64]) -> String {
+    const BARS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    values
+        .iter()
+        .map(|value| {
+            if max > min {
+                BARS[((value - min) / (max - min) * (BARS.len() - 1) as f64).round() as usize]
+            } else {
+                BARS[0]
+            }
+        })
+        .collect()
+}
+
+// Per-epoch economics of a validator: vote credits, commission earned by the vote account,
+// identity fee rewards and the vote transaction costs paid by the identity
+async fn process_report_validator(
     db: &Db,
-    outfile: &str,
-    filter_by_year: Option<i32>,
+    rpc_client: &RpcClient,
+    vote_address: Pubkey,
+    epochs: u64,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    use simple_excel_writer::*;
+    let token = MaybeToken::SOL();
+    let base_currency = db.get_base_currency();
+    let c = base_currency.symbol();
+    let price_precision = db.get_price_precision();
+    let reporting_timezone = db.get_reporting_timezone();
 
-    let mut workbook = Workbook::create(outfile);
+    let vote_account = rpc_client
+        .get_account_with_commitment(&vote_address, rpc_client.commitment())?
+        .value
+        .ok_or_else(|| format!("Vote account, {}, does not exist", vote_address))?;
+    if vote_account.owner != solana_vote_program::id() {
+        return Err(format!("{} is not a vote account", vote_address).into());
+    }
+    let vote_state = solana_vote_program::vote_state::VoteState::deserialize(&vote_account.data)
+        .map_err(|err| format!("Invalid vote account {}: {}", vote_address, err))?;
+    let identity = vote_state.node_pubkey;
+
+    let epoch_schedule = rpc_client.get_epoch_schedule()?;
+    let stop_epoch = rpc_client.get_epoch_info()?.epoch.saturating_sub(1);
+    let start_epoch = stop_epoch.saturating_sub(epochs.saturating_sub(1));
+
+    // Identity fee rewards are taken from the block reward lots of the tracked identity account.
+    // Disposed block reward lots can only be attributed when a single validator identity is
+    // configured
+    let mut identity_lots = db
+        .get_account(identity, token)
+        .map(|account| account.lots)
+        .unwrap_or_default();
+    let validator_identities = db.get_validator_identities();
+    if validator_identities.len() == 1 && validator_identities[0].address == identity {
+        identity_lots.extend(
+            db.disposed_lots()
+                .into_iter()
+                .filter(|disposed_lot| disposed_lot.token.is_sol())
+                .map(|disposed_lot| disposed_lot.lot),
+        );
+    }
+    let mut identity_fee_rewards = BTreeMap::<Epoch, u64>::default();
+    for lot in identity_lots {
+        if let LotAcquistionKind::BlockReward { slot, .. } = lot.acquisition.kind {
+            *identity_fee_rewards
+                .entry(epoch_schedule.get_epoch(slot))
+                .or_default() += lot.amount;
+        }
+    }
 
-    let mut sheet = workbook.create_sheet(&match filter_by_year {
-        Some(year) => format!("Disposed in {}", year),
-        None => "Disposed".into(),
-    });
-    sheet.add_column(Column { width: 12. });
-    sheet.add_column(Column { width: 15. });
-    sheet.add_column(Column { width: 12. });
-    sheet.add_column(Column { width: 12. });
-    sheet.add_column(Column { width: 10. });
-    sheet.add_column(Column { width: 40. });
-    sheet.add_column(Column { width: 12. });
-    sheet.add_column(Column { width: 10. });
-    sheet.add_column(Column { width: 10. });
-    sheet.add_column(Column { width: 10. });
-    sheet.add_column(Column { width: 10. });
-    sheet.add_column(Column { width: 40. });
+    // Vote costs are the fee expenses recorded by `sys account sync` for the identity, which
+    // requires the identity to be a fee payer. They're dated at the sync that observed them, so
+    // fall in the epoch of that sync rather than the epochs they were incurred in
+    let mut identity_vote_costs = BTreeMap::<Epoch, u64>::default();
+    for disposed_lot in db.disposed_lots() {
+        if let LotDisposalKind::Fee {
+            payer: Some(payer),
+            slot: Some(slot),
+            ..
+        } = disposed_lot.kind
+        {
+            if payer == identity && disposed_lot.token.is_sol() {
+                *identity_vote_costs
+                    .entry(epoch_schedule.get_epoch(slot))
+                    .or_default() += disposed_lot.lot.amount;
+            }
+        }
+    }
+    let identity_is_fee_payer = db.get_fee_payers().contains(&identity);
+    let identity_rewards_tracked = validator_identities
+        .iter()
+        .any(|validator_identity| validator_identity.address == identity);
 
-    let mut disposed_lots = db.disposed_lots();
-    disposed_lots.sort_by_key(|lot| lot.when);
+    println!("Vote account: {}", vote_address);
+    println!("Identity: {}", identity);
+    println!("Current commission: {}%", vote_state.commission);
+    println!();
+    println!(
+        "Epoch  Credits  Commission  Commission earned  Identity fees  Vote costs at sync  Net ({})",
+        base_currency
+    );
 
-    if let Some(year) = filter_by_year {
-        // Exclude disposed lots that were neither acquired nor disposed of in the filter year
-        disposed_lots.retain(|disposed_lot| {
-            (disposed_lot.lot.acquisition.when.year() == year
-                && disposed_lot.lot.income(disposed_lot.token) > 0.)
-                || disposed_lot.when.year() == year
-        })
-    }
+    let mut total_commission_earned = 0;
+    let mut total_identity_fees = 0;
+    let mut total_vote_costs = 0;
+    let mut total_net_value = 0.;
+    for epoch in start_epoch..=stop_epoch {
+        let credits = vote_state
+            .epoch_credits
+            .iter()
+            .find(|(credits_epoch, ..)| *credits_epoch == epoch)
+            .map(|(_, credits, prev_credits)| credits.saturating_sub(*prev_credits))
+            .unwrap_or_default();
+
+        let inflation_reward = rpc_client
+            .get_inflation_reward(&[vote_address], Some(epoch))?
+            .pop()
+            .flatten();
+        let commission_earned = inflation_reward
+            .as_ref()
+            .map(|inflation_reward| inflation_reward.amount)
+            .unwrap_or_default();
+        let commission = inflation_reward
+            .as_ref()
+            .and_then(|inflation_reward| inflation_reward.commission)
+            .map(|commission| format!("{}%", commission))
+            .unwrap_or_else(|| "-".into());
+
+        let identity_fees = identity_fee_rewards
+            .get(&epoch)
+            .copied()
+            .unwrap_or_default();
+
+        let vote_costs = identity_vote_costs.get(&epoch).copied().unwrap_or_default();
+
+        // Price the epoch like its rewards, at the first block of the following epoch
+        let slot = rpc_client
+            .get_blocks_with_limit(epoch_schedule.get_first_slot_in_epoch(epoch + 1), 1)?
+            .first()
+            .copied()
+            .ok_or_else(|| format!("No blocks found after epoch {}", epoch))?;
+        let (_, _, price) = get_block_date_and_price(
+            rpc_client,
+            slot,
+            token,
+            price_precision,
+            base_currency,
+            reporting_timezone,
+        )
+        .await?;
+
+        let net_amount =
+            token.ui_amount(commission_earned + identity_fees) - token.ui_amount(vote_costs);
+        let net_value = net_amount * price.to_f64().unwrap();
+
+        println!(
+            "{:>5}  {:>7}  {:>10}  {:>17}  {:>13}  {:>18}  {}{}",
+            epoch,
+            credits,
+            commission,
+            format!("◎{:.4}", token.ui_amount(commission_earned)),
+            format!("◎{:.4}", token.ui_amount(identity_fees)),
+            format!("◎{:.4}", token.ui_amount(vote_costs)),
+            c,
+            net_value.separated_string_with_fixed_place(2),
+        );
+
+        total_commission_earned += commission_earned;
+        total_identity_fees += identity_fees;
+        total_vote_costs += vote_costs;
+        total_net_value += net_value;
+    }
+
+    println!();
+    println!(
+        "Commission earned: ◎{:.4}",
+        token.ui_amount(total_commission_earned)
+    );
+    println!(
+        "Identity fees: ◎{:.4}",
+        token.ui_amount(total_identity_fees)
+    );
+    println!("Vote costs: ◎{:.4}", token.ui_amount(total_vote_costs));
+    println!(
+        "Net: {}{}",
+        c,
+        total_net_value.separated_string_with_fixed_place(2)
+    );
+
+    println!();
+    if identity_is_fee_payer {
+        println!(
+            "Note: Vote costs are counted in the epoch of the `sys sync` that recorded them, \
+             which spans the epochs since the previous sync"
+        );
+    } else {
+        println!(
+            "Note: Vote costs are not tracked as {} is not a fee payer (see `sys account fee-payer`)",
+            identity
+        );
+    }
+    if !identity_rewards_tracked {
+        println!(
+            "Note: Identity fees are not tracked as block rewards of {} are not recorded \
+             (see `sys account validator-mode`)",
+            identity
+        );
+    }
+    Ok(())
+}
+
+async fn process_price_history(
+    db: &Db,
+    rpc_client: &RpcClient,
+    token: MaybeToken,
+    from: NaiveDate,
+    to: NaiveDate,
+    chart: bool,
+    csv: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if from > to {
+        return Err(format!("Invalid date range: {} is after {}", from, to).into());
+    }
+    let base_currency = db.get_base_currency();
+
+    let mut prices = vec![];
+    let mut when = from;
+    while when <= to {
+        let price = token
+            .get_historical_price(rpc_client, when, base_currency)
+            .await?;
+        println!("{} | {}{}", when, base_currency.symbol(), price.round_dp(4));
+        prices.push((when, price));
+        when = when.succ();
+    }
+
+    let (min_when, min_price) = prices.iter().min_by_key(|(_, price)| *price).unwrap();
+    let (max_when, max_price) = prices.iter().max_by_key(|(_, price)| *price).unwrap();
+    let average_price = prices.iter().map(|(_, price)| *price).sum::<Decimal>()
+        / Decimal::from_usize(prices.len()).unwrap();
+
+    println!();
+    if chart {
+        println!(
+            "{}",
+            format_sparkline(
+                &prices
+                    .iter()
+                    .map(|(_, price)| f64::try_from(*price).unwrap())
+                    .collect::<Vec<_>>()
+            )
+        );
+        println!();
+    }
+    println!(
+        "Min:     {}{} on {}",
+        base_currency.symbol(),
+        min_price.round_dp(4),
+        min_when
+    );
+    println!(
+        "Max:     {}{} on {}",
+        base_currency.symbol(),
+        max_price.round_dp(4),
+        max_when
+    );
+    println!(
+        "Average: {}{}",
+        base_currency.symbol(),
+        average_price.round_dp(4)
+    );
+
+    if let Some(csv) = csv {
+        let mut contents = format!("date,{} price ({})\n", token, base_currency);
+        for (when, price) in &prices {
+            contents += &format!("{},{}\n", when, price);
+        }
+        fs::write(&csv, contents)?;
+        println!("Wrote {}", csv);
+    }
+    Ok(())
+}
+
+// Returns the UTC time of a lot acquisition or disposal, if it can be determined
+fn get_lot_event_utc_time(
+    rpc_client: &RpcClient,
+    reporting_timezone: ReportingTimezone,
+    slot: Option<Slot>,
+    signature: Option<Signature>,
+    time: Option<NaiveDateTime>,
+) -> Result<Option<NaiveDateTime>, Box<dyn std::error::Error>> {
+    let slot = match (slot, signature) {
+        (Some(slot), _) => Some(slot),
+        (None, Some(signature)) => rpc_client
+            .get_signature_statuses_with_history(&[signature])?
+            .value
+            .get(0)
+            .and_then(|ts| ts.as_ref().map(|ts| ts.slot)),
+        (None, None) => None,
+    };
+
+    if let Some(block_time) = slot.and_then(|slot| rpc_client.get_block_time(slot).ok()) {
+        return Ok(Some(NaiveDateTime::from_timestamp(block_time, 0)));
+    }
+    Ok(time.and_then(|time| reporting_timezone.utc_datetime(time)))
+}
+
+fn get_lot_acquisition_utc_time(
+    rpc_client: &RpcClient,
+    reporting_timezone: ReportingTimezone,
+    acquisition: &LotAcquistion,
+) -> Result<Option<NaiveDateTime>, Box<dyn std::error::Error>> {
+    let (slot, signature) = match acquisition.kind {
+        LotAcquistionKind::EpochReward { slot, .. }
+        | LotAcquistionKind::BlockReward { slot, .. }
+        | LotAcquistionKind::Transaction { slot, .. } => (Some(slot), None),
+        LotAcquistionKind::Swap { signature, .. } => (None, Some(signature)),
+        LotAcquistionKind::Exchange { .. }
+        | LotAcquistionKind::NotAvailable
+        | LotAcquistionKind::Fiat => (None, None),
+    };
+    get_lot_event_utc_time(
+        rpc_client,
+        reporting_timezone,
+        slot,
+        signature,
+        acquisition.time,
+    )
+}
+
+// Reports the lots whose acquisition or disposal date differs between the current reporting
+// timezone and `new_reporting_timezone`. Existing lots are not modified
+fn process_db_timezone_migration_report(
+    db: &Db,
+    rpc_client: &RpcClient,
+    new_reporting_timezone: ReportingTimezone,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let reporting_timezone = db.get_reporting_timezone();
+    if reporting_timezone == new_reporting_timezone {
+        return Ok(());
+    }
+
+    let mut lots = db
+        .get_accounts()
+        .into_iter()
+        .flat_map(|account| account.lots.into_iter())
+        .chain(
+            db.open_orders(None, None)
+                .into_iter()
+                .flat_map(|open_order| open_order.lots.into_iter()),
+        )
+        .map(|lot| (lot, None))
+        .chain(
+            db.disposed_lots()
+                .into_iter()
+                .map(|disposed_lot| (disposed_lot.lot.clone(), Some(disposed_lot))),
+        )
+        .collect::<Vec<_>>();
+    lots.sort_by_key(|(lot, _)| lot.lot_number);
+
+    let mut changed_lots = 0;
+    let mut undetermined_lots = 0;
+
+    println!(
+        "Lots with dates that change from {} to {}:",
+        reporting_timezone, new_reporting_timezone
+    );
+    for (lot, disposed_lot) in lots {
+        let mut changes = vec![];
+
+        match get_lot_acquisition_utc_time(rpc_client, reporting_timezone, &lot.acquisition)? {
+            Some(utc_time) => {
+                let new_when = new_reporting_timezone.local_datetime(utc_time).date();
+                if new_when != lot.acquisition.when {
+                    changes.push(format!("acquired {} -> {}", lot.acquisition.when, new_when));
+                }
+            }
+            None => undetermined_lots += 1,
+        }
+
+        if let Some(disposed_lot) = disposed_lot {
+            let signature = match disposed_lot.kind {
+                LotDisposalKind::Swap { signature, .. } => Some(signature),
+                LotDisposalKind::Usd { .. }
+                | LotDisposalKind::Other { .. }
+                | LotDisposalKind::Fiat
+                | LotDisposalKind::Fee { .. } => None,
+            };
+            if let Some(utc_time) = get_lot_event_utc_time(
+                rpc_client,
+                reporting_timezone,
+                None,
+                signature,
+                disposed_lot.time,
+            )? {
+                let new_when = new_reporting_timezone.local_datetime(utc_time).date();
+                if new_when != disposed_lot.when {
+                    changes.push(format!("disposed {} -> {}", disposed_lot.when, new_when));
+                }
+            }
+        }
+
+        if !changes.is_empty() {
+            changed_lots += 1;
+            println!("{:>5}. {}", lot.lot_number, changes.join(", "));
+        }
+    }
+
+    if changed_lots == 0 {
+        println!("  None");
+    }
+    if undetermined_lots > 0 {
+        println!(
+            "Note: {} lots have no recorded block or time, their dates could not be checked",
+            undetermined_lots
+        );
+    }
+    println!("Existing lot dates are not modified");
+    Ok(())
+}
+
+async fn process_account_reprice(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    account_filter: Option<Pubkey>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    price_source: PriceSource,
+    confirm: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_currency = db.get_base_currency();
+    let reporting_timezone = db.get_reporting_timezone();
+    let c = base_currency.symbol();
+
+    let mut lot_prices = HashMap::new();
+    let mut total_income_change = 0.;
+    let mut total_basis_change = 0.;
+
+    // Income lots may still be held, be in an open order, or have been disposed of already
+    let mut lot_groups = vec![];
+    for account in db.get_accounts() {
+        if account_filter.map_or(true, |address| account.address == address) {
+            lot_groups.push((
+                format!(
+                    "{} ({}): {}",
+                    account.address, account.token, account.description
+                ),
+                account.token,
+                account.lots,
+            ));
+        }
+    }
+    for open_order in db.open_orders(None, None) {
+        if account_filter.map_or(true, |address| open_order.deposit_address == address) {
+            lot_groups.push((
+                format!(
+                    "{:?} {} order {} ({})",
+                    open_order.exchange, open_order.pair, open_order.order_id, open_order.token
+                ),
+                open_order.token,
+                open_order.lots,
+            ));
+        }
+    }
+    if account_filter.is_none() {
+        let mut disposed_lots = BTreeMap::<MaybeToken, Vec<Lot>>::new();
+        for disposed_lot in db.disposed_lots() {
+            disposed_lots
+                .entry(disposed_lot.token)
+                .or_default()
+                .push(disposed_lot.lot);
+        }
+        for (token, lots) in disposed_lots {
+            lot_groups.push((format!("Disposed {} lots", token), token, lots));
+        }
+    }
+
+    for (heading, token, lots) in lot_groups {
+        let mut lots = lots
+            .into_iter()
+            .filter(|lot| {
+                matches!(
+                    lot.acquisition.kind,
+                    LotAcquistionKind::EpochReward { .. }
+                        | LotAcquistionKind::BlockReward { .. }
+                        | LotAcquistionKind::NotAvailable
+                ) && from
+                    .map(|from| lot.acquisition.when >= from)
+                    .unwrap_or(true)
+                    && to.map(|to| lot.acquisition.when <= to).unwrap_or(true)
+            })
+            .collect::<Vec<_>>();
+        if lots.is_empty() {
+            continue;
+        }
+        lots.sort_by_key(|lot| lot.acquisition.when);
+
+        println!("{}", heading);
+        for lot in lots {
+            let utc_time = match price_source {
+                PriceSource::CoinGecko => None,
+                PriceSource::CoinGeckoIntraday => {
+                    get_lot_acquisition_utc_time(rpc_client, reporting_timezone, &lot.acquisition)?
+                }
+            };
+            let price = match utc_time {
+                Some(utc_time) => {
+                    token
+                        .get_historical_price_at(rpc_client, utc_time, base_currency)
+                        .await?
+                }
+                None => {
+                    token
+                        .get_historical_price(rpc_client, lot.acquisition.when, base_currency)
+                        .await?
+                }
+            };
+            // The acquisition time is only kept when the price is intraday
+            let time = utc_time.map(|utc_time| reporting_timezone.local_datetime(utc_time));
+
+            let old_price = lot.acquisition.price();
+            if price == old_price && time == lot.acquisition.time {
+                continue;
+            }
+
+            let ui_amount = Decimal::from_f64(token.ui_amount(lot.amount)).unwrap();
+            let old_basis = f64::try_from(old_price * ui_amount).unwrap();
+            let new_basis = f64::try_from(price * ui_amount).unwrap();
+            let old_income = lot.income(token);
+            let mut repriced_lot = lot.clone();
+            repriced_lot.acquisition = LotAcquistion::new_with_time(
+                lot.acquisition.when,
+                time,
+                price,
+                lot.acquisition.kind.clone(),
+            );
+            let new_income = repriced_lot.income(token);
+
+            println!(
+                "{:>5}. {} | {}{} | price: {c}{} -> {c}{} | income: {c}{} -> {c}{} | basis: {c}{} -> {c}{}",
+                lot.lot_number,
+                format_acquisition_when(&lot.acquisition),
+                token.symbol(),
+                token.ui_amount(lot.amount),
+                old_price,
+                price,
+                old_income.separated_string_with_fixed_place(2),
+                new_income.separated_string_with_fixed_place(2),
+                old_basis.separated_string_with_fixed_place(2),
+                new_basis.separated_string_with_fixed_place(2),
+                c = c,
+            );
+
+            total_income_change += new_income - old_income;
+            total_basis_change += new_basis - old_basis;
+            lot_prices.insert(lot.lot_number, (price, time));
+        }
+        println!();
+    }
+
+    if lot_prices.is_empty() {
+        println!("No lot prices changed");
+        return Ok(());
+    }
+
+    println!(
+        "{} lots repriced from {}. Income change: {c}{}, basis change: {c}{}",
+        lot_prices.len(),
+        price_source,
+        total_income_change.separated_string_with_fixed_place(2),
+        total_basis_change.separated_string_with_fixed_place(2),
+        c = c,
+    );
+    if confirm {
+        db.reprice_lots(&lot_prices)?;
+        println!("Lot prices updated");
+    } else {
+        println!("Add --confirm to update the lot prices");
+    }
+    Ok(())
+}
+
+async fn process_account_xls(
+    db: &Db,
+    outfile: &str,
+    filter_by_year: Option<i32>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use simple_excel_writer::*;
+
+    let base_currency = db.get_base_currency();
+    let income_header = format!("Income ({})", base_currency);
+    let acquisition_price_header = format!("Acq. Price ({})", base_currency);
+    let cap_gain_header = format!("Cap Gain ({})", base_currency);
+    let sale_price_header = format!("Sale Price ({})", base_currency);
+    let fee_header = format!("Fee ({})", base_currency);
+
+    let mut workbook = Workbook::create(outfile);
+
+    let mut sheet = workbook.create_sheet(&match filter_by_year {
+        Some(year) => format!("Disposed in {}", year),
+        None => "Disposed".into(),
+    });
+    sheet.add_column(Column { width: 12. });
+    sheet.add_column(Column { width: 15. });
+    sheet.add_column(Column { width: 12. });
+    sheet.add_column(Column { width: 12. });
+    sheet.add_column(Column { width: 10. });
+    sheet.add_column(Column { width: 40. });
+    sheet.add_column(Column { width: 12. });
+    sheet.add_column(Column { width: 10. });
+    sheet.add_column(Column { width: 10. });
+    sheet.add_column(Column { width: 10. });
+    sheet.add_column(Column { width: 10. });
+    sheet.add_column(Column { width: 40. });
+
+    let mut disposed_lots = db.disposed_lots();
+    disposed_lots.sort_by_key(|lot| lot.when);
+
+    if let Some(year) = filter_by_year {
+        // Exclude disposed lots that were neither acquired nor disposed of in the filter year
+        disposed_lots.retain(|disposed_lot| {
+            (disposed_lot.lot.acquisition.when.year() == year
+                && disposed_lot.lot.income(disposed_lot.token) > 0.)
+                || disposed_lot.when.year() == year
+        })
+    }
 
     workbook.write_sheet(&mut sheet, |sheet_writer| {
         sheet_writer.append_row(row![
             "Token",
             "Amount",
-            "Income (USD)",
+            income_header.as_str(),
             "Acq. Date",
-            "Acq. Price (USD)",
+            acquisition_price_header.as_str(),
             "Acquisition Description",
-            "Cap Gain (USD)",
+            cap_gain_header.as_str(),
             "Cap Gain Type",
             "Sale Date",
-            "Sale Price (USD)",
-            "Fee (USD)",
+            sale_price_header.as_str(),
+            fee_header.as_str(),
             "Sale Description"
         ])?;
 
         for disposed_lot in disposed_lots {
             let long_term_cap_gain =
-                is_long_term_cap_gain(disposed_lot.lot.acquisition.when, Some(disposed_lot.when));
+                is_long_term_cap_gain(disposed_lot.lot.acquisition.when, disposed_lot.when);
 
             let mut income = disposed_lot.lot.income(disposed_lot.token);
             if let Some(year) = filter_by_year {
@@ -2265,20 +3035,23 @@ async fn process_account_xls(
                 disposed_lot.token.to_string(),
                 disposed_lot.token.ui_amount(disposed_lot.lot.amount),
                 income,
-                disposed_lot.lot.acquisition.when.to_string(),
+                format_acquisition_when(&disposed_lot.lot.acquisition),
                 disposed_lot.lot.acquisition.price().to_string(),
                 disposed_lot.lot.acquisition.kind.to_string(),
                 disposed_lot
                     .lot
                     .cap_gain(disposed_lot.token, disposed_lot.price()),
                 if long_term_cap_gain { "Long" } else { "Short" },
-                disposed_lot.when.to_string(),
+                disposed_lot
+                    .time
+                    .map(|time| time.to_string())
+                    .unwrap_or_else(|| disposed_lot.when.to_string()),
                 disposed_lot.price().to_string(),
                 disposed_lot
                     .kind
                     .fee()
                     .map(|(amount, currency)| {
-                        assert_eq!(currency, "USD");
+                        assert_eq!(currency, base_currency.name());
                         *amount
                     })
                     .unwrap_or_default(),
@@ -2314,7 +3087,7 @@ async fn process_account_xls(
                     R::Text(account.token.to_string()),
                     R::Number(account.token.ui_amount(lot.amount)),
                     R::Number(lot.income(account.token)),
-                    R::Text(lot.acquisition.when.to_string()),
+                    R::Text(format_acquisition_when(&lot.acquisition)),
                     R::Text(lot.acquisition.price().to_string()),
                     R::Text(lot.acquisition.kind.to_string()),
                     R::Text(account.description.clone()),
@@ -2339,7 +3112,7 @@ async fn process_account_xls(
                     R::Text(open_order.token.to_string()),
                     R::Number(open_order.token.ui_amount(lot.amount)),
                     R::Number(lot.income(open_order.token)),
-                    R::Text(lot.acquisition.when.to_string()),
+                    R::Text(format_acquisition_when(&lot.acquisition)),
                     R::Text(lot.acquisition.price().to_string()),
                     R::Text(lot.acquisition.kind.to_string()),
                     R::Text(format!(
@@ -2377,9 +3150,9 @@ async fn process_account_xls(
             sheet_writer.append_row(row![
                 "Token",
                 "Amount",
-                "Income (USD)",
+                income_header.as_str(),
                 "Acq. Date",
-                "Acq. Price (USD)",
+                acquisition_price_header.as_str(),
                 "Acquisition Description",
                 "Account Description",
                 "Account Address"
@@ -2416,23 +3189,11 @@ async fn process_account_merge<T: Signers>(
 ) -> Result<(), Box<dyn std::error::Error>> {
     let token = MaybeToken::SOL(); // TODO: Support merging tokens one day
 
-    let (recent_blockhash, last_valid_block_height) =
-        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-
     let from_account = rpc_client
         .get_account_with_commitment(&from_address, rpc_client.commitment())?
         .value
         .ok_or_else(|| format!("From account, {}, does not exist", from_address))?;
 
-    let authority_account = if from_address == authority_address {
-        from_account.clone()
-    } else {
-        rpc_client
-            .get_account_with_commitment(&authority_address, rpc_client.commitment())?
-            .value
-            .ok_or_else(|| format!("Authority account, {}, does not exist", authority_address))?
-    };
-
     let instructions = if from_account.owner == solana_stake_program::id() {
         solana_stake_program::stake_instruction::merge(
             &into_address,
@@ -2449,41 +3210,35 @@ async fn process_account_merge<T: Signers>(
         println!("Authority address: {}", authority_address);
     }
 
-    let mut message = Message::new(&instructions, Some(&authority_address));
-    message.recent_blockhash = recent_blockhash;
-    if rpc_client.get_fee_for_message(&message)? > authority_account.lamports {
-        return Err("Insufficient funds for transaction fee".into());
-    }
-
-    let mut transaction = Transaction::new_unsigned(message);
-    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
-    if simulation_result.err.is_some() {
-        return Err(format!("Simulation failure: {:?}", simulation_result).into());
-    }
-
-    transaction.try_sign(&signers, recent_blockhash)?;
-    let signature = transaction.signatures[0];
-    println!("Transaction signature: {}", signature);
-
-    db.record_transfer(
-        signature,
-        last_valid_block_height,
-        None,
-        from_address,
-        token,
-        into_address,
-        token,
-        LotSelectionMethod::default(),
-        None,
-    )?;
-
-    if !send_transaction_until_expired(rpc_client, &transaction, last_valid_block_height) {
-        db.cancel_transfer(signature)?;
-        return Err("Merge failed".into());
-    }
-    let when = get_signature_date(rpc_client, signature).await?;
-    db.confirm_transfer(signature, when)?;
-    db.remove_account(from_address, token)?;
+    TransactionPipeline::new(rpc_client, db)
+        .execute(
+            db,
+            TransactionPayload::Instructions {
+                instructions: &instructions,
+                payer: &authority_address,
+            },
+            |transaction, recent_blockhash| transaction.try_sign(&signers, recent_blockhash),
+            Some(Pending::new(
+                PendingKind::Transfer,
+                |db, signature, last_valid_block_height| {
+                    db.record_transfer(
+                        signature,
+                        last_valid_block_height,
+                        None,
+                        from_address,
+                        token,
+                        into_address,
+                        token,
+                        LotSelectionMethod::default(),
+                        None,
+                    )?;
+                    // The merged account is closed
+                    db.remove_account_on_transfer_confirmation(signature)
+                },
+            )),
+        )
+        .await
+        .map_err(|err| format!("Merge failed: {}", err))?;
     Ok(())
 }
 
@@ -2497,13 +3252,11 @@ async fn process_account_sweep<T: Signers>(
     from_authority_address: Pubkey,
     signers: T,
     to_address: Option<Pubkey>,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
     notifier: &Notifier,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let token = MaybeToken::SOL();
-
-    let (recent_blockhash, last_valid_block_height) =
-        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-    let fee_calculator = get_deprecated_fee_calculator(rpc_client)?;
+    let transaction_pipeline = TransactionPipeline::new(rpc_client, db);
 
     let from_account = rpc_client
         .get_account_with_commitment(&from_address, rpc_client.commitment())?
@@ -2546,22 +3299,19 @@ async fn process_account_sweep<T: Signers>(
             .ok_or_else(|| format!("Account {} ({}) does not exist", to_address, token))?;
         (to_address, None)
     } else {
+        if multisig_proposal().is_some() {
+            return Err("A multisig sweep requires a destination account".into());
+        }
         let transitory_stake_account = Keypair::new();
 
         let sweep_stake_account = db
             .get_sweep_stake_account()
             .ok_or("Sweep stake account not configured")?;
-        let sweep_stake_authority_keypair = read_keypair_file(&sweep_stake_account.stake_authority)
-            .map_err(|err| {
-                format!(
-                    "Failed to read {}: {}",
-                    sweep_stake_account.stake_authority.display(),
-                    err
-                )
-            })?;
+        let (sweep_stake_authority_signer, sweep_stake_authority_address) =
+            signer_from_uri(&sweep_stake_account.stake_authority, wallet_manager)?;
 
         num_transaction_signatures += 1; // transitory_stake_account
-        if from_authority_address != sweep_stake_authority_keypair.pubkey() {
+        if from_authority_address != sweep_stake_authority_address {
             num_transaction_signatures += 1;
         }
 
@@ -2569,27 +3319,26 @@ async fn process_account_sweep<T: Signers>(
             transitory_stake_account.pubkey(),
             Some((
                 transitory_stake_account,
-                sweep_stake_authority_keypair,
+                sweep_stake_authority_signer,
                 sweep_stake_account.address,
             )),
         )
     };
 
-    if authority_account.lamports
-        < num_transaction_signatures * fee_calculator.lamports_per_signature
-    {
+    let max_fee = transaction_pipeline.get_max_fee(num_transaction_signatures)?;
+    if authority_account.lamports < max_fee {
         return Err(format!(
             "Authority has insufficient funds for the transaction fee of {}",
-            token.ui_amount(num_transaction_signatures * fee_calculator.lamports_per_signature)
+            token.ui_amount(max_fee)
         )
         .into());
     }
 
     let (mut instructions, sweep_amount) = if from_account.owner == system_program::id() {
         let lamports = if from_address == from_authority_address {
-            from_tracked_account.last_update_balance.saturating_sub(
-                num_transaction_signatures * fee_calculator.lamports_per_signature + retain_amount,
-            )
+            from_tracked_account
+                .last_update_balance
+                .saturating_sub(max_fee + retain_amount)
         } else {
             from_tracked_account
                 .last_update_balance
@@ -2669,7 +3418,7 @@ async fn process_account_sweep<T: Signers>(
 
     let msg = if let Some((
         transitory_stake_account,
-        sweep_stake_authority_keypair,
+        sweep_stake_authority_signer,
         sweep_stake_address,
     )) = via_transitory_stake.as_ref()
     {
@@ -2678,7 +3427,7 @@ async fn process_account_sweep<T: Signers>(
         let (sweep_stake_authorized, sweep_stake_vote_account_address) =
             rpc_client_utils::get_stake_authorized(rpc_client, *sweep_stake_address)?;
 
-        if sweep_stake_authorized.staker != sweep_stake_authority_keypair.pubkey() {
+        if sweep_stake_authorized.staker != sweep_stake_authority_signer.pubkey() {
             return Err("Stake authority mismatch".into());
         }
 
@@ -2698,7 +3447,7 @@ async fn process_account_sweep<T: Signers>(
             ),
             solana_stake_program::stake_instruction::delegate_stake(
                 &transitory_stake_account.pubkey(),
-                &sweep_stake_authority_keypair.pubkey(),
+                &sweep_stake_authority_signer.pubkey(),
                 &sweep_stake_vote_account_address,
             ),
         ]);
@@ -2719,59 +3468,55 @@ async fn process_account_sweep<T: Signers>(
         )
     };
 
-    let mut message = Message::new(&instructions, Some(&from_authority_address));
-    message.recent_blockhash = recent_blockhash;
-    assert_eq!(
-        rpc_client.get_fee_for_message(&message)?,
-        num_transaction_signatures * fee_calculator.lamports_per_signature
-    );
-
-    let mut transaction = Transaction::new_unsigned(message);
-    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
-    if simulation_result.err.is_some() {
-        return Err(format!("Simulation failure: {:?}", simulation_result).into());
-    }
-
-    transaction.partial_sign(&signers, recent_blockhash);
-    if let Some((transitory_stake_account, sweep_stake_authority_keypair, ..)) =
-        via_transitory_stake.as_ref()
-    {
-        transaction.try_sign(
-            //&[&transitory_stake_account, &sweep_stake_authority_keypair],
-            &[transitory_stake_account, sweep_stake_authority_keypair],
-            recent_blockhash,
-        )?;
-    }
-
-    let signature = transaction.signatures[0];
-    println!("Transaction signature: {}", signature);
-
     let epoch = rpc_client.get_epoch_info()?.epoch;
     if let Some((transitory_stake_account, ..)) = via_transitory_stake.as_ref() {
         db.add_transitory_sweep_stake_address(transitory_stake_account.pubkey(), epoch)?;
     }
-    db.record_transfer(
-        signature,
-        last_valid_block_height,
-        Some(sweep_amount),
-        from_address,
-        token,
-        to_address,
-        token,
-        LotSelectionMethod::default(),
-        None,
-    )?;
 
-    if !send_transaction_until_expired(rpc_client, &transaction, last_valid_block_height) {
-        db.cancel_transfer(signature)?;
+    let result = transaction_pipeline
+        .execute(
+            db,
+            TransactionPayload::Instructions {
+                instructions: &instructions,
+                payer: &from_authority_address,
+            },
+            |transaction, recent_blockhash| {
+                transaction.try_partial_sign(&signers, recent_blockhash)?;
+                if let Some((transitory_stake_account, sweep_stake_authority_signer, ..)) =
+                    via_transitory_stake.as_ref()
+                {
+                    let signers: Vec<&dyn Signer> = vec![
+                        transitory_stake_account,
+                        sweep_stake_authority_signer.as_ref(),
+                    ];
+                    transaction.try_sign(&signers, recent_blockhash)?;
+                }
+                Ok(())
+            },
+            Some(Pending::new(
+                PendingKind::Transfer,
+                |db, signature, last_valid_block_height| {
+                    db.record_transfer(
+                        signature,
+                        last_valid_block_height,
+                        Some(sweep_amount),
+                        from_address,
+                        token,
+                        to_address,
+                        token,
+                        LotSelectionMethod::default(),
+                        None,
+                    )
+                },
+            )),
+        )
+        .await;
+    if let Err(err) = result {
         if let Some((transitory_stake_account, ..)) = via_transitory_stake.as_ref() {
             db.remove_transitory_sweep_stake_address(transitory_stake_account.pubkey())?;
         }
-        return Err("Sweep failed".into());
+        return Err(format!("Sweep failed: {}", err).into());
     }
-    println!("Confirming sweep: {}", signature);
-    let when = get_signature_date(rpc_client, signature).await?;
-    db.confirm_transfer(signature, when)?;
 
     notifier.send(&msg).await;
     println!("{}", msg);
@@ -2792,678 +3537,3296 @@ async fn process_account_split<T: Signers>(
     into_keypair: Option<Keypair>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // TODO: Support splitting two system accounts? Tokens? Otherwise at least error cleanly when it's attempted
-    let token = MaybeToken::SOL(); // TODO: Support splitting tokens one day
+    let token = MaybeToken::SOL(); // Tokens move between owners with `sys account transfer` instead
 
-    let (recent_blockhash, last_valid_block_height) =
-        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-
-    let into_keypair = into_keypair.unwrap_or_else(Keypair::new);
-    if db.get_account(into_keypair.pubkey(), token).is_some() {
-        return Err(format!(
-            "Account {} ({}) already exists",
-            into_keypair.pubkey(),
-            token
-        )
-        .into());
+    // A multisig can't sign for a new keypair, so its split account is derived from the
+    // authority with a seed instead
+    let (into_keypair, into_seed) = if multisig_proposal().is_some() {
+        if into_keypair.is_some() {
+            return Err("--into cannot be used with --multisig-proposal".into());
+        }
+        (None, Some(format!("sys-split-{}", Utc::now().timestamp())))
+    } else {
+        (Some(into_keypair.unwrap_or_else(Keypair::new)), None)
+    };
+    let into_address = match (&into_keypair, &into_seed) {
+        (Some(into_keypair), _) => into_keypair.pubkey(),
+        (None, Some(into_seed)) => {
+            Pubkey::create_with_seed(&authority_address, into_seed, &solana_stake_program::id())?
+        }
+        (None, None) => unreachable!(),
+    };
+    if db.get_account(into_address, token).is_some() {
+        return Err(format!("Account {} ({}) already exists", into_address, token).into());
     }
 
-    let instructions = solana_stake_program::stake_instruction::split(
-        &from_address,
-        &authority_address,
-        amount,
-        &into_keypair.pubkey(),
-    );
-
-    let message = Message::new(&instructions, Some(&authority_address));
+    let instructions = match into_seed {
+        Some(ref into_seed) => solana_stake_program::stake_instruction::split_with_seed(
+            &from_address,
+            &authority_address,
+            amount,
+            &into_address,
+            &authority_address,
+            into_seed,
+        ),
+        None => solana_stake_program::stake_instruction::split(
+            &from_address,
+            &authority_address,
+            amount,
+            &into_address,
+        ),
+    };
 
-    let mut transaction = Transaction::new_unsigned(message);
-    transaction.message.recent_blockhash = recent_blockhash;
-    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
-    if simulation_result.err.is_some() {
-        return Err(format!("Simulation failure: {:?}", simulation_result).into());
-    }
+    let transaction_pipeline = TransactionPipeline::new(rpc_client, db);
+    let payload = TransactionPayload::Instructions {
+        instructions: &instructions,
+        payer: &authority_address,
+    };
+    // Simulate before tracking the new account
+    transaction_pipeline.prepare(&payload)?;
 
     println!(
         "Splitting {} from {} into {}",
         token.ui_amount(amount),
         from_address,
-        into_keypair.pubkey(),
+        into_address,
     );
 
-    transaction.partial_sign(&signers, recent_blockhash);
-    transaction.try_sign(&[&into_keypair], recent_blockhash)?;
-
-    let signature = transaction.signatures[0];
-    println!("Transaction signature: {}", signature);
-
     let epoch = rpc_client.get_epoch_info()?.epoch;
     db.add_account(TrackedAccount {
-        address: into_keypair.pubkey(),
+        address: into_address,
         token,
         description,
         last_update_epoch: epoch.saturating_sub(1),
         last_update_balance: 0,
         lots: vec![],
         no_sync: None,
+        last_update_signature: None,
+        last_stake_change_signature: None,
+        token_account: None,
     })?;
-    db.record_transfer(
-        signature,
-        last_valid_block_height,
-        Some(amount),
-        from_address,
-        token,
-        into_keypair.pubkey(),
-        token,
-        lot_selection_method,
-        lot_numbers,
-    )?;
 
-    if !send_transaction_until_expired(rpc_client, &transaction, last_valid_block_height) {
-        db.cancel_transfer(signature)?;
-        db.remove_account(into_keypair.pubkey(), MaybeToken::SOL())?;
-        return Err("Split failed".into());
+    let result = transaction_pipeline
+        .execute(
+            db,
+            payload,
+            |transaction, recent_blockhash| {
+                transaction.try_partial_sign(&signers, recent_blockhash)?;
+                match into_keypair {
+                    Some(ref into_keypair) => {
+                        transaction.try_sign(&[into_keypair], recent_blockhash)
+                    }
+                    None => transaction.try_sign(&signers, recent_blockhash),
+                }
+            },
+            Some(Pending::new(
+                PendingKind::Transfer,
+                |db, signature, last_valid_block_height| {
+                    db.record_transfer(
+                        signature,
+                        last_valid_block_height,
+                        Some(amount),
+                        from_address,
+                        token,
+                        into_address,
+                        token,
+                        lot_selection_method,
+                        lot_numbers.clone(),
+                    )
+                },
+            )),
+        )
+        .await;
+    match result {
+        Ok(Some(signature)) => {
+            println!("Split confirmed: {}", signature);
+            Ok(())
+        }
+        Ok(None) => Ok(()),
+        Err(err) => {
+            db.remove_account(into_address, MaybeToken::SOL())?;
+            Err(format!("Split failed: {}", err).into())
+        }
     }
-    println!("Split confirmed: {}", signature);
-    let when = get_signature_date(rpc_client, signature).await?;
-    db.confirm_transfer(signature, when)?;
-    Ok(())
 }
 
-async fn process_account_sync(
+#[allow(clippy::too_many_arguments)]
+async fn process_account_transfer<T: Signers>(
     db: &mut Db,
     rpc_client: &RpcClient,
-    address: Option<Pubkey>,
-    notifier: &Notifier,
+    token: Token,
+    from_address: Pubkey,
+    to_address: Pubkey,
+    amount: Option<u64>, // None = all
+    lot_selection_method: LotSelectionMethod,
+    lot_numbers: Option<HashSet<usize>>,
+    authority_address: Pubkey,
+    signers: T,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    process_account_sync_pending_transfers(db, rpc_client).await?;
-    process_account_sync_sweep(db, rpc_client, notifier).await?;
-
-    let mut accounts = match address {
-        Some(address) => {
-            // sync all tokens for the given address...
-            let accounts = db.get_account_tokens(address);
-            if accounts.is_empty() {
-                return Err(format!("{} does not exist", address).into());
-            }
-            accounts
-        }
-        None => db.get_accounts(),
+    let maybe_token = MaybeToken::from(token);
+    let from_account = db
+        .get_account(from_address, maybe_token)
+        .ok_or_else(|| format!("Account {} ({}) does not exist", from_address, token))?;
+    if from_address == to_address {
+        return Err("Source and destination accounts are the same".into());
     }
-    .into_iter()
-    .filter(|account| !account.no_sync.unwrap_or_default())
-    .collect::<Vec<_>>();
-
-    let current_sol_price = MaybeToken::SOL().get_current_price(rpc_client).await?;
-
-    let addresses: Vec<Pubkey> = accounts
-        .iter()
-        .map(|TrackedAccount { address, .. }| *address)
-        .collect::<Vec<_>>();
 
-    let epoch_info = rpc_client.get_epoch_info()?;
-    let stop_epoch = epoch_info.epoch.saturating_sub(1);
-
-    let start_epoch = accounts
-        .iter()
-        .map(
-            |TrackedAccount {
-                 last_update_epoch, ..
-             }| last_update_epoch,
+    let amount = amount.unwrap_or(from_account.last_update_balance);
+    if amount == 0 {
+        return Err("Nothing to transfer".into());
+    }
+    if from_account.last_update_balance < amount {
+        return Err(format!(
+            "Insufficient {} balance in {}. Tracked balance is {}",
+            token,
+            from_address,
+            token.ui_amount(from_account.last_update_balance)
         )
-        .min()
-        .unwrap_or(&stop_epoch)
-        + 1;
-
-    if start_epoch > stop_epoch {
-        println!("Processed up to epoch {}", stop_epoch);
-        return Ok(());
+        .into());
     }
 
-    // Look for inflationary rewards
-    for epoch in start_epoch..=stop_epoch {
-        let msg = format!("Processing epoch: {}", epoch);
-        notifier.send(&msg).await;
-        println!("{}", msg);
-
-        let inflation_rewards = rpc_client.get_inflation_reward(&addresses, Some(epoch))?;
-
-        for (inflation_reward, address, mut account) in
-            itertools::izip!(inflation_rewards, addresses.iter(), accounts.iter_mut(),)
-        {
-            assert_eq!(*address, account.address);
-            if account.last_update_epoch >= epoch {
-                continue;
-            }
-
-            if let Some(inflation_reward) = inflation_reward {
-                assert!(!account.token.is_token()); // Only SOL accounts can receive inflationary rewards
+    let to_account = db.get_account(to_address, maybe_token);
+    let to_balance_address = match to_account {
+        Some(ref to_account) => to_account.balance_address(),
+        None => token.ata(&to_address),
+    };
 
-                account.last_update_balance += inflation_reward.amount;
+    let mut instructions = vec![];
+    if rpc_client
+        .get_account_with_commitment(&to_balance_address, rpc_client.commitment())?
+        .value
+        .is_none()
+    {
+        if to_balance_address != token.ata(&to_address) {
+            return Err(format!("Token account {} does not exist", to_balance_address).into());
+        }
+        instructions.push(token.create_ata_instruction(&authority_address, &to_address));
+    }
+    instructions.push(token.transfer_checked_instruction(
+        &from_account.balance_address(),
+        &to_balance_address,
+        &authority_address,
+        amount,
+    ));
 
-                let slot = inflation_reward.effective_slot;
-                let (when, price) =
-                    get_block_date_and_price(rpc_client, slot, account.token).await?;
+    let transaction_pipeline = TransactionPipeline::new(rpc_client, db);
+    let payload = TransactionPayload::Instructions {
+        instructions: &instructions,
+        payer: &authority_address,
+    };
+    // Simulate before tracking the recipient
+    transaction_pipeline.prepare(&payload)?;
 
-                let lot = Lot {
-                    lot_number: db.next_lot_number(),
-                    acquisition: LotAcquistion::new(
-                        when,
-                        price,
-                        LotAcquistionKind::EpochReward { epoch, slot },
-                    ),
-                    amount: inflation_reward.amount,
-                };
+    println!(
+        "Transferring {}{} from {} to {}",
+        token.symbol(),
+        token.ui_amount(amount),
+        from_address,
+        to_address,
+    );
 
-                let msg = format!("{}: {}", account.address, account.description);
-                notifier.send(&msg).await;
-                println!("{}", msg);
+    if to_account.is_none() {
+        let epoch = rpc_client.get_epoch_info()?.epoch;
+        db.add_account(TrackedAccount {
+            address: to_address,
+            token: maybe_token,
+            description: format!("Transfer from {}", from_account.description),
+            last_update_epoch: epoch.saturating_sub(1),
+            last_update_balance: 0,
+            lots: vec![],
+            no_sync: None,
+            last_update_signature: None,
+            last_stake_change_signature: None,
+            token_account: None,
+        })?;
+    }
 
-                println_lot(
-                    account.token,
-                    &lot,
-                    current_sol_price,
-                    None,
-                    &mut 0.,
-                    &mut 0.,
-                    &mut false,
-                    &mut 0.,
-                    Some(notifier),
-                )
-                .await;
-                account.lots.push(lot);
+    let result = transaction_pipeline
+        .execute(
+            db,
+            payload,
+            |transaction, recent_blockhash| transaction.try_sign(&signers, recent_blockhash),
+            Some(Pending::new(
+                PendingKind::Transfer,
+                |db, signature, last_valid_block_height| {
+                    db.record_transfer(
+                        signature,
+                        last_valid_block_height,
+                        Some(amount),
+                        from_address,
+                        maybe_token,
+                        to_address,
+                        maybe_token,
+                        lot_selection_method,
+                        lot_numbers.clone(),
+                    )
+                },
+            )),
+        )
+        .await;
+    match result {
+        Ok(Some(signature)) => {
+            println!("Transfer confirmed: {}", signature);
+            Ok(())
+        }
+        Ok(None) => Ok(()),
+        Err(err) => {
+            if to_account.is_none() {
+                db.remove_account(to_address, maybe_token)?;
             }
+            Err(format!("Transfer failed: {}", err).into())
         }
     }
+}
 
-    // Look for unexpected balance changes (such as transaction and rent rewards)
-    for mut account in accounts.iter_mut() {
-        account.last_update_epoch = stop_epoch;
-
-        let current_balance = account.token.balance(rpc_client, &account.address)?;
-        if current_balance < account.last_update_balance {
-            println!(
-                "\nWarning: {} ({}) balance is less than expected. Actual: {}{}, expected: {}{}\n",
-                account.address,
-                account.token,
-                account.token.symbol(),
-                account.token.ui_amount(current_balance),
-                account.token.symbol(),
-                account.token.ui_amount(account.last_update_balance)
-            );
-        } else if current_balance > account.last_update_balance + account.token.amount(0.005) {
-            let slot = epoch_info.absolute_slot;
-            let current_token_price = account.token.get_current_price(rpc_client).await?;
-            let (when, decimal_price) =
-                get_block_date_and_price(rpc_client, slot, account.token).await?;
-            let amount = current_balance - account.last_update_balance;
-
-            let lot = Lot {
-                lot_number: db.next_lot_number(),
-                acquisition: LotAcquistion::new(
-                    when,
-                    decimal_price,
-                    LotAcquistionKind::NotAvailable,
-                ),
-                amount,
-            };
+async fn process_account_stake_delegate<T: Signers>(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    stake_address: Pubkey,
+    vote_address: Pubkey,
+    authority_address: Pubkey,
+    signers: T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let token = MaybeToken::SOL();
+    if db.get_account(stake_address, token).is_none() {
+        return Err(format!("Account {} ({}) does not exist", stake_address, token).into());
+    }
 
-            let msg = format!(
-                "{} ({}): {}",
-                account.address, account.token, account.description
-            );
-            notifier.send(&msg).await;
-            println!("{}", msg);
+    let instructions = vec![solana_stake_program::stake_instruction::delegate_stake(
+        &stake_address,
+        &authority_address,
+        &vote_address,
+    )];
 
-            println_lot(
-                account.token,
-                &lot,
-                current_token_price,
-                None,
-                &mut 0.,
-                &mut 0.,
-                &mut false,
-                &mut 0.,
-                Some(notifier),
-            )
-            .await;
-            account.lots.push(lot);
-            account.last_update_balance = current_balance;
-        }
+    println!("Delegating {} to {}", stake_address, vote_address);
 
-        db.update_account(account.clone())?;
+    let signature = TransactionPipeline::new(rpc_client, db)
+        .execute(
+            db,
+            TransactionPayload::Instructions {
+                instructions: &instructions,
+                payer: &authority_address,
+            },
+            |transaction, recent_blockhash| transaction.try_sign(&signers, recent_blockhash),
+            None,
+        )
+        .await
+        .map_err(|err| format!("Delegation failed: {}", err))?;
+    if let Some(signature) = signature {
+        println!("Delegation confirmed: {}", signature);
+        db.record_stake_change_signature(signature, db.get_reporting_timezone().today())?;
     }
-
+    db.remove_deactivating_stake(stake_address)?;
     Ok(())
 }
 
-#[allow(clippy::too_many_arguments)]
-async fn process_account_wrap<T: Signers>(
+async fn process_account_stake_deactivate<T: Signers>(
     db: &mut Db,
     rpc_client: &RpcClient,
-    address: Pubkey,
-    amount: u64,
-    lot_selection_method: LotSelectionMethod,
-    lot_numbers: Option<HashSet<usize>>,
+    stake_address: Pubkey,
     authority_address: Pubkey,
     signers: T,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let sol = MaybeToken::SOL();
-    let wsol = Token::wSOL;
-    let wsol_address = wsol.ata(&address);
-
-    let _from_account = db
-        .get_account(address, sol)
-        .ok_or_else(|| format!("SOL account does not exist for {}", address))?;
-
-    let _to_account = db
-        .get_account(address, wsol.into())
-        .ok_or_else(|| format!("Wrapped SOL account does not exist for {}", address))?;
-
-    let (recent_blockhash, last_valid_block_height) =
-        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-
-    let mut instructions = vec![];
-
-    // TODO: replace the following block with
-    // `spl_associated_token_account::instruction::create_associated_token_account_idempotent()`
-    // when it ships to mainnet
-    if rpc_client
-        .get_account_with_commitment(&wsol_address, rpc_client.commitment())?
-        .value
-        .is_none()
-    {
-        instructions.push(
-            spl_associated_token_account::create_associated_token_account(
-                &authority_address,
-                &address,
-                &wsol.mint(),
-            ),
-        );
-    }
-    instructions.extend([
-        system_instruction::transfer(&address, &wsol_address, amount),
-        spl_token::instruction::sync_native(&spl_token::id(), &wsol_address).unwrap(),
-    ]);
-
-    let message = Message::new(&instructions, Some(&authority_address));
-
-    let mut transaction = Transaction::new_unsigned(message);
-    transaction.message.recent_blockhash = recent_blockhash;
-    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
-    if simulation_result.err.is_some() {
-        return Err(format!("Simulation failure: {:?}", simulation_result).into());
+    let token = MaybeToken::SOL();
+    if db.get_account(stake_address, token).is_none() {
+        return Err(format!("Account {} ({}) does not exist", stake_address, token).into());
     }
 
-    println!("Wrapping {} for {}", wsol.ui_amount(amount), address);
-
-    transaction.try_sign(&signers, recent_blockhash)?;
-
-    let signature = transaction.signatures[0];
-    println!("Transaction signature: {}", signature);
+    let instructions = vec![solana_stake_program::stake_instruction::deactivate_stake(
+        &stake_address,
+        &authority_address,
+    )];
 
-    db.record_transfer(
-        signature,
-        last_valid_block_height,
-        Some(amount),
-        address,
-        sol,
-        address,
-        wsol.into(),
-        lot_selection_method,
-        lot_numbers,
-    )?;
+    println!("Deactivating {}", stake_address);
 
-    if !send_transaction_until_expired(rpc_client, &transaction, last_valid_block_height) {
-        db.cancel_transfer(signature)?;
-        return Err("Wrap failed".into());
+    let signature = TransactionPipeline::new(rpc_client, db)
+        .execute(
+            db,
+            TransactionPayload::Instructions {
+                instructions: &instructions,
+                payer: &authority_address,
+            },
+            |transaction, recent_blockhash| transaction.try_sign(&signers, recent_blockhash),
+            None,
+        )
+        .await
+        .map_err(|err| format!("Deactivation failed: {}", err))?;
+    if let Some(signature) = signature {
+        println!("Deactivation confirmed: {}", signature);
+        db.record_stake_change_signature(signature, db.get_reporting_timezone().today())?;
     }
-    println!("Wrap confirmed: {}", signature);
-    let when = get_signature_date(rpc_client, signature).await?;
-    db.confirm_transfer(signature, when)?;
 
+    let epoch = rpc_client.get_epoch_info()?.epoch;
+    db.add_deactivating_stake(stake_address, epoch)?;
+    println!("`sys sync` will report when the stake becomes inactive and can be withdrawn");
     Ok(())
 }
 
 #[allow(clippy::too_many_arguments)]
-async fn process_account_unwrap<T: Signers>(
+async fn process_account_stake_withdraw<T: Signers>(
     db: &mut Db,
     rpc_client: &RpcClient,
-    address: Pubkey,
-    amount: u64,
+    stake_address: Pubkey,
+    to_address: Pubkey,
+    amount: Option<u64>, // None = all
     lot_selection_method: LotSelectionMethod,
     lot_numbers: Option<HashSet<usize>>,
     authority_address: Pubkey,
     signers: T,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let sol = MaybeToken::SOL();
-    let wsol = Token::wSOL;
-
-    let _from_account = db
-        .get_account(address, wsol.into())
-        .ok_or_else(|| format!("Wrapped SOL account does not exist for {}", address))?;
-
-    let _to_account = db
-        .get_account(address, sol)
-        .ok_or_else(|| format!("SOL account does not exist for {}", address))?;
-
-    let (recent_blockhash, last_valid_block_height) =
-        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-
-    let ephemeral_token_account = Keypair::new();
+    let token = MaybeToken::SOL();
 
-    let instructions = [
-        spl_associated_token_account::create_associated_token_account(
-            &authority_address,
-            &ephemeral_token_account.pubkey(),
-            &wsol.mint(),
-        ),
-        spl_token::instruction::transfer_checked(
-            &spl_token::id(),
-            &wsol.ata(&address),
-            &wsol.mint(),
-            &wsol.ata(&ephemeral_token_account.pubkey()),
-            &authority_address,
-            &[],
-            amount,
-            wsol.decimals(),
-        )
-        .unwrap(),
-        spl_token::instruction::close_account(
-            &spl_token::id(),
-            &wsol.ata(&ephemeral_token_account.pubkey()),
-            &address,
-            &ephemeral_token_account.pubkey(),
-            &[],
+    let tracked_stake_account = db
+        .get_account(stake_address, token)
+        .ok_or_else(|| format!("Account {} ({}) does not exist", stake_address, token))?;
+    if db.get_account(to_address, token).is_none() {
+        return Err(format!(
+            "Recipient {} is not tracked, add it with `sys account add` first",
+            to_address
         )
-        .unwrap(),
-    ];
-
-    let message = Message::new(&instructions, Some(&authority_address));
+        .into());
+    }
 
-    let mut transaction = Transaction::new_unsigned(message);
-    transaction.message.recent_blockhash = recent_blockhash;
-    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
-    if simulation_result.err.is_some() {
-        return Err(format!("Simulation failure: {:?}", simulation_result).into());
+    let stake_account = rpc_client
+        .get_account_with_commitment(&stake_address, rpc_client.commitment())?
+        .value
+        .ok_or_else(|| format!("Stake account, {}, does not exist", stake_address))?;
+    if stake_account.owner != solana_stake_program::id() {
+        return Err(format!("{} is not a stake account", stake_address).into());
     }
 
-    println!("Unwrapping {} for {}", wsol.ui_amount(amount), address);
+    let amount = amount.unwrap_or(stake_account.lamports);
 
-    transaction.partial_sign(&signers, recent_blockhash);
-    transaction.try_sign(&[&ephemeral_token_account], recent_blockhash)?;
+    if db
+        .get_deactivating_stakes()
+        .iter()
+        .any(|ds| ds.address == stake_address)
+    {
+        let stake_activation = rpc_client.get_stake_activation(stake_address, None)?;
+        if stake_activation.state != StakeActivationState::Inactive {
+            println!(
+                "Note: {} is still deactivating, ◎{} remains active",
+                stake_address,
+                lamports_to_sol(stake_activation.active)
+            );
+        }
+    }
 
-    let signature = transaction.signatures[0];
-    println!("Transaction signature: {}", signature);
+    let instructions = vec![solana_stake_program::stake_instruction::withdraw(
+        &stake_address,
+        &authority_address,
+        &to_address,
+        amount,
+        None,
+    )];
 
-    db.record_transfer(
-        signature,
-        last_valid_block_height,
-        Some(amount),
-        address,
-        wsol.into(),
-        address,
-        sol,
-        lot_selection_method,
-        lot_numbers,
-    )?;
+    println!(
+        "Withdrawing {}{} from {} into {}",
+        token.symbol(),
+        token.ui_amount(amount),
+        stake_address,
+        to_address,
+    );
 
-    if !send_transaction_until_expired(rpc_client, &transaction, last_valid_block_height) {
-        db.cancel_transfer(signature)?;
-        return Err("Wrap failed".into());
+    let signature = TransactionPipeline::new(rpc_client, db)
+        .execute(
+            db,
+            TransactionPayload::Instructions {
+                instructions: &instructions,
+                payer: &authority_address,
+            },
+            |transaction, recent_blockhash| transaction.try_sign(&signers, recent_blockhash),
+            Some(Pending::new(
+                PendingKind::Transfer,
+                |db, signature, last_valid_block_height| {
+                    // Unsynced rewards are not yet covered by lots, the recipient's sync will
+                    // record them
+                    db.record_transfer(
+                        signature,
+                        last_valid_block_height,
+                        Some(amount.min(tracked_stake_account.last_update_balance)),
+                        stake_address,
+                        token,
+                        to_address,
+                        token,
+                        lot_selection_method,
+                        lot_numbers.clone(),
+                    )?;
+                    // A fully withdrawn stake account is closed
+                    if amount == stake_account.lamports {
+                        db.remove_account_on_transfer_confirmation(signature)?;
+                    }
+                    Ok(())
+                },
+            )),
+        )
+        .await
+        .map_err(|err| format!("Withdraw failed: {}", err))?;
+    if let Some(signature) = signature {
+        println!("Withdraw confirmed: {}", signature);
+        if amount == stake_account.lamports {
+            db.remove_deactivating_stake(stake_address)?;
+        }
     }
-    println!("Unwrap confirmed: {}", signature);
-    let when = get_signature_date(rpc_client, signature).await?;
-    db.confirm_transfer(signature, when)?;
-
     Ok(())
 }
-async fn process_account_sync_pending_transfers(
+
+// Deactivates `stake_address` now, `sys sync` later delegates it to `vote_address` once the stake
+// has cooled down
+async fn process_account_redelegate(
     db: &mut Db,
     rpc_client: &RpcClient,
+    stake_address: Pubkey,
+    vote_address: Pubkey,
+    stake_authority: String,
+    new_account: bool,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let block_height = rpc_client.get_epoch_info()?.block_height;
-    for PendingTransfer {
-        signature,
-        last_valid_block_height,
-        ..
-    } in db.pending_transfers()
+    let token = MaybeToken::SOL();
+    if db.get_account(stake_address, token).is_none() {
+        return Err(format!("Account {} ({}) does not exist", stake_address, token).into());
+    }
+    if db
+        .get_pending_redelegations()
+        .iter()
+        .any(|pr| pr.address == stake_address)
     {
-        let status = rpc_client.get_signature_status_with_commitment_and_history(
-            &signature,
-            rpc_client.commitment(),
-            true,
-        )?;
-        match status {
-            Some(result) => {
-                if result.is_ok() {
-                    println!("Pending transfer confirmed: {}", signature);
-                    let when = get_signature_date(rpc_client, signature).await?;
-                    db.confirm_transfer(signature, when)?;
-                } else {
-                    println!("Pending transfer failed with {:?}: {}", result, signature);
-                    db.cancel_transfer(signature)?;
-                }
-            }
-            None => {
-                if block_height > last_valid_block_height {
-                    println!("Pending transfer cancelled: {}", signature);
-                    db.cancel_transfer(signature)?;
-                } else {
-                    println!(
-                        "Transfer pending for at most {} blocks: {}",
-                        last_valid_block_height.saturating_sub(block_height),
-                        signature
-                    );
-                }
-            }
-        }
+        return Err(format!("{} already has a pending redelegation", stake_address).into());
     }
+
+    let (stake_authority_signer, stake_authority_address) =
+        signer_from_uri(&stake_authority, wallet_manager)?;
+
+    let (authorized, current_vote_address) =
+        rpc_client_utils::get_stake_authorized(rpc_client, stake_address)?;
+    if current_vote_address == vote_address {
+        return Err(format!("{} is already delegated to {}", stake_address, vote_address).into());
+    }
+    if authorized.staker != stake_authority_address {
+        return Err("Stake authority mismatch".into());
+    }
+    if new_account && authorized.withdrawer != stake_authority_address {
+        return Err("Withdraw authority mismatch, required by --new-account".into());
+    }
+
+    let instructions = vec![solana_stake_program::stake_instruction::deactivate_stake(
+        &stake_address,
+        &stake_authority_address,
+    )];
+
+    println!(
+        "Redelegating {} from {} to {}",
+        stake_address, current_vote_address, vote_address
+    );
+
+    let signature = TransactionPipeline::new(rpc_client, db)
+        .execute(
+            db,
+            TransactionPayload::Instructions {
+                instructions: &instructions,
+                payer: &stake_authority_address,
+            },
+            |transaction, recent_blockhash| {
+                transaction.try_sign(&vec![stake_authority_signer.as_ref()], recent_blockhash)
+            },
+            None,
+        )
+        .await
+        .map_err(|err| format!("Deactivation failed: {}", err))?;
+    if let Some(signature) = signature {
+        println!("Deactivation confirmed: {}", signature);
+        db.record_stake_change_signature(signature, db.get_reporting_timezone().today())?;
+    }
+
+    let epoch = rpc_client.get_epoch_info()?.epoch;
+    db.set_pending_redelegation(PendingRedelegation {
+        address: stake_address,
+        vote_address,
+        stake_authority,
+        new_account,
+        epoch,
+        state: RedelegationState::Deactivating,
+    })?;
+    println!("`sys sync` will delegate the stake once it has cooled down");
     Ok(())
 }
 
-async fn process_account_sync_sweep(
+// Each attributed transaction is fetched, so a busy account is only walked this far back
+const MAX_ATTRIBUTION_SIGNATURES: usize = 500;
+
+async fn process_account_sync(
     db: &mut Db,
     rpc_client: &RpcClient,
-    _notifier: &Notifier,
+    address: Option<Pubkey>,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+    notifier: &Notifier,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let token = MaybeToken::SOL();
+    process_account_sync_multisig_proposals(db, rpc_client)?;
+    process_account_sync_pending_transfers(db, rpc_client).await?;
+    process_account_sync_stake_changes(db, rpc_client, notifier).await?;
+    process_account_sync_redelegations(db, rpc_client, wallet_manager, notifier).await?;
+    process_account_sync_deactivating_stakes(db, rpc_client, notifier).await?;
+    process_account_sync_sweep(db, rpc_client, wallet_manager, notifier).await?;
 
-    let transitory_sweep_stake_addresses = db.get_transitory_sweep_stake_addresses();
-    if transitory_sweep_stake_addresses.is_empty() {
-        return Ok(());
+    let mut accounts = match address {
+        Some(address) => {
+            // sync all tokens for the given address...
+            let accounts = db.get_account_tokens(address);
+            if accounts.is_empty() {
+                return Err(format!("{} does not exist", address).into());
+            }
+            accounts
+        }
+        None => db.get_accounts(),
     }
+    .into_iter()
+    .filter(|account| !account.no_sync.unwrap_or_default())
+    .collect::<Vec<_>>();
 
-    let sweep_stake_account_info = db
-        .get_sweep_stake_account()
-        .ok_or("Sweep stake account is not configured")?;
+    let base_currency = db.get_base_currency();
+    let current_sol_price = MaybeToken::SOL()
+        .get_current_price(rpc_client, base_currency)
+        .await?;
+    let price_precision = db.get_price_precision();
+    let reporting_timezone = db.get_reporting_timezone();
 
-    let sweep_stake_account_authority_keypair =
-        read_keypair_file(&sweep_stake_account_info.stake_authority).map_err(|err| {
-            format!(
-                "Failed to read {}: {}",
-                sweep_stake_account_info.stake_authority.display(),
-                err
-            )
-        })?;
+    let addresses: Vec<Pubkey> = accounts
+        .iter()
+        .map(|TrackedAccount { address, .. }| *address)
+        .collect::<Vec<_>>();
 
-    let sweep_stake_account = rpc_client
-        .get_account_with_commitment(&sweep_stake_account_info.address, rpc_client.commitment())?
-        .value
-        .ok_or("Sweep stake account does not exist")?;
+    let epoch_info = rpc_client.get_epoch_info()?;
+    let stop_epoch = epoch_info.epoch.saturating_sub(1);
 
-    let sweep_stake_activation = rpc_client
-        .get_stake_activation(sweep_stake_account_info.address, None)
-        .map_err(|err| {
-            format!(
-                "Unable to get activation information for sweep stake account: {}: {}",
-                sweep_stake_account_info.address, err
-            )
-        })?;
+    let start_epoch = accounts
+        .iter()
+        .map(
+            |TrackedAccount {
+                 last_update_epoch, ..
+             }| last_update_epoch,
+        )
+        .min()
+        .unwrap_or(&stop_epoch)
+        + 1;
 
-    if sweep_stake_activation.state != StakeActivationState::Active {
-        println!(
-            "Sweep stake account is not active, unable to continue: {:?}",
-            sweep_stake_activation
-        );
+    if start_epoch > stop_epoch {
+        println!("Processed up to epoch {}", stop_epoch);
         return Ok(());
     }
 
-    for transitory_sweep_stake_address in transitory_sweep_stake_addresses {
-        println!(
-            "Considering merging transitory stake {}",
-            transitory_sweep_stake_address
-        );
+    // Look for inflationary rewards
+    for epoch in start_epoch..=stop_epoch {
+        let msg = format!("Processing epoch: {}", epoch);
+        notifier.send(&msg).await;
+        println!("{}", msg);
 
-        let transitory_sweep_stake_account = match rpc_client
-            .get_account_with_commitment(&transitory_sweep_stake_address, rpc_client.commitment())?
-            .value
+        let inflation_rewards = rpc_client.get_inflation_reward(&addresses, Some(epoch))?;
+
+        for (inflation_reward, address, mut account) in
+            itertools::izip!(inflation_rewards, addresses.iter(), accounts.iter_mut(),)
         {
-            None => {
-                println!(
-                    "  Transitory sweep stake account does not exist, removing it: {}",
-                    transitory_sweep_stake_address
-                );
+            assert_eq!(*address, account.address);
+            if account.last_update_epoch >= epoch {
+                continue;
+            }
 
-                if let Some(tracked_account) = db.get_account(transitory_sweep_stake_address, token)
-                {
-                    if tracked_account.last_update_balance > 0 || !tracked_account.lots.is_empty() {
-                        panic!("Tracked account is not empty: {:?}", tracked_account);
+            if let Some(inflation_reward) = inflation_reward {
+                assert!(!account.token.is_token()); // Only SOL accounts can receive inflationary rewards
 
-                        // TODO: Simulate a transfer to move the lots into the sweep account in
-                        // this case?
-                        /*
-                        let signature = Signature::default();
-                        db.record_transfer(
+                account.last_update_balance += inflation_reward.amount;
+
+                let slot = inflation_reward.effective_slot;
+                let (when, time, price) = get_block_date_and_price(
+                    rpc_client,
+                    slot,
+                    account.token,
+                    price_precision,
+                    base_currency,
+                    reporting_timezone,
+                )
+                .await?;
+
+                let lot = Lot {
+                    lot_number: db.next_lot_number(),
+                    acquisition: LotAcquistion::new_with_time(
+                        when,
+                        time,
+                        price,
+                        LotAcquistionKind::EpochReward { epoch, slot },
+                    ),
+                    amount: inflation_reward.amount,
+                };
+
+                let msg = format!("{}: {}", account.address, account.description);
+                notifier.send(&msg).await;
+                println!("{}", msg);
+
+                println_lot(
+                    account.token,
+                    &lot,
+                    current_sol_price,
+                    base_currency,
+                    reporting_timezone.today(),
+                    None,
+                    &mut 0.,
+                    &mut 0.,
+                    &mut false,
+                    &mut 0.,
+                    Some(notifier),
+                )
+                .await;
+                account.lots.push(lot);
+            }
+        }
+    }
+
+    // Look for block rewards
+    let mut validator_identities = vec![];
+    let mut scanned_balances = HashMap::new();
+    for mut validator_identity in db.get_validator_identities() {
+        if let Some(account) = accounts.iter_mut().find(|account| {
+            account.address == validator_identity.address && !account.token.is_token()
+        }) {
+            let scanned_balance = process_account_sync_block_rewards(
+                db,
+                rpc_client,
+                account,
+                &mut validator_identity,
+                current_sol_price,
+                notifier,
+            )
+            .await?;
+            scanned_balances.insert(account.address, scanned_balance);
+            validator_identities.push(validator_identity);
+        }
+    }
+
+    // Token accounts are keyed by their token account address, so that credits can be matched
+    // to tracked accounts of the same token
+    let tracked_addresses = db
+        .get_accounts()
+        .into_iter()
+        .map(|account| (account.balance_address(), account.address))
+        .collect::<HashMap<_, _>>();
+
+    let fee_payers = db.get_fee_payers();
+
+    // Transactions issued by `sys`, or already followed, are reflected in the lots
+    let known_signatures = db.get_known_signatures();
+
+    // Credits funded by another tracked account, moved with their lots once the balances of all
+    // accounts have been reconciled
+    let mut internal_moves: Vec<InternalMove> = vec![];
+
+    // Look for unexpected balance increases (such as transaction and rent rewards)
+    let mut current_balances = vec![];
+    for mut account in accounts.iter_mut() {
+        account.last_update_epoch = stop_epoch;
+
+        let (address, address_is_token) = (account.balance_address(), account.token.is_token());
+        let latest_signature =
+            rpc_client_utils::get_latest_signature_for_address(rpc_client, &address)?;
+
+        // Validator identity balances are compared as of the last slot scanned for block rewards,
+        // so that the rewards of later blocks aren't also taken as an unexpected increase
+        let current_balance = match scanned_balances.get(&account.address) {
+            Some(scanned_balance) if account.token.is_sol() => *scanned_balance,
+            _ => account.token.balance_at(rpc_client, &address)?,
+        };
+        current_balances.push(current_balance);
+
+        if current_balance > account.last_update_balance + account.token.amount(0.005) {
+            let current_token_price = account
+                .token
+                .get_current_price(rpc_client, base_currency)
+                .await?;
+            let mut unattributed_amount = current_balance - account.last_update_balance;
+            let mut internal_amount = 0;
+
+            let msg = format!(
+                "{} ({}): {}",
+                account.address, account.token, account.description
+            );
+            notifier.send(&msg).await;
+            println!("{}", msg);
+
+            // Attribute the increase to the transactions since the previous sync. No more than
+            // the unexpected increase is attributed, and whatever isn't attributed is recorded
+            // below as not attributable.
+            //
+            // The vote transactions of validator identities and fee payers would exhaust the
+            // signature limit, so their increases aren't attributed
+            let walk_signatures = !fee_payers.contains(&account.address)
+                && !validator_identities
+                    .iter()
+                    .any(|vi| vi.address == account.address);
+            if let (Some(last_update_signature), true) =
+                (account.last_update_signature, walk_signatures)
+            {
+                let (signatures, limit_reached) =
+                    rpc_client_utils::get_signatures_for_address_since(
+                        rpc_client,
+                        &address,
+                        last_update_signature,
+                        MAX_ATTRIBUTION_SIGNATURES,
+                    )?;
+                if limit_reached {
+                    println!(
+                        "Warning: Only the latest {} transactions of {} are attributed",
+                        MAX_ATTRIBUTION_SIGNATURES, address
+                    );
+                }
+                for signature in signatures {
+                    if unattributed_amount == 0 {
+                        break;
+                    }
+                    if known_signatures.contains(&signature) {
+                        continue;
+                    }
+
+                    let GetTransactionAddrssBalanceChange {
+                        pre_amount,
+                        post_amount,
+                        slot,
+                        debits,
+                        ..
+                    } = match get_transaction_balance_change(
+                        rpc_client,
+                        &signature,
+                        &address,
+                        address_is_token,
+                    ) {
+                        Ok(balance_change) => balance_change,
+                        Err(err) => {
+                            println!(
+                                "Warning: Unable to attribute the balance change of {}: {}",
+                                signature, err
+                            );
+                            continue;
+                        }
+                    };
+                    if post_amount <= pre_amount {
+                        continue;
+                    }
+                    let amount = (post_amount - pre_amount).min(unattributed_amount);
+                    unattributed_amount -= amount;
+
+                    // A credit funded by another tracked account with sufficient lots is an
+                    // internal move, not income
+                    let internal_source =
+                        debits.iter().find_map(|(debit_address, debit_amount)| {
+                            let from_address = *tracked_addresses.get(debit_address)?;
+                            if *debit_amount < amount {
+                                return None;
+                            }
+                            let from_balance = db
+                                .get_account(from_address, account.token)?
+                                .last_update_balance;
+                            let moved_amount = internal_moves
+                                .iter()
+                                .filter(|internal_move| {
+                                    internal_move.from_address == from_address
+                                        && internal_move.token == account.token
+                                })
+                                .map(|internal_move| internal_move.amount)
+                                .sum::<u64>();
+                            (from_balance >= moved_amount + amount).then(|| from_address)
+                        });
+
+                    if let Some(from_address) = internal_source {
+                        let when = get_block_date(rpc_client, slot, reporting_timezone).await?;
+                        let msg = format!(
+                            "Internal move of {}{} from {} ({})",
+                            account.token.symbol(),
+                            account.token.ui_amount(amount),
+                            from_address,
                             signature,
-                            None,
-                            transitory_sweep_stake_address,
-                            sweep_stake_account_info.address,
-                            None,
-                        )?;
-                        db.confirm_transfer(signature)?;
-                        */
+                        );
+                        notifier.send(&msg).await;
+                        println!("{}", msg);
+
+                        internal_amount += amount;
+                        internal_moves.push(InternalMove {
+                            signature,
+                            when,
+                            amount,
+                            from_address,
+                            to_address: account.address,
+                            token: account.token,
+                        });
+                        continue;
                     }
+
+                    let (when, time, decimal_price) = get_block_date_and_price(
+                        rpc_client,
+                        slot,
+                        account.token,
+                        price_precision,
+                        base_currency,
+                        reporting_timezone,
+                    )
+                    .await?;
+
+                    let lot = Lot {
+                        lot_number: db.next_lot_number(),
+                        acquisition: LotAcquistion::new_with_time(
+                            when,
+                            time,
+                            decimal_price,
+                            LotAcquistionKind::Transaction {
+                                slot,
+                                signature,
+                                income: true,
+                            },
+                        ),
+                        amount,
+                    };
+
+                    println_lot(
+                        account.token,
+                        &lot,
+                        current_token_price,
+                        base_currency,
+                        reporting_timezone.today(),
+                        None,
+                        &mut 0.,
+                        &mut 0.,
+                        &mut false,
+                        &mut 0.,
+                        Some(notifier),
+                    )
+                    .await;
+                    account.lots.push(lot);
                 }
-                db.remove_transitory_sweep_stake_address(transitory_sweep_stake_address)?;
-                continue;
             }
-            Some(x) => x,
+
+            // Remaining increases, such as rent rewards, are not attributable to a transaction
+            if unattributed_amount > 0 {
+                let slot = epoch_info.absolute_slot;
+                let (when, time, decimal_price) = get_block_date_and_price(
+                    rpc_client,
+                    slot,
+                    account.token,
+                    price_precision,
+                    base_currency,
+                    reporting_timezone,
+                )
+                .await?;
+
+                let lot = Lot {
+                    lot_number: db.next_lot_number(),
+                    acquisition: LotAcquistion::new_with_time(
+                        when,
+                        time,
+                        decimal_price,
+                        LotAcquistionKind::NotAvailable,
+                    ),
+                    amount: unattributed_amount,
+                };
+
+                println_lot(
+                    account.token,
+                    &lot,
+                    current_token_price,
+                    base_currency,
+                    reporting_timezone.today(),
+                    None,
+                    &mut 0.,
+                    &mut 0.,
+                    &mut false,
+                    &mut 0.,
+                    Some(notifier),
+                )
+                .await;
+                account.lots.push(lot);
+            }
+
+            // Internal moves add their lots, and balance, later
+            account.last_update_balance = current_balance - internal_amount;
+        }
+
+        if latest_signature.is_some() {
+            account.last_update_signature = latest_signature;
+        }
+    }
+
+    // Look for unexpected balance decreases, other than internal moves
+    for (account, current_balance) in accounts.iter_mut().zip(current_balances) {
+        let moved_amount = internal_moves
+            .iter()
+            .filter(|internal_move| {
+                internal_move.from_address == account.address
+                    && internal_move.token == account.token
+            })
+            .map(|internal_move| internal_move.amount)
+            .sum::<u64>();
+        let expected_balance = account.last_update_balance.saturating_sub(moved_amount);
+
+        if current_balance < expected_balance
+            && account.token.is_sol()
+            && fee_payers.contains(&account.address)
+        {
+            let fees = expected_balance - current_balance;
+            let msg = format!(
+                "{} ({}): {}{} of transaction fees recorded as an expense",
+                account.address,
+                account.token,
+                account.token.symbol(),
+                account.token.ui_amount(fees),
+            );
+            notifier.send(&msg).await;
+            println!("{}", msg);
+
+            // Dated and priced at the slot the balance was observed, like other unattributed
+            // balance changes
+            let slot = epoch_info.absolute_slot;
+            let (when, _, decimal_price) = get_block_date_and_price(
+                rpc_client,
+                slot,
+                account.token,
+                price_precision,
+                base_currency,
+                reporting_timezone,
+            )
+            .await?;
+
+            let description = format!("Transaction fees paid by {}", account.address);
+            db.record_fee_disposal(account, fees, description, slot, when, decimal_price)?;
+        } else if current_balance < expected_balance {
+            println!(
+                "\nWarning: {} ({}) balance is less than expected. Actual: {}{}, expected: {}{}\n",
+                account.address,
+                account.token,
+                account.token.symbol(),
+                account.token.ui_amount(current_balance),
+                account.token.symbol(),
+                account.token.ui_amount(expected_balance)
+            );
+        }
+        db.update_account(account.clone())?;
+    }
+
+    // Only advance the block reward scan once the lots have been recorded
+    for validator_identity in validator_identities {
+        db.set_validator_identity(validator_identity)?;
+    }
+
+    for InternalMove {
+        signature,
+        when,
+        amount,
+        from_address,
+        to_address,
+        token,
+    } in internal_moves
+    {
+        db.record_transfer(
+            signature,
+            0,
+            Some(amount),
+            from_address,
+            token,
+            to_address,
+            token,
+            LotSelectionMethod::default(),
+            None,
+        )?;
+        db.confirm_transfer(signature, when)?;
+    }
+
+    Ok(())
+}
+
+// A credit to a tracked account funded by another tracked account
+struct InternalMove {
+    signature: Signature,
+    when: NaiveDate,
+    amount: u64,
+    from_address: Pubkey,
+    to_address: Pubkey,
+    token: MaybeToken,
+}
+
+// Records a lot for the fee and rent rewards of each block (or day of blocks) produced by
+// `validator_identity` up to the latest finalized slot. Returns the balance of the identity as of
+// that slot
+async fn process_account_sync_block_rewards(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    account: &mut TrackedAccount,
+    validator_identity: &mut ValidatorIdentity,
+    current_sol_price: Decimal,
+    notifier: &Notifier,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let base_currency = db.get_base_currency();
+    let price_precision = db.get_price_precision();
+    let reporting_timezone = db.get_reporting_timezone();
+
+    let balance_response =
+        rpc_client.get_balance_with_commitment(&account.address, CommitmentConfig::finalized())?;
+    let current_balance = balance_response.value;
+
+    let epoch_schedule = rpc_client.get_epoch_schedule()?;
+    let stop_slot = balance_response.context.slot;
+    let start_slot = validator_identity.last_scanned_slot + 1;
+    if start_slot > stop_slot {
+        return Ok(current_balance);
+    }
+
+    let identity = validator_identity.address.to_string();
+    let mut leader_slots = vec![];
+    for epoch in epoch_schedule.get_epoch(start_slot)..=epoch_schedule.get_epoch(stop_slot) {
+        let first_slot_in_epoch = epoch_schedule.get_first_slot_in_epoch(epoch);
+        if let Some(leader_schedule) = rpc_client.get_leader_schedule_with_config(
+            Some(first_slot_in_epoch),
+            RpcLeaderScheduleConfig {
+                identity: Some(identity.clone()),
+                commitment: Some(CommitmentConfig::finalized()),
+            },
+        )? {
+            leader_slots.extend(
+                leader_schedule
+                    .get(&identity)
+                    .cloned()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|slot_index| first_slot_in_epoch + slot_index as Slot)
+                    .filter(|slot| (start_slot..=stop_slot).contains(slot)),
+            );
+        }
+    }
+    leader_slots.sort_unstable();
+
+    let msg = format!(
+        "Scanning {} leader slots of {} from slot {} to {}",
+        leader_slots.len(),
+        validator_identity.address,
+        start_slot,
+        stop_slot
+    );
+    notifier.send(&msg).await;
+    println!("{}", msg);
+
+    // Rewards by date, truncated UTC time, slot (if not aggregated) and reward type, with the first
+    // slot and block count of each. The UTC time is kept for pricing, as a local time may be
+    // ambiguous or nonexistent across a DST change
+    let mut block_rewards = BTreeMap::<
+        (NaiveDate, Option<NaiveDateTime>, Slot, String),
+        (Slot, RewardType, usize, u64),
+    >::new();
+    for slot in leader_slots {
+        let block = match rpc_client.get_block_with_config(
+            slot,
+            RpcBlockConfig {
+                transaction_details: Some(TransactionDetails::None),
+                rewards: Some(true),
+                commitment: Some(CommitmentConfig::finalized()),
+                ..RpcBlockConfig::default()
+            },
+        ) {
+            Ok(block) => block,
+            Err(err) if is_skipped_slot_error(&err) => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let block_time = block
+            .block_time
+            .ok_or_else(|| format!("Block time not available for slot {}", slot))?;
+
+        let utc_time = NaiveDateTime::from_timestamp(block_time, 0);
+        let (when, time) = match validator_identity.aggregation {
+            BlockRewardAggregation::Block => (
+                reporting_timezone.date_of_timestamp(block_time),
+                price_precision.truncate(utc_time),
+            ),
+            BlockRewardAggregation::Day => (reporting_timezone.date_of_timestamp(block_time), None),
+        };
+
+        for reward in block.rewards.unwrap_or_default() {
+            if reward.pubkey != identity || reward.lamports <= 0 {
+                continue;
+            }
+            let reward_type = match reward.reward_type {
+                Some(reward_type @ RewardType::Fee) | Some(reward_type @ RewardType::Rent) => {
+                    reward_type
+                }
+                _ => continue,
+            };
+
+            let key = match validator_identity.aggregation {
+                BlockRewardAggregation::Block => (when, time, slot, reward_type.to_string()),
+                BlockRewardAggregation::Day => (when, time, 0, reward_type.to_string()),
+            };
+            let entry = block_rewards
+                .entry(key)
+                .or_insert((slot, reward_type, 0, 0));
+            entry.2 += 1;
+            entry.3 += reward.lamports as u64;
+        }
+    }
+
+    let mut total_rewards = 0;
+    let mut prices = HashMap::<(NaiveDate, Option<NaiveDateTime>), Decimal>::new();
+    for ((when, time, _, _), (slot, reward_type, blocks, amount)) in block_rewards {
+        let price = match prices.get(&(when, time)) {
+            Some(price) => *price,
+            None => {
+                let price = match time {
+                    Some(time) => {
+                        MaybeToken::SOL()
+                            .get_historical_price_at(rpc_client, time, base_currency)
+                            .await?
+                    }
+                    None => {
+                        MaybeToken::SOL()
+                            .get_historical_price(rpc_client, when, base_currency)
+                            .await?
+                    }
+                };
+                prices.insert((when, time), price);
+                price
+            }
+        };
+
+        let lot = Lot {
+            lot_number: db.next_lot_number(),
+            acquisition: LotAcquistion::new_with_time(
+                when,
+                time.map(|time| reporting_timezone.local_datetime(time)),
+                price,
+                LotAcquistionKind::BlockReward {
+                    slot,
+                    reward_type,
+                    blocks,
+                },
+            ),
+            amount,
+        };
+        println_lot(
+            account.token,
+            &lot,
+            current_sol_price,
+            base_currency,
+            reporting_timezone.today(),
+            None,
+            &mut 0.,
+            &mut 0.,
+            &mut false,
+            &mut 0.,
+            Some(notifier),
+        )
+        .await;
+
+        total_rewards += amount;
+        account.last_update_balance += amount;
+        account.lots.push(lot);
+    }
+
+    let msg = format!(
+        "{}: block rewards ◎{}, expected balance ◎{}, actual balance ◎{} (◎{} unexplained)",
+        account.address,
+        lamports_to_sol(total_rewards),
+        lamports_to_sol(account.last_update_balance),
+        lamports_to_sol(current_balance),
+        lamports_to_sol(current_balance) - lamports_to_sol(account.last_update_balance),
+    );
+    notifier.send(&msg).await;
+    println!("{}", msg);
+
+    validator_identity.last_scanned_slot = stop_slot;
+    Ok(current_balance)
+}
+
+fn is_skipped_slot_error(err: &solana_client::client_error::ClientError) -> bool {
+    use solana_client::{
+        client_error::ClientErrorKind,
+        rpc_custom_error::{
+            JSON_RPC_LONG_TERM_STORAGE_SLOT_SKIPPED, JSON_RPC_SERVER_ERROR_SLOT_SKIPPED,
+        },
+        rpc_request::RpcError,
+    };
+
+    matches!(
+        err.kind(),
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })
+            if *code == JSON_RPC_SERVER_ERROR_SLOT_SKIPPED
+                || *code == JSON_RPC_LONG_TERM_STORAGE_SLOT_SKIPPED
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn process_account_wrap<T: Signers>(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    address: Pubkey,
+    amount: u64,
+    lot_selection_method: LotSelectionMethod,
+    lot_numbers: Option<HashSet<usize>>,
+    authority_address: Pubkey,
+    signers: T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sol = MaybeToken::SOL();
+    let wsol = Token::wSOL;
+    let wsol_address = wsol.ata(&address);
+
+    let _from_account = db
+        .get_account(address, sol)
+        .ok_or_else(|| format!("SOL account does not exist for {}", address))?;
+
+    let _to_account = db
+        .get_account(address, wsol.into())
+        .ok_or_else(|| format!("Wrapped SOL account does not exist for {}", address))?;
+
+    let mut instructions = vec![];
+
+    // TODO: replace the following block with
+    // `spl_associated_token_account::instruction::create_associated_token_account_idempotent()`
+    // when it ships to mainnet
+    if rpc_client
+        .get_account_with_commitment(&wsol_address, rpc_client.commitment())?
+        .value
+        .is_none()
+    {
+        instructions.push(
+            spl_associated_token_account::create_associated_token_account(
+                &authority_address,
+                &address,
+                &wsol.mint(),
+            ),
+        );
+    }
+    instructions.extend([
+        system_instruction::transfer(&address, &wsol_address, amount),
+        spl_token::instruction::sync_native(&spl_token::id(), &wsol_address).unwrap(),
+    ]);
+
+    println!("Wrapping {} for {}", wsol.ui_amount(amount), address);
+
+    let signature = TransactionPipeline::new(rpc_client, db)
+        .execute(
+            db,
+            TransactionPayload::Instructions {
+                instructions: &instructions,
+                payer: &authority_address,
+            },
+            |transaction, recent_blockhash| transaction.try_sign(&signers, recent_blockhash),
+            Some(Pending::new(
+                PendingKind::Transfer,
+                |db, signature, last_valid_block_height| {
+                    db.record_transfer(
+                        signature,
+                        last_valid_block_height,
+                        Some(amount),
+                        address,
+                        sol,
+                        address,
+                        wsol.into(),
+                        lot_selection_method,
+                        lot_numbers.clone(),
+                    )
+                },
+            )),
+        )
+        .await
+        .map_err(|err| format!("Wrap failed: {}", err))?;
+    if let Some(signature) = signature {
+        println!("Wrap confirmed: {}", signature);
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn process_account_unwrap<T: Signers>(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    address: Pubkey,
+    amount: u64,
+    lot_selection_method: LotSelectionMethod,
+    lot_numbers: Option<HashSet<usize>>,
+    authority_address: Pubkey,
+    signers: T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sol = MaybeToken::SOL();
+    let wsol = Token::wSOL;
+
+    let _from_account = db
+        .get_account(address, wsol.into())
+        .ok_or_else(|| format!("Wrapped SOL account does not exist for {}", address))?;
+
+    let _to_account = db
+        .get_account(address, sol)
+        .ok_or_else(|| format!("SOL account does not exist for {}", address))?;
+
+    let ephemeral_token_account = Keypair::new();
+
+    let instructions = [
+        spl_associated_token_account::create_associated_token_account(
+            &authority_address,
+            &ephemeral_token_account.pubkey(),
+            &wsol.mint(),
+        ),
+        spl_token::instruction::transfer_checked(
+            &spl_token::id(),
+            &wsol.ata(&address),
+            &wsol.mint(),
+            &wsol.ata(&ephemeral_token_account.pubkey()),
+            &authority_address,
+            &[],
+            amount,
+            wsol.decimals(),
+        )
+        .unwrap(),
+        spl_token::instruction::close_account(
+            &spl_token::id(),
+            &wsol.ata(&ephemeral_token_account.pubkey()),
+            &address,
+            &ephemeral_token_account.pubkey(),
+            &[],
+        )
+        .unwrap(),
+    ];
+
+    println!("Unwrapping {} for {}", wsol.ui_amount(amount), address);
+
+    let signature = TransactionPipeline::new(rpc_client, db)
+        .execute(
+            db,
+            TransactionPayload::Instructions {
+                instructions: &instructions,
+                payer: &authority_address,
+            },
+            |transaction, recent_blockhash| {
+                transaction.try_partial_sign(&signers, recent_blockhash)?;
+                transaction.try_sign(&[&ephemeral_token_account], recent_blockhash)
+            },
+            Some(Pending::new(
+                PendingKind::Transfer,
+                |db, signature, last_valid_block_height| {
+                    db.record_transfer(
+                        signature,
+                        last_valid_block_height,
+                        Some(amount),
+                        address,
+                        wsol.into(),
+                        address,
+                        sol,
+                        lot_selection_method,
+                        lot_numbers.clone(),
+                    )
+                },
+            )),
+        )
+        .await
+        .map_err(|err| format!("Unwrap failed: {}", err))?;
+    if let Some(signature) = signature {
+        println!("Unwrap confirmed: {}", signature);
+    }
+
+    Ok(())
+}
+// A pending operation of a multisig proposal is moved to the signature of the transaction that
+// executed the proposal, for the pending transfer, swap and deposit syncs to complete
+fn process_account_sync_multisig_proposals(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for PendingMultisigProposal {
+        proposal,
+        pending_signature,
+        instructions,
+    } in db.get_pending_multisig_proposals()
+    {
+        let mut executed_signature = None;
+        // Oldest first, the proposal is executed at most once
+        for status in rpc_client
+            .get_signatures_for_address(&proposal)?
+            .iter()
+            .rev()
+        {
+            if status.err.is_some() {
+                continue;
+            }
+            let signature = Signature::from_str(&status.signature)?;
+            let executed_instructions =
+                rpc_client_utils::get_executed_instructions(rpc_client, &signature)?;
+            if instructions.iter().all(|instruction| {
+                executed_instructions
+                    .iter()
+                    .any(|executed_instruction| instruction.matches(executed_instruction))
+            }) {
+                executed_signature = Some(signature);
+                break;
+            }
+        }
+
+        match executed_signature {
+            Some(signature) => {
+                println!("Multisig proposal {} executed: {}", proposal, signature);
+                if let Some(pending_signature) = pending_signature {
+                    db.update_pending_signature(pending_signature, signature)?;
+                }
+                db.remove_pending_multisig_proposal(proposal)?;
+            }
+            None => println!("Multisig proposal {} awaiting execution", proposal),
+        }
+    }
+    Ok(())
+}
+
+async fn process_account_sync_pending_transfers(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let block_height = rpc_client.get_epoch_info()?.block_height;
+    for PendingTransfer {
+        signature,
+        last_valid_block_height,
+        ..
+    } in db.pending_transfers()
+    {
+        let status = rpc_client.get_signature_status_with_commitment_and_history(
+            &signature,
+            rpc_client.commitment(),
+            true,
+        )?;
+        match status {
+            Some(result) => {
+                if result.is_ok() {
+                    println!("Pending transfer confirmed: {}", signature);
+                    let when =
+                        get_signature_date(rpc_client, signature, db.get_reporting_timezone())
+                            .await?;
+                    db.confirm_transfer(signature, when)?;
+                } else {
+                    println!("Pending transfer failed with {:?}: {}", result, signature);
+                    db.cancel_transfer(signature)?;
+                }
+            }
+            None => {
+                if last_valid_block_height == OFFLINE_BLOCK_HEIGHT {
+                    println!("Transfer awaiting submission: {}", signature);
+                } else if block_height > last_valid_block_height {
+                    println!("Pending transfer cancelled: {}", signature);
+                    db.cancel_transfer(signature)?;
+                } else {
+                    println!(
+                        "Transfer pending for at most {} blocks: {}",
+                        last_valid_block_height.saturating_sub(block_height),
+                        signature
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Moves lots between tracked accounts to follow a stake change made outside of `sys`. Returns
+// the amount that could not be followed because the lots of `from_address` were insufficient
+fn follow_stake_transfer(
+    db: &mut Db,
+    signature: Signature,
+    when: NaiveDate,
+    amount: Option<u64>, // None = all
+    from_address: Pubkey,
+    to_address: Pubkey,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let token = MaybeToken::SOL();
+    let from_account = db
+        .get_account(from_address, token)
+        .ok_or_else(|| format!("{} is not tracked", from_address))?;
+
+    let available_amount = from_account.last_update_balance;
+    let amount = amount.unwrap_or(available_amount);
+    let moved_amount = amount.min(available_amount);
+
+    if moved_amount > 0 {
+        db.record_transfer(
+            signature,
+            0,
+            Some(moved_amount),
+            from_address,
+            token,
+            to_address,
+            token,
+            LotSelectionMethod::default(),
+            None,
+        )?;
+        db.confirm_transfer(signature, when)?;
+    }
+    Ok(amount - moved_amount)
+}
+
+const MAX_STAKE_CHANGE_SIGNATURES: usize = 1_000;
+
+// Follows splits, merges and withdrawals made outside of `sys` on tracked stake accounts by moving
+// lots between tracked accounts, and reports the stake changes that need attention
+async fn process_account_sync_stake_changes(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    notifier: &Notifier,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let token = MaybeToken::SOL();
+    let reporting_timezone = db.get_reporting_timezone();
+
+    // Transfers completed by `sys` are already reflected in the lots, and its delegations and
+    // deactivations aren't outside stake changes
+    let completed_transfer_signatures = db.get_completed_transfer_signatures();
+
+    let mut addresses = db
+        .get_accounts()
+        .into_iter()
+        .filter(|account| account.token.is_sol() && !account.no_sync.unwrap_or_default())
+        .map(|account| account.address)
+        .collect::<VecDeque<_>>();
+
+    let mut reconciliation = vec![];
+    let mut note = |msg: String| {
+        println!("  {}", msg);
+        reconciliation.push(msg);
+    };
+
+    while let Some(address) = addresses.pop_front() {
+        let mut account = match db.get_account(address, token) {
+            Some(account) => account,
+            None => continue,
+        };
+
+        let stake_account = rpc_client
+            .get_account_with_commitment(&address, rpc_client.commitment())?
+            .value;
+        if matches!(&stake_account, Some(stake_account) if stake_account.owner != solana_stake_program::id())
+        {
+            continue;
+        }
+
+        let last_stake_change_signature = match account.last_stake_change_signature {
+            Some(last_stake_change_signature) => last_stake_change_signature,
+            None => {
+                // Earlier stake changes are assumed to be reflected in the lots already
+                account.last_stake_change_signature =
+                    rpc_client_utils::get_latest_signature_for_address(rpc_client, &address)?;
+                db.update_account(account)?;
+                continue;
+            }
+        };
+
+        let mut removed = false;
+        let mut latest_signature = last_stake_change_signature;
+        let (signatures, limit_reached) = rpc_client_utils::get_signatures_for_address_since(
+            rpc_client,
+            &address,
+            last_stake_change_signature,
+            MAX_STAKE_CHANGE_SIGNATURES,
+        )?;
+        if limit_reached {
+            note(format!(
+                "Only the latest {} transactions of {} were checked for stake changes",
+                MAX_STAKE_CHANGE_SIGNATURES, address
+            ));
+        }
+        for signature in signatures {
+            if completed_transfer_signatures.contains(&signature) {
+                latest_signature = signature;
+                continue;
+            }
+
+            // The signature is checked again by the next sync
+            let GetStakeChanges {
+                slot,
+                block_time,
+                changes,
+            } = match get_stake_changes(rpc_client, &signature) {
+                Ok(stake_changes) => stake_changes,
+                Err(err) => {
+                    note(format!(
+                        "Unable to check {} for stake changes, stopping at it: {}",
+                        signature, err
+                    ));
+                    break;
+                }
+            };
+            latest_signature = signature;
+            let when = match block_time {
+                Some(block_time) => reporting_timezone.date_of_timestamp(block_time),
+                None => get_block_date(rpc_client, slot, reporting_timezone).await?,
+            };
+
+            for change in changes {
+                match change {
+                    StakeChange::Split {
+                        source,
+                        destination,
+                        lamports,
+                    } if source == address => {
+                        if db.get_account(destination, token).is_none() {
+                            let epoch = rpc_client.get_epoch_info()?.epoch;
+                            db.add_account(TrackedAccount {
+                                address: destination,
+                                token,
+                                description: format!("Split from {}", account.description),
+                                last_update_epoch: epoch.saturating_sub(1),
+                                last_update_balance: 0,
+                                lots: vec![],
+                                no_sync: None,
+                                last_update_signature: Some(signature),
+                                last_stake_change_signature: Some(signature),
+                                token_account: None,
+                            })?;
+                            addresses.push_back(destination);
+                            note(format!("Now tracking stake account {}", destination));
+                        }
+
+                        let shortfall = follow_stake_transfer(
+                            db,
+                            signature,
+                            when,
+                            Some(lamports),
+                            source,
+                            destination,
+                        )?;
+                        note(format!(
+                            "Split ◎{} from {} into {}: {}",
+                            lamports_to_sol(lamports),
+                            source,
+                            destination,
+                            signature
+                        ));
+                        if shortfall > 0 {
+                            note(format!(
+                                "Lots of {} were short by ◎{} for the split, {} will record the \
+                                 difference as an unexpected balance change",
+                                source,
+                                lamports_to_sol(shortfall),
+                                destination
+                            ));
+                        }
+                    }
+                    StakeChange::Merge {
+                        source,
+                        destination,
+                        lamports,
+                    } if source == address => {
+                        if db.get_account(destination, token).is_none() {
+                            let epoch = rpc_client.get_epoch_info()?.epoch;
+                            db.add_account(TrackedAccount {
+                                address: destination,
+                                token,
+                                description: format!("Merged from {}", account.description),
+                                last_update_epoch: epoch.saturating_sub(1),
+                                last_update_balance: 0,
+                                lots: vec![],
+                                no_sync: None,
+                                last_update_signature: Some(signature),
+                                last_stake_change_signature: Some(signature),
+                                token_account: None,
+                            })?;
+                            addresses.push_back(destination);
+                            note(format!("Now tracking stake account {}", destination));
+                        }
+
+                        follow_stake_transfer(db, signature, when, None, source, destination)?;
+                        note(format!(
+                            "Merged {} (◎{}) into {}: {}",
+                            source,
+                            lamports_to_sol(lamports),
+                            destination,
+                            signature
+                        ));
+
+                        if db
+                            .get_account(source, token)
+                            .map(|account| account.lots.is_empty())
+                            .unwrap_or_default()
+                        {
+                            db.remove_account(source, token)?;
+                            note(format!(
+                                "No longer tracking merged stake account {}",
+                                source
+                            ));
+                            removed = true;
+                        }
+                    }
+                    StakeChange::Withdraw {
+                        stake,
+                        recipient,
+                        lamports,
+                    } if stake == address => {
+                        if db.get_account(recipient, token).is_some() {
+                            let shortfall = follow_stake_transfer(
+                                db,
+                                signature,
+                                when,
+                                Some(lamports),
+                                stake,
+                                recipient,
+                            )?;
+                            note(format!(
+                                "Withdrew ◎{} from {} into {}: {}",
+                                lamports_to_sol(lamports),
+                                stake,
+                                recipient,
+                                signature
+                            ));
+                            if shortfall > 0 {
+                                note(format!(
+                                    "Lots of {} were short by ◎{} for the withdrawal",
+                                    stake,
+                                    lamports_to_sol(shortfall),
+                                ));
+                            }
+                        } else {
+                            note(format!(
+                                "Withdrew ◎{} from {} to untracked {}, its lots were not \
+                                 moved. Use `sys account dispose` to record the disposal: {}",
+                                lamports_to_sol(lamports),
+                                stake,
+                                recipient,
+                                signature
+                            ));
+                        }
+                    }
+                    StakeChange::Split {
+                        source,
+                        destination,
+                        lamports,
+                    } if destination == address && db.get_account(source, token).is_none() => {
+                        note(format!(
+                            "Split ◎{} from untracked {} into {}: {}",
+                            lamports_to_sol(lamports),
+                            source,
+                            destination,
+                            signature
+                        ));
+                    }
+                    StakeChange::Merge {
+                        source,
+                        destination,
+                        lamports,
+                    } if destination == address && db.get_account(source, token).is_none() => {
+                        note(format!(
+                            "Merged untracked {} (◎{}) into {}: {}",
+                            source,
+                            lamports_to_sol(lamports),
+                            destination,
+                            signature
+                        ));
+                    }
+                    StakeChange::Deactivate { stake } if stake == address => {
+                        // Redelegations track their own cooldown
+                        if !db
+                            .get_pending_redelegations()
+                            .iter()
+                            .any(|pr| pr.address == stake)
+                        {
+                            let epoch = rpc_client.get_epoch_schedule()?.get_epoch(slot);
+                            db.add_deactivating_stake(stake, epoch)?;
+                        }
+                        note(format!("Deactivated {}: {}", stake, signature));
+                    }
+                    StakeChange::Delegate { stake, vote } if stake == address => {
+                        db.remove_deactivating_stake(stake)?;
+                        note(format!("Delegated {} to {}: {}", stake, vote, signature));
+                    }
+                    StakeChange::Authorize {
+                        stake,
+                        new_authority,
+                        stake_authorize,
+                    } if stake == address => {
+                        note(format!(
+                            "{:?} authority of {} changed to {}: {}",
+                            stake_authorize, stake, new_authority, signature
+                        ));
+                    }
+                    _ => {
+                        // Followed while processing the other tracked account
+                    }
+                }
+            }
+
+            if removed {
+                break;
+            }
+        }
+
+        if !removed {
+            let mut account = db.get_account(address, token).unwrap();
+            account.last_stake_change_signature = Some(latest_signature);
+            if stake_account.is_none() && account.last_update_balance > 0 {
+                note(format!(
+                    "{} no longer exists but still holds ◎{} of lots",
+                    address,
+                    lamports_to_sol(account.last_update_balance)
+                ));
+            }
+            db.update_account(account)?;
+        }
+    }
+
+    // Kept well beyond the interval between syncs, as the signatures since the previous sync are
+    // checked against them here and by the balance change detection of `process_account_sync()`
+    db.prune_completed_transfer_signatures(
+        reporting_timezone.today() - chrono::Duration::days(90),
+    )?;
+
+    for account in db.get_accounts() {
+        if let Some(lot_balance) = account.lot_balance_mismatch() {
+            note(format!(
+                "{} ({}) lots add up to {}{}, but its balance is {}{}",
+                account.address,
+                account.token,
+                account.token.symbol(),
+                account.token.ui_amount(lot_balance),
+                account.token.symbol(),
+                account.token.ui_amount(account.last_update_balance),
+            ));
+        }
+    }
+
+    if !reconciliation.is_empty() {
+        let msg = format!("Stake reconciliation:\n{}", reconciliation.join("\n"));
+        notifier.send(&msg).await;
+    }
+    Ok(())
+}
+
+// Drives pending redelegations forward: once the stake has cooled down it is delegated to the new
+// vote account, optionally after withdrawing it into a new stake account that inherits its lots
+async fn process_account_sync_redelegations(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+    notifier: &Notifier,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for mut pending_redelegation in db.get_pending_redelegations() {
+        let address = pending_redelegation.address;
+
+        if rpc_client
+            .get_account_with_commitment(&address, rpc_client.commitment())?
+            .value
+            .is_none()
+        {
+            let msg = format!(
+                "Redelegation of {} abandoned, the stake account no longer exists",
+                address
+            );
+            notifier.send(&msg).await;
+            println!("{}", msg);
+            db.remove_pending_redelegation(address)?;
+            continue;
+        }
+
+        if pending_redelegation.state == RedelegationState::Deactivating {
+            let stake_activation = rpc_client.get_stake_activation(address, None)?;
+            if stake_activation.state != StakeActivationState::Inactive {
+                println!(
+                    "{} cooling down since epoch {} for redelegation to {}, ◎{} remains active",
+                    address,
+                    pending_redelegation.epoch,
+                    pending_redelegation.vote_address,
+                    lamports_to_sol(stake_activation.active)
+                );
+                continue;
+            }
+            pending_redelegation.state = RedelegationState::Inactive;
+            db.set_pending_redelegation(pending_redelegation.clone())?;
+        }
+
+        match process_account_sync_redelegate_inactive_stake(
+            db,
+            rpc_client,
+            &pending_redelegation,
+            wallet_manager,
+        )
+        .await
+        {
+            Ok(new_address) => {
+                let msg = if new_address == address {
+                    format!(
+                        "Redelegated {} to {}",
+                        address, pending_redelegation.vote_address
+                    )
+                } else {
+                    format!(
+                        "Redelegated {} to {} via new stake account {}",
+                        address, pending_redelegation.vote_address, new_address
+                    )
+                };
+                notifier.send(&msg).await;
+                println!("{}", msg);
+                db.remove_pending_redelegation(address)?;
+            }
+            Err(err) => {
+                println!(
+                    "Redelegation of {} failed, it will be retried: {}",
+                    address, err
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// Returns the address of the delegated stake account
+async fn process_account_sync_redelegate_inactive_stake(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    pending_redelegation: &PendingRedelegation,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+) -> Result<Pubkey, Box<dyn std::error::Error>> {
+    let token = MaybeToken::SOL();
+    let PendingRedelegation {
+        address,
+        vote_address,
+        ref stake_authority,
+        new_account,
+        ..
+    } = *pending_redelegation;
+
+    let (stake_authority_signer, stake_authority_address) =
+        signer_from_uri(stake_authority, wallet_manager)?;
+
+    let transaction_pipeline = TransactionPipeline::new(rpc_client, db);
+
+    if !new_account {
+        let instructions = vec![solana_stake_program::stake_instruction::delegate_stake(
+            &address,
+            &stake_authority_address,
+            &vote_address,
+        )];
+        let signature = transaction_pipeline
+            .execute(
+                db,
+                TransactionPayload::Instructions {
+                    instructions: &instructions,
+                    payer: &stake_authority_address,
+                },
+                |transaction, recent_blockhash| {
+                    transaction.try_sign(&vec![stake_authority_signer.as_ref()], recent_blockhash)
+                },
+                None,
+            )
+            .await
+            .map_err(|err| format!("Delegation failed: {}", err))?;
+        if let Some(signature) = signature {
+            db.record_stake_change_signature(signature, db.get_reporting_timezone().today())?;
+        }
+        return Ok(address);
+    }
+
+    let tracked_stake_account = db
+        .get_account(address, token)
+        .ok_or_else(|| format!("Account {} ({}) does not exist", address, token))?;
+    let stake_account = rpc_client
+        .get_account_with_commitment(&address, rpc_client.commitment())?
+        .value
+        .ok_or_else(|| format!("Stake account, {}, does not exist", address))?;
+    let authorized = match stake_account.state() {
+        Ok(solana_stake_program::stake_state::StakeState::Initialized(meta))
+        | Ok(solana_stake_program::stake_state::StakeState::Stake(meta, _)) => meta.authorized,
+        _ => return Err(format!("Invalid stake account: {}", address).into()),
+    };
+
+    let new_stake_keypair = Keypair::new();
+    let instructions = vec![
+        solana_stake_program::stake_instruction::withdraw(
+            &address,
+            &stake_authority_address,
+            &new_stake_keypair.pubkey(),
+            stake_account.lamports,
+            None,
+        ),
+        system_instruction::allocate(
+            &new_stake_keypair.pubkey(),
+            std::mem::size_of::<solana_stake_program::stake_state::StakeState>() as u64,
+        ),
+        system_instruction::assign(&new_stake_keypair.pubkey(), &solana_stake_program::id()),
+        solana_stake_program::stake_instruction::initialize(
+            &new_stake_keypair.pubkey(),
+            &authorized,
+            &solana_stake_program::stake_state::Lockup::default(),
+        ),
+        solana_stake_program::stake_instruction::delegate_stake(
+            &new_stake_keypair.pubkey(),
+            &stake_authority_address,
+            &vote_address,
+        ),
+    ];
+    let payload = TransactionPayload::Instructions {
+        instructions: &instructions,
+        payer: &stake_authority_address,
+    };
+    // Simulate before tracking the new stake account
+    transaction_pipeline.prepare(&payload)?;
+
+    let epoch = rpc_client.get_epoch_info()?.epoch;
+    db.add_account(TrackedAccount {
+        address: new_stake_keypair.pubkey(),
+        token,
+        description: tracked_stake_account.description.clone(),
+        last_update_epoch: epoch.saturating_sub(1),
+        last_update_balance: 0,
+        lots: vec![],
+        no_sync: None,
+        last_update_signature: None,
+        last_stake_change_signature: None,
+        token_account: None,
+    })?;
+
+    let result = transaction_pipeline
+        .execute(
+            db,
+            payload,
+            |transaction, recent_blockhash| {
+                let signers: Vec<&dyn Signer> =
+                    vec![stake_authority_signer.as_ref(), &new_stake_keypair];
+                transaction.try_sign(&signers, recent_blockhash)
+            },
+            Some(Pending::new(
+                PendingKind::Transfer,
+                |db, signature, last_valid_block_height| {
+                    db.record_transfer(
+                        signature,
+                        last_valid_block_height,
+                        Some(
+                            stake_account
+                                .lamports
+                                .min(tracked_stake_account.last_update_balance),
+                        ),
+                        address,
+                        token,
+                        new_stake_keypair.pubkey(),
+                        token,
+                        LotSelectionMethod::default(),
+                        None,
+                    )?;
+                    // The old stake account is closed by the withdrawal
+                    db.remove_account_on_transfer_confirmation(signature)
+                },
+            )),
+        )
+        .await;
+    if let Err(err) = result {
+        db.remove_account(new_stake_keypair.pubkey(), token)?;
+        return Err(format!("Withdraw into new stake account failed: {}", err).into());
+    }
+    Ok(new_stake_keypair.pubkey())
+}
+
+async fn process_account_sync_deactivating_stakes(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    notifier: &Notifier,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for DeactivatingStake { address, epoch } in db.get_deactivating_stakes() {
+        if rpc_client
+            .get_account_with_commitment(&address, rpc_client.commitment())?
+            .value
+            .is_none()
+        {
+            db.remove_deactivating_stake(address)?;
+            continue;
+        }
+
+        let stake_activation = rpc_client.get_stake_activation(address, None)?;
+        if stake_activation.state == StakeActivationState::Inactive {
+            let msg = format!(
+                "Stake account {} is now inactive and can be withdrawn",
+                address
+            );
+            notifier.send(&msg).await;
+            println!("{}", msg);
+            db.remove_deactivating_stake(address)?;
+        } else {
+            println!(
+                "Stake account {} deactivating since epoch {}, ◎{} remains active",
+                address,
+                epoch,
+                lamports_to_sol(stake_activation.active)
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn process_account_sync_sweep(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+    _notifier: &Notifier,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let token = MaybeToken::SOL();
+
+    let transitory_sweep_stake_addresses = db.get_transitory_sweep_stake_addresses();
+    if transitory_sweep_stake_addresses.is_empty() {
+        return Ok(());
+    }
+
+    let sweep_stake_account_info = db
+        .get_sweep_stake_account()
+        .ok_or("Sweep stake account is not configured")?;
+
+    let (sweep_stake_account_authority_signer, sweep_stake_account_authority_address) =
+        signer_from_uri(&sweep_stake_account_info.stake_authority, wallet_manager)?;
+
+    let sweep_stake_account = rpc_client
+        .get_account_with_commitment(&sweep_stake_account_info.address, rpc_client.commitment())?
+        .value
+        .ok_or("Sweep stake account does not exist")?;
+
+    let sweep_stake_activation = rpc_client
+        .get_stake_activation(sweep_stake_account_info.address, None)
+        .map_err(|err| {
+            format!(
+                "Unable to get activation information for sweep stake account: {}: {}",
+                sweep_stake_account_info.address, err
+            )
+        })?;
+
+    if sweep_stake_activation.state != StakeActivationState::Active {
+        println!(
+            "Sweep stake account is not active, unable to continue: {:?}",
+            sweep_stake_activation
+        );
+        return Ok(());
+    }
+
+    for transitory_sweep_stake_address in transitory_sweep_stake_addresses {
+        println!(
+            "Considering merging transitory stake {}",
+            transitory_sweep_stake_address
+        );
+
+        let transitory_sweep_stake_account = match rpc_client
+            .get_account_with_commitment(&transitory_sweep_stake_address, rpc_client.commitment())?
+            .value
+        {
+            None => {
+                println!(
+                    "  Transitory sweep stake account does not exist, removing it: {}",
+                    transitory_sweep_stake_address
+                );
+
+                if let Some(tracked_account) = db.get_account(transitory_sweep_stake_address, token)
+                {
+                    if tracked_account.last_update_balance > 0 || !tracked_account.lots.is_empty() {
+                        panic!("Tracked account is not empty: {:?}", tracked_account);
+
+                        // TODO: Simulate a transfer to move the lots into the sweep account in
+                        // this case?
+                        /*
+                        let signature = Signature::default();
+                        db.record_transfer(
+                            signature,
+                            None,
+                            transitory_sweep_stake_address,
+                            sweep_stake_account_info.address,
+                            None,
+                        )?;
+                        db.confirm_transfer(signature)?;
+                        */
+                    }
+                }
+                db.remove_transitory_sweep_stake_address(transitory_sweep_stake_address)?;
+                continue;
+            }
+            Some(x) => x,
+        };
+
+        let transient_stake_activation = rpc_client
+            .get_stake_activation(transitory_sweep_stake_address, None)
+            .map_err(|err| {
+                format!(
+                    "Unable to get activation information for transient stake: {}: {}",
+                    transitory_sweep_stake_address, err
+                )
+            })?;
+
+        if transient_stake_activation.state != StakeActivationState::Active {
+            println!(
+                "  Transitory stake is not yet active: {:?}",
+                transient_stake_activation
+            );
+            continue;
+        }
+
+        if !rpc_client_utils::stake_accounts_have_same_credits_observed(
+            &sweep_stake_account,
+            &transitory_sweep_stake_account,
+        )? {
+            println!(
+                "  Transitory stake credits observed mismatch with sweep stake account: {}",
+                transitory_sweep_stake_address
+            );
+            continue;
+        }
+        println!("  Merging into sweep stake account");
+
+        let instructions = solana_stake_program::stake_instruction::merge(
+            &sweep_stake_account_info.address,
+            &transitory_sweep_stake_address,
+            &sweep_stake_account_authority_address,
+        );
+        let signature = TransactionPipeline::new(rpc_client, db)
+            .execute(
+                db,
+                TransactionPayload::Instructions {
+                    instructions: &instructions,
+                    payer: &sweep_stake_account_authority_address,
+                },
+                |transaction, recent_blockhash| {
+                    transaction.try_sign(
+                        &vec![sweep_stake_account_authority_signer.as_ref()],
+                        recent_blockhash,
+                    )
+                },
+                Some(Pending::new(
+                    PendingKind::Transfer,
+                    |db, signature, last_valid_block_height| {
+                        db.record_transfer(
+                            signature,
+                            last_valid_block_height,
+                            None,
+                            transitory_sweep_stake_address,
+                            token,
+                            sweep_stake_account_info.address,
+                            token,
+                            LotSelectionMethod::default(),
+                            None,
+                        )
+                    },
+                )),
+            )
+            .await
+            .map_err(|err| format!("Merge failed: {}", err))?;
+        if signature.is_some() {
+            db.remove_transitory_sweep_stake_address(transitory_sweep_stake_address)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_valid_token_or_sol(value: String) -> Result<(), String> {
+    if value == "SOL" {
+        Ok(())
+    } else {
+        is_valid_token(value)
+    }
+}
+
+fn lot_numbers_of(matches: &ArgMatches<'_>, name: &str) -> Option<HashSet<usize>> {
+    values_t!(matches, name, usize)
+        .ok()
+        .map(|x| x.into_iter().collect())
+}
+
+fn lot_numbers_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("lot_numbers")
+        .long("lot")
+        .value_name("LOT NUMBER")
+        .takes_value(true)
+        .multiple(true)
+        .validator(is_parsable::<usize>)
+        .help("Lot to fund the wrap from")
+}
+
+fn lot_selection_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("lot_selection")
+        .long("lot-selection")
+        .value_name("METHOD")
+        .takes_value(true)
+        .validator(is_parsable::<LotSelectionMethod>)
+        .default_value(POSSIBLE_LOT_SELECTION_METHOD_VALUES[0])
+        .possible_values(POSSIBLE_LOT_SELECTION_METHOD_VALUES)
+        .help("Lot selection method")
+}
+
+// `uri` is a keypair file, usb://ledger or prompt:// signer
+fn signer_from_uri(
+    uri: &str,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+) -> Result<(Box<dyn Signer>, Pubkey), Box<dyn std::error::Error>> {
+    let signer = solana_clap_utils::keypair::signer_from_path(
+        &ArgMatches::default(),
+        uri,
+        "authority",
+        wallet_manager,
+    )
+    .map_err(|err| format!("Failed to load signer {}: {}", uri, err))?;
+    let address = signer.pubkey();
+    Ok((signer, address))
+}
+
+// Authorities used by later invocations of `sys` are stored as signer URIs. Keypair file paths
+// are made absolute so that they don't depend on the working directory. prompt:// is refused as
+// those invocations may be unattended, such as `sys daemon`
+fn stored_signer_uri(uri: &str) -> Result<String, Box<dyn std::error::Error>> {
+    match solana_clap_utils::keypair::parse_signer_source(uri)?.kind {
+        SignerSourceKind::Filepath(path) => Ok(fs::canonicalize(&path)
+            .map_err(|err| format!("Failed to read {}: {}", path, err))?
+            .display()
+            .to_string()),
+        SignerSourceKind::Usb(_) => Ok(uri.to_string()),
+        _ => Err(format!("{} cannot be stored as an authority", uri).into()),
+    }
+}
+
+async fn process_plan_step(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    step: &PlanStep,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+    notifier: &Notifier,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match step {
+        PlanStep::Sweep {
+            address,
+            by,
+            retain,
+            to,
+            no_sweep_ok,
+            ..
+        } => {
+            let (authority_signer, authority_address) = signer_from_uri(by, wallet_manager)?;
+            process_account_sweep(
+                db,
+                rpc_client,
+                *address,
+                MaybeToken::SOL().amount(*retain),
+                *no_sweep_ok,
+                authority_address,
+                vec![authority_signer],
+                *to,
+                wallet_manager,
+                notifier,
+            )
+            .await
+        }
+        PlanStep::Transfer {
+            token,
+            from,
+            to,
+            amount,
+            by,
+            lot_selection,
+            ..
+        } => {
+            let (authority_signer, authority_address) = signer_from_uri(by, wallet_manager)?;
+            process_account_transfer(
+                db,
+                rpc_client,
+                *token,
+                *from,
+                *to,
+                amount.amount((*token).into())?,
+                lot_selection.clone(),
+                None,
+                authority_address,
+                vec![authority_signer],
+            )
+            .await
+        }
+        PlanStep::Wrap {
+            address,
+            amount,
+            by,
+            lot_selection,
+            ..
+        } => {
+            let (authority_signer, authority_address) = signer_from_uri(by, wallet_manager)?;
+            process_account_wrap(
+                db,
+                rpc_client,
+                *address,
+                MaybeToken::SOL().amount(*amount),
+                lot_selection.clone(),
+                None,
+                authority_address,
+                vec![authority_signer],
+            )
+            .await
+        }
+        PlanStep::Swap {
+            address,
+            from_token,
+            to_token,
+            amount,
+            slippage,
+            by,
+            lot_selection,
+            ..
+        } => {
+            let (signer, _) = signer_from_uri(by, wallet_manager)?;
+            process_jup_swap(
+                db,
+                rpc_client,
+                *address,
+                *from_token,
+                *to_token,
+                *amount,
+                *slippage,
+                lot_selection.clone(),
+                vec![signer],
+            )
+            .await?;
+
+            // The swap went through, should its sync fail `sys sync` completes it later
+            if let Err(err) = process_sync_swaps(db, rpc_client, notifier).await {
+                println!("Warning: Unable to sync the swap: {}", err);
+            }
+            Ok(())
+        }
+        PlanStep::Deposit {
+            exchange,
+            token,
+            from,
+            amount,
+            by,
+            lot_selection,
+            if_exchange_balance_less_than,
+            ..
+        } => {
+            let token = MaybeToken::from(*token);
+            let (authority_signer, authority_address) = signer_from_uri(by, wallet_manager)?;
+
+            let exchange_credentials = db
+                .get_exchange_credentials(*exchange)
+                .ok_or_else(|| format!("No API key set for {:?}", exchange))?;
+            let exchange_client =
+                exchange_client_new(*exchange, exchange_credentials, db.get_reporting_timezone())?;
+            let deposit_address = exchange_client.deposit_address(token).await?;
+            add_exchange_deposit_address_to_db(db, *exchange, token, deposit_address, rpc_client)?;
+
+            // `if_source_balance_exceeds` was already checked by `process_run()`
+            process_exchange_deposit(
+                db,
+                rpc_client,
+                *exchange,
+                exchange_client.as_ref(),
+                token,
+                deposit_address,
+                amount.amount(token)?,
+                *from,
+                None,
+                if_exchange_balance_less_than.map(|x| token.amount(x)),
+                authority_address,
+                vec![authority_signer],
+                lot_selection.clone(),
+                None,
+            )
+            .await?;
+
+            // The deposit went through, should its sync fail `sys sync` completes it later
+            if let Err(err) = process_sync_exchange(
+                db,
+                *exchange,
+                exchange_client.as_ref(),
+                rpc_client,
+                notifier,
+            )
+            .await
+            {
+                println!("Warning: Unable to sync {:?}: {}", exchange, err);
+            }
+            Ok(())
+        }
+    }
+}
+
+// Runs the steps of `plan_path` in order, stopping at the first failure. A rerun resumes after the
+// last completed step unless `restart` is set, which is required should the plan have been edited
+// since
+async fn process_run(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    plan_path: &Path,
+    restart: bool,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+    notifier: &Notifier,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (plan, contents) = Plan::load(plan_path)?;
+    let plan_path = fs::canonicalize(plan_path)?;
+    let plan_hash = hash(&contents);
+
+    let mut completed_steps = 0;
+    if let Some(plan_progress) = db.get_plan_progress(&plan_path) {
+        if restart {
+            println!("Restarting {}", plan_path.display());
+        } else if plan_progress.plan_hash != plan_hash {
+            return Err(format!(
+                "{} changed since it stopped after step {}. Rerun with --restart to start from \
+                 the first step",
+                plan_path.display(),
+                plan_progress.completed_steps
+            )
+            .into());
+        } else {
+            completed_steps = plan_progress.completed_steps;
+            println!(
+                "Resuming {} after step {}",
+                plan_path.display(),
+                completed_steps
+            );
+        }
+    }
+
+    for (index, step) in plan.steps.iter().enumerate().skip(completed_steps) {
+        println!(
+            "Step {}/{}: {}",
+            index + 1,
+            plan.steps.len(),
+            step.describe()
+        );
+
+        let (source_address, source_token) = step.source();
+        let source_balance_exceeded = match step.if_source_balance_exceeds() {
+            None => true,
+            Some(if_source_balance_exceeds) => {
+                let balance_address = db
+                    .get_account(source_address, source_token)
+                    .map(|account| account.balance_address())
+                    .unwrap_or_else(|| match source_token.token() {
+                        Some(token) => token.ata(&source_address),
+                        None => source_address,
+                    });
+                let balance = source_token.balance_at(rpc_client, &balance_address)?;
+                let if_source_balance_exceeds = source_token.amount(if_source_balance_exceeds);
+                if balance < if_source_balance_exceeds {
+                    println!(
+                        "Skipped because {} balance is less than {}{}",
+                        source_address,
+                        source_token.symbol(),
+                        source_token.ui_amount(if_source_balance_exceeds)
+                    );
+                }
+                balance >= if_source_balance_exceeds
+            }
+        };
+
+        if source_balance_exceeded {
+            process_plan_step(db, rpc_client, step, wallet_manager, notifier)
+                .await
+                .map_err(|err| {
+                    format!(
+                        "Step {} failed: {}. Rerun `sys run` to resume from it",
+                        index + 1,
+                        err
+                    )
+                })?;
+        }
+        db.set_plan_progress(
+            &plan_path,
+            Some(PlanProgress {
+                plan_hash,
+                completed_steps: index + 1,
+            }),
+        )?;
+    }
+
+    db.set_plan_progress(&plan_path, None)?;
+    println!("All {} steps completed", plan.steps.len());
+    Ok(())
+}
+
+async fn process_sync(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+    notifier: &Notifier,
+) -> Result<(), Box<dyn std::error::Error>> {
+    process_sync_swaps(db, rpc_client, notifier).await?;
+    for (exchange, exchange_credentials) in db.get_configured_exchanges() {
+        println!("Synchronizing {:?}...", exchange);
+        let exchange_client =
+            exchange_client_new(exchange, exchange_credentials, db.get_reporting_timezone())?;
+        process_sync_exchange(db, exchange, exchange_client.as_ref(), rpc_client, notifier).await?
+    }
+    process_account_sync(db, rpc_client, None, wallet_manager, notifier).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn process_sell_ladder(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    exchange: Exchange,
+    pair: &str,
+    rungs: &[SellLadderRung],
+    if_balance_exceeds: Option<f64>,
+    lot_selection_method: &LotSelectionMethod,
+    notifier: &Notifier,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let token = MaybeToken::SOL();
+    let exchange_credentials = db
+        .get_exchange_credentials(exchange)
+        .ok_or_else(|| format!("No API key set for {:?}", exchange))?;
+    let exchange_client =
+        exchange_client_new(exchange, exchange_credentials, db.get_reporting_timezone())?;
+    process_sync_exchange(db, exchange, exchange_client.as_ref(), rpc_client, notifier).await?;
+
+    // Sell prices are rounded to two decimal places when placed
+    let price_cents = |price: f64| (price * 100.).round() as u64;
+    let open_orders_at = |db: &Db, price_cents: u64| {
+        db.open_orders(Some(exchange), Some(OrderSide::Sell))
+            .into_iter()
+            .filter(|order| order.pair == pair && price_cents(order.price) == price_cents)
+            .map(|order| order.order_id)
+            .collect::<HashSet<_>>()
+    };
+
+    for rung in rungs {
+        let rung_price_cents = price_cents(rung.price);
+        let price = rung_price_cents as f64 / 100.;
+        let amount = token.amount(rung.amount);
+        let open_order_ids = open_orders_at(db, rung_price_cents);
+
+        // Closing the order of a rung removes its record if cancelled, or marks it filled
+        let placed = db
+            .get_placed_sell_ladder_rungs()
+            .into_iter()
+            .find(|placed| {
+                placed.exchange == exchange
+                    && placed.pair == pair
+                    && placed.price_cents == rung_price_cents
+                    && placed.amount == amount
+            });
+        match placed {
+            Some(placed) if placed.filled => {
+                if !rung.repeat {
+                    continue;
+                }
+                db.remove_placed_sell_ladder_rung(&placed.order_id)?;
+            }
+            Some(placed) if open_order_ids.contains(&placed.order_id) => continue,
+            Some(placed) => {
+                // The order is gone without `sys` closing it
+                db.remove_placed_sell_ladder_rung(&placed.order_id)?;
+            }
+            None => {}
+        }
+
+        if let Some(order_id) = open_order_ids.into_iter().next() {
+            // Adopt an order placed before rungs were recorded
+            db.add_placed_sell_ladder_rung(PlacedSellLadderRung {
+                exchange,
+                pair: pair.to_string(),
+                price_cents: rung_price_cents,
+                amount,
+                order_id,
+                filled: false,
+            })?;
+            continue;
+        }
+
+        process_exchange_sell(
+            db,
+            exchange,
+            exchange_client.as_ref(),
+            token,
+            pair.to_string(),
+            rung.amount,
+            LimitOrderPrice::At(price),
+            if_balance_exceeds.map(|x| token.amount(x)),
+            None,
+            false,
+            None,
+            lot_selection_method.clone(),
+            None,
+            notifier,
+        )
+        .await?;
+
+        // No order is placed while the balance is below `if_balance_exceeds`
+        if let Some(order_id) = open_orders_at(db, rung_price_cents).into_iter().next() {
+            db.add_placed_sell_ladder_rung(PlacedSellLadderRung {
+                exchange,
+                pair: pair.to_string(),
+                price_cents: rung_price_cents,
+                amount,
+                order_id,
+                filled: false,
+            })?;
+        }
+    }
+    process_sync_exchange(db, exchange, exchange_client.as_ref(), rpc_client, notifier).await
+}
+
+async fn process_daemon_job(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    job: &DaemonJob,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+    notifier: &Notifier,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match &job.action {
+        JobAction::Sync => process_sync(db, rpc_client, wallet_manager, notifier).await,
+        JobAction::Plan { plan } => {
+            process_run(db, rpc_client, plan, false, wallet_manager, notifier).await
+        }
+        JobAction::SellLadder {
+            exchange,
+            pair,
+            rungs,
+            if_balance_exceeds,
+            lot_selection,
+        } => {
+            process_sell_ladder(
+                db,
+                rpc_client,
+                *exchange,
+                pair,
+                rungs,
+                *if_balance_exceeds,
+                lot_selection,
+                notifier,
+            )
+            .await
+        }
+    }
+}
+
+// Detects when the inflation rewards of the last completed epoch become available, which is once
+// the first block of the following epoch is confirmed
+struct EpochWatcher {
+    epoch_schedule: EpochSchedule,
+    rewarded_epoch: Epoch,
+}
+
+impl EpochWatcher {
+    // Rewards up to the epoch before the current one are assumed to be available already
+    fn new(rpc_client: &RpcClient) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            epoch_schedule: rpc_client.get_epoch_schedule()?,
+            rewarded_epoch: rpc_client.get_epoch_info()?.epoch.saturating_sub(1),
+        })
+    }
+
+    // Returns the epoch whose rewards became available, if any. It's returned again by the next
+    // poll until `set_rewarded()` is called
+    fn poll(&self, rpc_client: &RpcClient) -> Result<Option<Epoch>, Box<dyn std::error::Error>> {
+        let completed_epoch = rpc_client.get_epoch_info()?.epoch.saturating_sub(1);
+        if completed_epoch <= self.rewarded_epoch {
+            return Ok(None);
+        }
+
+        let first_slot = self
+            .epoch_schedule
+            .get_first_slot_in_epoch(completed_epoch + 1);
+        if rpc_client.get_blocks_with_limit(first_slot, 1)?.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(completed_epoch))
+    }
+
+    fn set_rewarded(&mut self, epoch: Epoch) {
+        self.rewarded_epoch = epoch;
+    }
+}
+
+// Total and per account inflation rewards of `epoch`, as recorded by `sys account sync`. Reward
+// lots disposed of since, such as by a filled sell order, are listed separately
+fn epoch_rewards_summary(db: &Db, epoch: Epoch) -> String {
+    let base_currency = db.get_base_currency();
+    let token = MaybeToken::SOL();
+
+    // Amount and value of the rewards among `lots`
+    let rewards_of = |lots: &[Lot]| {
+        lots.iter()
+            .filter(|lot| {
+                matches!(
+                    lot.acquisition.kind,
+                    LotAcquistionKind::EpochReward { epoch: lot_epoch, .. } if lot_epoch == epoch
+                )
+            })
+            .fold((0, 0.), |(amount, value), lot| {
+                (
+                    amount + lot.amount,
+                    value
+                        + lot.acquisition.price().to_f64().unwrap_or_default()
+                            * token.ui_amount(lot.amount),
+                )
+            })
+    };
+
+    let mut total_amount = 0;
+    let mut total_value = 0.;
+    let mut account_summaries = vec![];
+    for account in db.get_accounts() {
+        let (amount, value) = rewards_of(&account.lots);
+        if amount == 0 {
+            continue;
+        }
+        total_amount += amount;
+        total_value += value;
+        account_summaries.push(format!(
+            "  {} ({}): {}{} ({}{:.2})",
+            account.address,
+            account.description,
+            token.symbol(),
+            token.ui_amount(amount),
+            base_currency.symbol(),
+            value
+        ));
+    }
+
+    let disposed_lots = db
+        .disposed_lots()
+        .into_iter()
+        .filter(|disposed_lot| disposed_lot.token.is_sol())
+        .map(|disposed_lot| disposed_lot.lot)
+        .collect::<Vec<_>>();
+    let (amount, value) = rewards_of(&disposed_lots);
+    if amount > 0 {
+        total_amount += amount;
+        total_value += value;
+        account_summaries.push(format!(
+            "  Disposed: {}{} ({}{:.2})",
+            token.symbol(),
+            token.ui_amount(amount),
+            base_currency.symbol(),
+            value
+        ));
+    }
+
+    let mut summary = format!(
+        "Epoch {} rewards: {}{} ({}{:.2})",
+        epoch,
+        token.symbol(),
+        token.ui_amount(total_amount),
+        base_currency.symbol(),
+        total_value
+    );
+    for account_summary in account_summaries {
+        summary += "\n";
+        summary += &account_summary;
+    }
+    summary
+}
+
+async fn process_epoch_boundary(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    epoch: Epoch,
+    epoch_boundary: &EpochBoundary,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+    notifier: &Notifier,
+) -> Result<(), Box<dyn std::error::Error>> {
+    process_account_sync(db, rpc_client, None, wallet_manager, notifier).await?;
+
+    let summary = epoch_rewards_summary(db, epoch);
+    println!("{}", summary);
+    notifier.send(&summary).await;
+
+    for hook in &epoch_boundary.hooks {
+        println!("Running hook: {}", hook);
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(hook)
+            .env("SYS_EPOCH", epoch.to_string())
+            .status()?;
+        if !status.success() {
+            let msg = format!("Epoch {} hook failed ({}): {}", epoch, status, hook);
+            eprintln!("{}", msg);
+            notifier.send(&msg).await;
+        }
+    }
+    Ok(())
+}
+
+// With `--ws`, pending transactions and tracked accounts are subscribed to so that `sync` jobs
+// run as soon as one of them changes, rather than at their next interval. Validator identities
+// and fee payers are left out, vote fees change them every slot
+#[derive(Default)]
+struct ChangeWatcher {
+    signatures: HashMap<Signature, Subscription<SignatureResult>>,
+    accounts: HashMap<Pubkey, Subscription<()>>,
+}
+
+impl ChangeWatcher {
+    // Subscribes to new pending transactions and tracked accounts, and drops stale subscriptions
+    fn refresh(&mut self, db: &Db, pubsub: &dyn Pubsub, commitment: CommitmentConfig) {
+        let signatures = db
+            .pending_deposits(None)
+            .into_iter()
+            .map(|pending_deposit| pending_deposit.transfer.signature)
+            .chain(db.pending_swaps().into_iter().map(|ps| ps.signature))
+            .chain(db.pending_transfers().into_iter().map(|pt| pt.signature))
+            .collect::<HashSet<_>>();
+        let fee_payers = db.get_fee_payers();
+        let validator_identities = db
+            .get_validator_identities()
+            .into_iter()
+            .map(|vi| vi.address)
+            .collect::<HashSet<_>>();
+        let accounts = db
+            .get_accounts()
+            .into_iter()
+            .filter(|account| {
+                !account.no_sync.unwrap_or_default()
+                    && !fee_payers.contains(&account.address)
+                    && !validator_identities.contains(&account.address)
+            })
+            .map(|account| account.balance_address())
+            .collect::<HashSet<_>>();
+
+        self.signatures
+            .retain(|signature, _| signatures.contains(signature));
+        for signature in signatures {
+            if !self.signatures.contains_key(&signature) {
+                match pubsub.signature_subscribe(&signature, commitment) {
+                    Ok(subscription) => {
+                        self.signatures.insert(signature, subscription);
+                    }
+                    Err(err) => eprintln!("Failed to subscribe to {}: {}", signature, err),
+                }
+            }
+        }
+
+        self.accounts
+            .retain(|address, _| accounts.contains(address));
+        for address in accounts {
+            if !self.accounts.contains_key(&address) {
+                match pubsub.account_subscribe(&address, commitment) {
+                    Ok(subscription) => {
+                        self.accounts.insert(address, subscription);
+                    }
+                    Err(err) => eprintln!("Failed to subscribe to {}: {}", address, err),
+                }
+            }
+        }
+    }
+
+    // Whether any subscription was notified since the last call
+    fn changed(&self) -> bool {
+        let mut changed = false;
+        for subscription in self.signatures.values() {
+            while subscription.try_recv().is_some() {
+                changed = true;
+            }
+        }
+        for subscription in self.accounts.values() {
+            while subscription.try_recv().is_some() {
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+// Resolves on Ctrl-C, or SIGTERM where available
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+const MIN_CHANGE_SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+// Runs the jobs of `config_path` on their schedules until shutdown. Interval jobs first run at
+// startup, epoch boundary jobs once the rewards of the completed epoch are available. A shutdown
+// request lets the running job complete first
+async fn process_daemon(
+    db: &mut Db,
+    rpc_client: &RpcClient,
+    config_path: &Path,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+    notifier: &Notifier,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = DaemonConfig::load(config_path)?;
+    if config.jobs.is_empty() && config.epoch_boundary.is_none() {
+        return Err(format!("No jobs in {}", config_path.display()).into());
+    }
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            println!("Shutdown requested");
+            shutdown.store(true, Ordering::Relaxed);
+        }
+    });
+
+    let poll_interval = Duration::from_secs(15);
+    let mut epoch_watcher = EpochWatcher::new(rpc_client)?;
+    let mut next_runs = config
+        .jobs
+        .iter()
+        .map(|_| Instant::now())
+        .collect::<Vec<_>>();
+    let mut change_watcher = pubsub::get().map(|_| ChangeWatcher::default());
+    let mut changed = false;
+    let mut last_change_sync: Option<Instant> = None;
+    // Changes run `sync` jobs at most once per `MIN_CHANGE_SYNC_INTERVAL`
+    let change_sync_allowed = |last_change_sync: Option<Instant>| {
+        last_change_sync.map_or(true, |last_change_sync| {
+            last_change_sync.elapsed() >= MIN_CHANGE_SYNC_INTERVAL
+        })
+    };
+    let mut failed_epoch = None; // Reported already
+    println!(
+        "Daemon started with {} jobs, epoch {} rewarded",
+        config.jobs.len(),
+        epoch_watcher.rewarded_epoch
+    );
+
+    'daemon: loop {
+        let rewarded_epoch = match epoch_watcher.poll(rpc_client) {
+            Ok(rewarded_epoch) => rewarded_epoch,
+            Err(err) => {
+                eprintln!("Failed to check for epoch rewards: {}", err);
+                None
+            }
         };
 
-        let transient_stake_activation = rpc_client
-            .get_stake_activation(transitory_sweep_stake_address, None)
-            .map_err(|err| {
-                format!(
-                    "Unable to get activation information for transient stake: {}: {}",
-                    transitory_sweep_stake_address, err
-                )
-            })?;
-
-        if transient_stake_activation.state != StakeActivationState::Active {
-            println!(
-                "  Transitory stake is not yet active: {:?}",
-                transient_stake_activation
-            );
-            continue;
+        // A failed epoch boundary is retried at the next poll, and its epoch boundary jobs wait
+        // for it to succeed
+        let mut epoch_boundary = false;
+        if let Some(rewarded_epoch) = rewarded_epoch {
+            let result = match &config.epoch_boundary {
+                Some(epoch_boundary) => {
+                    println!("Epoch {} rewards available", rewarded_epoch);
+                    process_epoch_boundary(
+                        db,
+                        rpc_client,
+                        rewarded_epoch,
+                        epoch_boundary,
+                        wallet_manager,
+                        notifier,
+                    )
+                    .await
+                }
+                None => Ok(()),
+            };
+            match result {
+                Ok(()) => {
+                    epoch_watcher.set_rewarded(rewarded_epoch);
+                    epoch_boundary = true;
+                }
+                Err(err) => {
+                    let msg = format!("Epoch {} processing failed: {}", rewarded_epoch, err);
+                    eprintln!("{}", msg);
+                    if failed_epoch != Some(rewarded_epoch) {
+                        notifier.send(&msg).await;
+                        failed_epoch = Some(rewarded_epoch);
+                    }
+                }
+            }
         }
 
-        if !rpc_client_utils::stake_accounts_have_same_credits_observed(
-            &sweep_stake_account,
-            &transitory_sweep_stake_account,
-        )? {
-            println!(
-                "  Transitory stake credits observed mismatch with sweep stake account: {}",
-                transitory_sweep_stake_address
-            );
-            continue;
+        let change_sync = changed && change_sync_allowed(last_change_sync);
+        if change_sync {
+            last_change_sync = Some(Instant::now());
+            changed = false;
         }
-        println!("  Merging into sweep stake account");
 
-        let message = Message::new(
-            &solana_stake_program::stake_instruction::merge(
-                &sweep_stake_account_info.address,
-                &transitory_sweep_stake_address,
-                &sweep_stake_account_authority_keypair.pubkey(),
-            ),
-            Some(&sweep_stake_account_authority_keypair.pubkey()),
-        );
-        let mut transaction = Transaction::new_unsigned(message);
+        for (job, next_run) in config.jobs.iter().zip(next_runs.iter_mut()) {
+            if shutdown.load(Ordering::Relaxed) {
+                break 'daemon;
+            }
 
-        let (recent_blockhash, last_valid_block_height) =
-            rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
+            let interval_due = job.every_minutes.is_some()
+                && (Instant::now() >= *next_run
+                    || (change_sync && matches!(job.action, JobAction::Sync)));
+            if !interval_due && !(job.at_epoch_boundary && epoch_boundary) {
+                continue;
+            }
+            if let Some(every_minutes) = job.every_minutes {
+                *next_run = Instant::now() + Duration::from_secs(every_minutes * 60);
+            }
 
-        transaction.message.recent_blockhash = recent_blockhash;
-        let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
-        if simulation_result.err.is_some() {
-            return Err(format!("Simulation failure: {:?}", simulation_result).into());
+            println!("Running job \"{}\"", job.name);
+            match process_daemon_job(db, rpc_client, job, wallet_manager, notifier).await {
+                Ok(()) => {
+                    println!("Job \"{}\" succeeded", job.name);
+                    if job.notify_on_success {
+                        notifier
+                            .send(&format!("Job \"{}\" succeeded", job.name))
+                            .await;
+                    }
+                }
+                Err(err) => {
+                    let msg = format!("Job \"{}\" failed: {}", job.name, err);
+                    eprintln!("{}", msg);
+                    notifier.send(&msg).await;
+                }
+            }
         }
 
-        transaction.sign(&[&sweep_stake_account_authority_keypair], recent_blockhash);
-
-        let signature = transaction.signatures[0];
-        println!("Transaction signature: {}", signature);
-        db.record_transfer(
-            signature,
-            last_valid_block_height,
-            None,
-            transitory_sweep_stake_address,
-            token,
-            sweep_stake_account_info.address,
-            token,
-            LotSelectionMethod::default(),
-            None,
-        )?;
+        if let (Some(change_watcher), Some(pubsub)) = (&mut change_watcher, pubsub::get()) {
+            change_watcher.refresh(db, pubsub, rpc_client.commitment());
+        }
 
-        if !send_transaction_until_expired(rpc_client, &transaction, last_valid_block_height) {
-            db.cancel_transfer(signature)?;
-            return Err("Merge failed".into());
+        let sleep_until = Instant::now() + poll_interval;
+        while Instant::now() < sleep_until {
+            if shutdown.load(Ordering::Relaxed) {
+                break 'daemon;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            if let Some(change_watcher) = &change_watcher {
+                if change_watcher.changed() && !changed {
+                    println!("Change detected");
+                    changed = true;
+                }
+            }
+            if changed && change_sync_allowed(last_change_sync) {
+                break;
+            }
         }
-        let when = get_signature_date(rpc_client, signature).await?;
-        db.confirm_transfer(signature, when)?;
-        db.remove_transitory_sweep_stake_address(transitory_sweep_stake_address)?;
     }
+
+    println!("Daemon stopped");
     Ok(())
 }
 
-fn is_valid_token_or_sol(value: String) -> Result<(), String> {
-    if value == "SOL" {
-        Ok(())
-    } else {
-        is_valid_token(value)
+// Prints how the lots of the throwaway `--dry-run` database differ from `baseline`
+fn process_dry_run_report(baseline: &Db, db: &Db) {
+    println!();
+    println!("Dry run, no changes were saved. Lot changes:");
+    let changes = dry_run_changes(baseline, db);
+    if changes.is_empty() {
+        println!("  None");
+    }
+    for change in changes {
+        println!("  {}", change);
     }
 }
 
-fn lot_numbers_of(matches: &ArgMatches<'_>, name: &str) -> Option<HashSet<usize>> {
-    values_t!(matches, name, usize)
-        .ok()
-        .map(|x| x.into_iter().collect())
-}
+fn dry_run_changes(baseline: &Db, db: &Db) -> Vec<String> {
+    let lots_of = |db: &Db| {
+        db.get_accounts()
+            .into_iter()
+            .flat_map(|account| {
+                let TrackedAccount {
+                    address,
+                    token,
+                    lots,
+                    ..
+                } = account;
+                lots.into_iter()
+                    .map(move |lot| (lot.lot_number, (address, token, lot.amount)))
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+    let lots = lots_of(db);
+    let baseline_lots = lots_of(baseline);
+
+    let mut changes = vec![];
+    for (lot_number, (address, token, amount)) in &baseline_lots {
+        match lots.get(lot_number) {
+            None => changes.push(format!(
+                "Lot {}: {}{} removed from {} ({})",
+                lot_number,
+                token.symbol(),
+                token.ui_amount(*amount),
+                address,
+                token
+            )),
+            Some(lot) if lot != &(*address, *token, *amount) => {
+                let (new_address, new_token, new_amount) = lot;
+                changes.push(format!(
+                    "Lot {}: {}{} in {} ({}) -> {}{} in {} ({})",
+                    lot_number,
+                    token.symbol(),
+                    token.ui_amount(*amount),
+                    address,
+                    token,
+                    new_token.symbol(),
+                    new_token.ui_amount(*new_amount),
+                    new_address,
+                    new_token
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+    for (lot_number, (address, token, amount)) in &lots {
+        if !baseline_lots.contains_key(lot_number) {
+            changes.push(format!(
+                "Lot {}: {}{} added to {} ({})",
+                lot_number,
+                token.symbol(),
+                token.ui_amount(*amount),
+                address,
+                token
+            ));
+        }
+    }
 
-fn lot_numbers_arg<'a, 'b>() -> Arg<'a, 'b> {
-    Arg::with_name("lot_numbers")
-        .long("lot")
-        .value_name("LOT NUMBER")
-        .takes_value(true)
-        .multiple(true)
-        .validator(is_parsable::<usize>)
-        .help("Lot to fund the wrap from")
-}
+    let baseline_disposed_lots = baseline
+        .disposed_lots()
+        .into_iter()
+        .map(|disposed_lot| disposed_lot.lot.lot_number)
+        .collect::<HashSet<_>>();
+    for disposed_lot in db.disposed_lots() {
+        if !baseline_disposed_lots.contains(&disposed_lot.lot.lot_number) {
+            changes.push(format!(
+                "Lot {}: {}{} disposed on {}: {}",
+                disposed_lot.lot.lot_number,
+                disposed_lot.token.symbol(),
+                disposed_lot.token.ui_amount(disposed_lot.lot.amount),
+                disposed_lot.when,
+                disposed_lot.kind
+            ));
+        }
+    }
 
-fn lot_selection_arg<'a, 'b>() -> Arg<'a, 'b> {
-    Arg::with_name("lot_selection")
-        .long("lot-selection")
-        .value_name("METHOD")
-        .takes_value(true)
-        .validator(is_parsable::<LotSelectionMethod>)
-        .default_value(POSSIBLE_LOT_SELECTION_METHOD_VALUES[0])
-        .possible_values(POSSIBLE_LOT_SELECTION_METHOD_VALUES)
-        .help("Lot selection method")
+    let pending = |db: &Db| {
+        db.pending_deposits(None).len()
+            + db.pending_swaps().len()
+            + db.pending_withdrawals(None).len()
+            + db.pending_transfers().len()
+            + db.open_orders(None, None).len()
+    };
+    let new_pending = pending(db).saturating_sub(pending(baseline));
+    if new_pending > 0 {
+        changes.push(format!("{} pending operation(s) recorded", new_pending));
+    }
+    changes
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     solana_logger::setup_with_default("solana=info");
     let default_db_path = "sell-your-sol";
+
+    // The token registry must be loaded before argument parsing so that token arguments may name
+    // registry entries
+    {
+        let mut args = std::env::args().skip(1);
+        let mut db_path = PathBuf::from(default_db_path);
+        while let Some(arg) = args.next() {
+            if arg == "--db-path" {
+                if let Some(path) = args.next() {
+                    db_path = PathBuf::from(path);
+                }
+            } else if let Some(path) = arg.strip_prefix("--db-path=") {
+                db_path = PathBuf::from(path);
+            }
+        }
+        let builtin_names = BUILTIN_TOKENS
+            .iter()
+            .map(|token| token.name())
+            .chain(std::iter::once("SOL"))
+            .collect::<Vec<_>>();
+        let builtin_mints = BUILTIN_TOKENS
+            .iter()
+            .map(|token| token.mint())
+            .collect::<Vec<_>>();
+        token_registry::load(
+            &db_path.join("token-registry.json"),
+            &builtin_names,
+            &builtin_mints,
+        )?;
+    }
     let default_json_rpc_url = "https://api.mainnet-beta.solana.com";
     let default_when = {
         let today = Local::now().date();
@@ -3498,6 +6861,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .default_value(default_json_rpc_url)
                 .help("JSON RPC URL for the cluster"),
         )
+        .arg(
+            Arg::with_name("websocket_url")
+                .long("ws")
+                .value_name("URL")
+                .takes_value(true)
+                .global(true)
+                .validator(is_url)
+                .help("WebSocket URL for the cluster. Transaction confirmations and `sys daemon` \
+                       changes are then received through subscriptions, with polling as \
+                       fallback"),
+        )
         .arg(
             Arg::with_name("verbose")
                 .short("v")
@@ -3506,41 +6880,325 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .global(true)
                 .help("Show additional information"),
         )
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .takes_value(false)
+                .global(true)
+                .conflicts_with("sign_only")
+                .help("Run against a throwaway copy of the database without sending \
+                       transactions or exchange orders, and print the resulting lot changes"),
+        )
+        .arg(
+            Arg::with_name("sign_only")
+                .long("sign-only")
+                .value_name("FILE")
+                .takes_value(true)
+                .global(true)
+                .help("Write the transaction to FILE for offline signing or multisig execution \
+                       instead of sending it. Signers may then be given by public key. \
+                       Requires --nonce or --multisig-proposal"),
+        )
+        .arg(
+            Arg::with_name("nonce")
+                .long("nonce")
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .global(true)
+                .requires("sign_only")
+                .validator(is_valid_pubkey)
+                .help("Durable nonce account providing the blockhash of a --sign-only transaction"),
+        )
+        .arg(
+            Arg::with_name("multisig_proposal")
+                .long("multisig-proposal")
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .global(true)
+                .requires("sign_only")
+                .conflicts_with("nonce")
+                .validator(is_valid_pubkey)
+                .help("Multisig proposal account that will execute the instructions of a \
+                       --sign-only command. FILE receives the proposal payload"),
+        )
+        .subcommand(
+            SubCommand::with_name("price")
+                .about("Get token price")
+                .arg(
+                    Arg::with_name("token")
+                        .value_name("SOL or SPL Token")
+                        .takes_value(true)
+                        .required(true)
+                        .validator(is_valid_token_or_sol)
+                        .default_value("SOL")
+                        .help("Token type"),
+                )
+                .arg(
+                    Arg::with_name("when")
+                        .value_name("YY/MM/DD")
+                        .takes_value(true)
+                        .required(false)
+                        .validator(|value| naivedate_of(&value).map(|_| ()))
+                        .help("Date to fetch the price for [default: current spot price]"),
+                )
+                .subcommand(
+                    SubCommand::with_name("history")
+                        .about("Display daily token prices over a date range")
+                        .arg(
+                            Arg::with_name("token")
+                                .value_name("SOL or SPL Token")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_valid_token_or_sol)
+                                .default_value("SOL")
+                                .help("Token type"),
+                        )
+                        .arg(
+                            Arg::with_name("from")
+                                .long("from")
+                                .value_name("YY/MM/DD")
+                                .takes_value(true)
+                                .validator(|value| naivedate_of(&value).map(|_| ()))
+                                .help("First date, inclusive [default: 30 days before --to]"),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .long("to")
+                                .value_name("YY/MM/DD")
+                                .takes_value(true)
+                                .validator(|value| naivedate_of(&value).map(|_| ()))
+                                .help("Last date, inclusive [default: today]"),
+                        )
+                        .arg(
+                            Arg::with_name("chart")
+                                .long("chart")
+                                .takes_value(false)
+                                .help("Also display the prices as a sparkline"),
+                        )
+                        .arg(
+                            Arg::with_name("csv")
+                                .long("csv")
+                                .value_name("FILEPATH")
+                                .takes_value(true)
+                                .help("Write the daily prices to a CSV file"),
+                        )
+                )
+        )
+        .subcommand(SubCommand::with_name("sync").about("Synchronize with all exchanges and accounts"))
+        .subcommand(
+            SubCommand::with_name("report")
+                .about("Reports")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .setting(AppSettings::InferSubcommands)
+                .subcommand(
+                    SubCommand::with_name("validator")
+                        .about("Display the per-epoch economics of a validator")
+                        .arg(
+                            Arg::with_name("vote_address")
+                                .value_name("VOTE_ADDRESS")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_valid_pubkey)
+                                .help("Vote account address"),
+                        )
+                        .arg(
+                            Arg::with_name("epochs")
+                                .long("epochs")
+                                .value_name("COUNT")
+                                .takes_value(true)
+                                .default_value("10")
+                                .validator(is_parsable::<u64>)
+                                .help("Number of completed epochs to report"),
+                        )
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("db")
+                .about("Database management")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .setting(AppSettings::InferSubcommands)
+                .subcommand(
+                    SubCommand::with_name("import")
+                        .about("Import another database")
+                        .arg(
+                            Arg::with_name("other_db_path")
+                                .value_name("PATH")
+                                .takes_value(true)
+                                .help("Path to the database to import"),
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("price-precision")
+                        .about("Show or set the precision used to price new lots")
+                        .arg(
+                            Arg::with_name("price_precision")
+                                .value_name("PRECISION")
+                                .takes_value(true)
+                                .possible_values(POSSIBLE_PRICE_PRECISION_VALUES)
+                                .help("Price lots by the block date (day) or the block time \
+                                       truncated to the hour or minute [default: show current precision]"),
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("transaction-settings")
+                        .about("Show or set how transactions are submitted")
+                        .arg(
+                            Arg::with_name("priority_fee")
+                                .long("priority-fee")
+                                .value_name("MICRO_LAMPORTS")
+                                .takes_value(true)
+                                .validator(is_parsable::<u64>)
+                                .help("Priority fee per compute unit, in micro-lamports. \
+                                       The compute unit limit is estimated by simulation"),
+                        )
+                        .arg(
+                            Arg::with_name("rebroadcast_interval")
+                                .long("rebroadcast-interval")
+                                .value_name("SECONDS")
+                                .takes_value(true)
+                                .validator(is_parsable::<u64>)
+                                .help("Seconds between broadcasts of a pending transaction"),
+                        )
+                        .arg(
+                            Arg::with_name("max_blockhash_refreshes")
+                                .long("max-blockhash-refreshes")
+                                .value_name("COUNT")
+                                .takes_value(true)
+                                .validator(is_parsable::<u32>)
+                                .help("Number of times an expired transaction is retried with a new blockhash"),
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("timezone")
+                        .about("Show or set the timezone used to date lots")
+                        .arg(
+                            Arg::with_name("timezone")
+                                .value_name("TIMEZONE")
+                                .takes_value(true)
+                                .validator(is_parsable::<ReportingTimezone>)
+                                .help("IANA timezone name, such as America/Los_Angeles, or \
+                                       \"local\" for the system timezone. Lots with dates \
+                                       that would change are reported first \
+                                       [default: show current timezone]"),
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("currency")
+                        .about("Show or set the base currency used to price lots and reports")
+                        .arg(
+                            Arg::with_name("currency")
+                                .value_name("CURRENCY")
+                                .takes_value(true)
+                                .possible_values(POSSIBLE_CURRENCY_VALUES)
+                                .help("Base currency. Can only be changed before any lots exist \
+                                       [default: show current base currency]"),
+                        )
+                        .arg(
+                            Arg::with_name("fx_rate_source")
+                                .long("fx-source")
+                                .value_name("SOURCE")
+                                .takes_value(true)
+                                .possible_values(POSSIBLE_FX_RATE_SOURCE_VALUES)
+                                .help("Source of the daily rates used to convert USD exchange \
+                                       prices into the base currency"),
+                        )
+                )
+        )
         .subcommand(
-            SubCommand::with_name("price")
-                .about("Get token price")
+            SubCommand::with_name("daemon")
+                .about("Run the sync, plan and sell ladder jobs of a configuration file on their \
+                        schedules, until interrupted")
                 .arg(
-                    Arg::with_name("token")
-                        .value_name("SOL or SPL Token")
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
                         .takes_value(true)
                         .required(true)
-                        .validator(is_valid_token_or_sol)
-                        .default_value("SOL")
-                        .help("Token type"),
+                        .help("Job configuration file (TOML)"),
                 )
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Run the sweeps, transfers, wraps, swaps and exchange deposits of a \
+                        plan file in order")
                 .arg(
-                    Arg::with_name("when")
-                        .value_name("YY/MM/DD")
+                    Arg::with_name("plan")
+                        .value_name("PLAN")
                         .takes_value(true)
-                        .required(false)
-                        .validator(|value| naivedate_of(&value).map(|_| ()))
-                        .help("Date to fetch the price for [default: current spot price]"),
+                        .required(true)
+                        .help("Plan file (TOML)"),
+                )
+                .arg(
+                    Arg::with_name("restart")
+                        .long("restart")
+                        .takes_value(false)
+                        .help("Start from the first step, discarding the progress of a \
+                               previous run that failed. Required if the plan changed since"),
                 )
         )
-        .subcommand(SubCommand::with_name("sync").about("Synchronize with all exchanges and accounts"))
         .subcommand(
-            SubCommand::with_name("db")
-                .about("Database management")
+            SubCommand::with_name("tx")
+                .about("Offline transaction signing and multisig proposals")
                 .setting(AppSettings::SubcommandRequiredElseHelp)
                 .setting(AppSettings::InferSubcommands)
                 .subcommand(
-                    SubCommand::with_name("import")
-                        .about("Import another database")
+                    SubCommand::with_name("sign")
+                        .about("Add signatures to a transaction written by --sign-only")
                         .arg(
-                            Arg::with_name("other_db_path")
-                                .value_name("PATH")
+                            Arg::with_name("file")
+                                .value_name("FILE")
                                 .takes_value(true)
-                                .help("Path to the database to import"),
+                                .required(true)
+                                .help("Transaction file"),
+                        )
+                        .arg(
+                            Arg::with_name("signer")
+                                .long("signer")
+                                .value_name("KEYPAIR")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .required(true)
+                                .validator(is_valid_signer)
+                                .help("Signer to add. May be specified multiple times"),
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("submit")
+                        .about("Broadcast a signed transaction written by --sign-only and \
+                                complete its pending bookkeeping")
+                        .arg(
+                            Arg::with_name("file")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Transaction file"),
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("cancel")
+                        .about("Abandon a transaction written by --sign-only that will not be \
+                                submitted, cancelling its pending bookkeeping")
+                        .arg(
+                            Arg::with_name("signature")
+                                .value_name("SIGNATURE")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_parsable::<Signature>)
+                                .help("Signature of the pending operation, as reported by \
+                                       `sys sync`"),
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("cancel-proposal")
+                        .about("Abandon a multisig proposal that will not be executed, \
+                                cancelling its pending bookkeeping")
+                        .arg(
+                            Arg::with_name("proposal")
+                                .value_name("ADDRESS")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_valid_pubkey)
+                                .help("Multisig proposal account"),
                         )
                 )
         )
@@ -3598,10 +7256,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             Arg::with_name("price")
                                 .short("p")
                                 .long("price")
-                                .value_name("USD")
+                                .value_name("PRICE")
                                 .takes_value(true)
                                 .validator(is_parsable::<f64>)
-                                .help("Acquisition price per SOL/token [default: market price on acquisition date]"),
+                                .help("Acquisition price per SOL/token in the base currency [default: market price on acquisition date]"),
                         )
                         .arg(
                             Arg::with_name("income")
@@ -3615,6 +7273,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 .long("no-sync")
                                 .takes_value(false)
                                 .help("Never synchronize this account with the on-chain state (advanced; uncommon)"),
+                        )
+                        .arg(
+                            Arg::with_name("token_account")
+                                .long("token-account")
+                                .value_name("TOKEN_ACCOUNT_ADDRESS")
+                                .takes_value(true)
+                                .validator(is_valid_pubkey)
+                                .help("Token account owned by ADDRESS to track instead of its associated token account"),
                         ),
                 )
                 .subcommand(
@@ -3665,10 +7331,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             Arg::with_name("price")
                                 .short("p")
                                 .long("price")
-                                .value_name("USD")
+                                .value_name("PRICE")
                                 .takes_value(true)
                                 .validator(is_parsable::<f64>)
-                                .help("Disposal price per SOL/token [default: market price on disposal date]"),
+                                .help("Disposal price per SOL/token in the base currency [default: market price on disposal date]"),
                         )
                         .arg(lot_selection_arg())
                         .arg(lot_numbers_arg()),
@@ -3696,6 +7362,97 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 .help("Limit output to summary line"),
                         ),
                 )
+                .subcommand(
+                    SubCommand::with_name("reprice")
+                        .about("Recompute the acquisition price of income lots")
+                        .arg(
+                            Arg::with_name("account")
+                                .long("account")
+                                .value_name("ADDRESS")
+                                .takes_value(true)
+                                .validator(is_valid_pubkey)
+                                .help("Limit repricing to lots of this address and its open \
+                                       orders. Disposed lots are only repriced without this flag"),
+                        )
+                        .arg(
+                            Arg::with_name("from")
+                                .long("from")
+                                .value_name("YY/MM/DD")
+                                .takes_value(true)
+                                .validator(|value| naivedate_of(&value).map(|_| ()))
+                                .help("Limit repricing to lots acquired on or after this date"),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .long("to")
+                                .value_name("YY/MM/DD")
+                                .takes_value(true)
+                                .validator(|value| naivedate_of(&value).map(|_| ()))
+                                .help("Limit repricing to lots acquired on or before this date"),
+                        )
+                        .arg(
+                            Arg::with_name("source")
+                                .long("source")
+                                .value_name("SOURCE")
+                                .takes_value(true)
+                                .possible_values(POSSIBLE_PRICE_SOURCE_VALUES)
+                                .default_value("coingecko")
+                                .help("Price source"),
+                        )
+                        .arg(
+                            Arg::with_name("confirm")
+                                .long("confirm")
+                                .takes_value(false)
+                                .help("Update the lot prices. Without this flag only the \
+                                       changes are displayed"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("validator-mode")
+                        .about("Record the block rewards of a validator identity account on sync")
+                        .arg(
+                            Arg::with_name("address")
+                                .value_name("ADDRESS")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_valid_pubkey)
+                                .help("Validator identity address"),
+                        )
+                        .arg(
+                            Arg::with_name("aggregate")
+                                .long("aggregate")
+                                .value_name("AGGREGATION")
+                                .takes_value(true)
+                                .possible_values(POSSIBLE_BLOCK_REWARD_AGGREGATION_VALUES)
+                                .default_value("day")
+                                .help("Record one lot per block, or one lot per day and reward type"),
+                        )
+                        .arg(
+                            Arg::with_name("disable")
+                                .long("disable")
+                                .takes_value(false)
+                                .help("Stop recording block rewards for this address"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("fee-payer")
+                        .about("Record unexpected balance decreases of an account, such as the \
+                                vote transaction fees paid by a validator identity, as expenses on sync")
+                        .arg(
+                            Arg::with_name("address")
+                                .value_name("ADDRESS")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_valid_pubkey)
+                                .help("Fee paying account address"),
+                        )
+                        .arg(
+                            Arg::with_name("disable")
+                                .long("disable")
+                                .takes_value(false)
+                                .help("Stop recording balance decreases of this address as expenses"),
+                        ),
+                )
                 .subcommand(
                     SubCommand::with_name("xls")
                         .about("Export an Excel spreadsheet file")
@@ -3753,10 +7510,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         )
                         .arg(
                             Arg::with_name("stake_authority")
-                                .value_name("KEYPAIR")
+                                .value_name("SIGNER")
                                 .takes_value(true)
                                 .required(true)
-                                .help("Stake authority keypair"),
+                                .validator(is_valid_signer)
+                                .help("Stake authority: a keypair file or usb://ledger"),
                         )
                 )
                 .subcommand(
@@ -3827,55 +7585,239 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 .long("retain")
                                 .value_name("SOL")
                                 .takes_value(true)
-                                .validator(is_parsable::<f64>)
-                                .help("Amount of SOL to retain in the source account [default: 0]"),
-                        ),
+                                .validator(is_parsable::<f64>)
+                                .help("Amount of SOL to retain in the source account [default: 0]"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("split")
+                        .about("Split a stake account")
+                        .arg(
+                            Arg::with_name("from_address")
+                                .value_name("ADDRESS")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_valid_pubkey)
+                                .help("Address of the account to split")
+                        )
+                        .arg(
+                            Arg::with_name("amount")
+                                .value_name("AMOUNT")
+                                .takes_value(true)
+                                .validator(is_amount)
+                                .required(true)
+                                .help("The amount to split, in SOL"),
+                        )
+                        .arg(
+                            Arg::with_name("description")
+                                .short("d")
+                                .long("description")
+                                .value_name("TEXT")
+                                .takes_value(true)
+                                .help("Description of the new account"),
+                        )
+                        .arg(
+                            Arg::with_name("by")
+                                .long("by")
+                                .value_name("KEYPAIR")
+                                .takes_value(true)
+                                .validator(is_valid_signer)
+                                .help("Optional authority for the split"),
+                        )
+                        .arg(
+                            Arg::with_name("into_keypair")
+                                .long("into")
+                                .value_name("KEYPAIR")
+                                .takes_value(true)
+                                .validator(is_keypair)
+                                .help("Optional keypair of the split destination [default: randomly generated]"),
+                        )
+                        .arg(lot_selection_arg())
+                        .arg(lot_numbers_arg())
+                )
+                .subcommand(
+                    SubCommand::with_name("stake")
+                        .about("Stake account lifecycle")
+                        .setting(AppSettings::SubcommandRequiredElseHelp)
+                        .setting(AppSettings::InferSubcommands)
+                        .subcommand(
+                            SubCommand::with_name("delegate")
+                                .about("Delegate a stake account")
+                                .arg(
+                                    Arg::with_name("address")
+                                        .value_name("ADDRESS")
+                                        .takes_value(true)
+                                        .required(true)
+                                        .validator(is_valid_pubkey)
+                                        .help("Stake account address"),
+                                )
+                                .arg(
+                                    Arg::with_name("vote_address")
+                                        .long("vote")
+                                        .value_name("VOTE_ADDRESS")
+                                        .takes_value(true)
+                                        .required(true)
+                                        .validator(is_valid_pubkey)
+                                        .help("Vote account to delegate to"),
+                                )
+                                .arg(
+                                    Arg::with_name("by")
+                                        .long("by")
+                                        .value_name("KEYPAIR")
+                                        .takes_value(true)
+                                        .validator(is_valid_signer)
+                                        .help("Optional stake authority"),
+                                )
+                        )
+                        .subcommand(
+                            SubCommand::with_name("deactivate")
+                                .about("Deactivate a stake account")
+                                .arg(
+                                    Arg::with_name("address")
+                                        .value_name("ADDRESS")
+                                        .takes_value(true)
+                                        .required(true)
+                                        .validator(is_valid_pubkey)
+                                        .help("Stake account address"),
+                                )
+                                .arg(
+                                    Arg::with_name("by")
+                                        .long("by")
+                                        .value_name("KEYPAIR")
+                                        .takes_value(true)
+                                        .validator(is_valid_signer)
+                                        .help("Optional stake authority"),
+                                )
+                        )
+                        .subcommand(
+                            SubCommand::with_name("withdraw")
+                                .about("Withdraw from a stake account into another tracked account")
+                                .arg(
+                                    Arg::with_name("address")
+                                        .value_name("ADDRESS")
+                                        .takes_value(true)
+                                        .required(true)
+                                        .validator(is_valid_pubkey)
+                                        .help("Stake account address"),
+                                )
+                                .arg(
+                                    Arg::with_name("to_address")
+                                        .value_name("RECIPIENT_ADDRESS")
+                                        .takes_value(true)
+                                        .required(true)
+                                        .validator(is_valid_pubkey)
+                                        .help("Tracked account to receive the withdrawal"),
+                                )
+                                .arg(
+                                    Arg::with_name("amount")
+                                        .value_name("AMOUNT")
+                                        .takes_value(true)
+                                        .validator(is_amount_or_all)
+                                        .required(true)
+                                        .help("The amount to withdraw, in SOL; accepts keyword ALL"),
+                                )
+                                .arg(
+                                    Arg::with_name("by")
+                                        .long("by")
+                                        .value_name("KEYPAIR")
+                                        .takes_value(true)
+                                        .validator(is_valid_signer)
+                                        .help("Optional withdraw authority"),
+                                )
+                                .arg(lot_selection_arg())
+                                .arg(lot_numbers_arg())
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("transfer")
+                        .about("Transfer tokens between tracked accounts, preserving their lots")
+                        .arg(
+                            Arg::with_name("token")
+                                .long("token")
+                                .value_name("SPL Token")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_valid_token)
+                                .help("Token type"),
+                        )
+                        .arg(
+                            Arg::with_name("from_address")
+                                .long("from")
+                                .value_name("ADDRESS")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_valid_pubkey)
+                                .help("Tracked token owner to transfer from"),
+                        )
+                        .arg(
+                            Arg::with_name("to_address")
+                                .long("to")
+                                .value_name("RECIPIENT_ADDRESS")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_valid_pubkey)
+                                .help("Token owner to transfer to. \
+                                       The recipient's associated token account is created if necessary, \
+                                       and the recipient becomes a tracked account if it isn't already"),
+                        )
+                        .arg(
+                            Arg::with_name("amount")
+                                .long("amount")
+                                .value_name("AMOUNT")
+                                .takes_value(true)
+                                .validator(is_amount_or_all)
+                                .required(true)
+                                .help("The amount to transfer; accepts keyword ALL"),
+                        )
+                        .arg(
+                            Arg::with_name("by")
+                                .long("by")
+                                .value_name("KEYPAIR")
+                                .takes_value(true)
+                                .validator(is_valid_signer)
+                                .help("Optional token account owner or delegate"),
+                        )
+                        .arg(lot_selection_arg())
+                        .arg(lot_numbers_arg())
                 )
                 .subcommand(
-                    SubCommand::with_name("split")
-                        .about("Split a stake account")
+                    SubCommand::with_name("redelegate")
+                        .about("Redelegate a stake account to a different validator. \
+                                The stake is deactivated now and `sys sync` delegates it once it has cooled down")
                         .arg(
-                            Arg::with_name("from_address")
+                            Arg::with_name("address")
                                 .value_name("ADDRESS")
                                 .takes_value(true)
                                 .required(true)
                                 .validator(is_valid_pubkey)
-                                .help("Address of the account to split")
+                                .help("Stake account address"),
                         )
                         .arg(
-                            Arg::with_name("amount")
-                                .value_name("AMOUNT")
+                            Arg::with_name("vote_address")
+                                .long("vote")
+                                .value_name("VOTE_ADDRESS")
                                 .takes_value(true)
-                                .validator(is_amount)
                                 .required(true)
-                                .help("The amount to split, in SOL"),
-                        )
-                        .arg(
-                            Arg::with_name("description")
-                                .short("d")
-                                .long("description")
-                                .value_name("TEXT")
-                                .takes_value(true)
-                                .help("Description of the new account"),
+                                .validator(is_valid_pubkey)
+                                .help("Vote account to redelegate to"),
                         )
                         .arg(
-                            Arg::with_name("by")
+                            Arg::with_name("stake_authority")
                                 .long("by")
-                                .value_name("KEYPAIR")
+                                .value_name("SIGNER")
                                 .takes_value(true)
+                                .required(true)
                                 .validator(is_valid_signer)
-                                .help("Optional authority for the split"),
+                                .help("Stake authority: a keypair file or usb://ledger, retained until \
+                                       the redelegation completes"),
                         )
                         .arg(
-                            Arg::with_name("into_keypair")
-                                .long("into")
-                                .value_name("KEYPAIR")
-                                .takes_value(true)
-                                .validator(is_keypair)
-                                .help("Optional keypair of the split destination [default: randomly generated]"),
+                            Arg::with_name("new_account")
+                                .long("new-account")
+                                .takes_value(false)
+                                .help("Withdraw into a new stake account before delegating. \
+                                       The stake authority must also be the withdraw authority"),
                         )
-                        .arg(lot_selection_arg())
-                        .arg(lot_numbers_arg())
                 )
                 .subcommand(
                     SubCommand::with_name("sync")
@@ -4640,8 +8582,28 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         normalize_to_url_if_moniker(value_t_or_exit!(app_matches, "json_rpc_url", String)),
         CommitmentConfig::confirmed(),
     );
+    if let Ok(websocket_url) = value_t!(app_matches, "websocket_url", String) {
+        pubsub::set(Box::new(WebsocketPubsub::connect(websocket_url)?));
+    }
     let mut wallet_manager = None;
-    let notifier = Notifier::default();
+    let dry_run = app_matches.is_present("dry_run");
+    let notifier = if dry_run {
+        dry_run::enable();
+        Notifier::disabled()
+    } else {
+        Notifier::default()
+    };
+
+    if let Ok(sign_only_file) = value_t!(app_matches, "sign_only", PathBuf) {
+        let sign_only_mode = if let Some(nonce) = pubkey_of(&app_matches, "nonce") {
+            SignOnlyMode::DurableNonce(nonce)
+        } else if let Some(proposal) = pubkey_of(&app_matches, "multisig_proposal") {
+            SignOnlyMode::MultisigProposal(proposal)
+        } else {
+            return Err("--sign-only requires --nonce or --multisig-proposal".into());
+        };
+        set_sign_only(sign_only_mode, sign_only_file);
+    }
 
     if !db_path.exists() {
         fs::create_dir_all(&db_path)?;
@@ -4662,32 +8624,75 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    let mut db = db::new(&db_path).unwrap_or_else(|err| {
+    let db = if dry_run {
+        db::new_dry_run(&db_path)
+    } else {
+        db::new(&db_path)
+    };
+    let mut db = db.unwrap_or_else(|err| {
         eprintln!("Failed to open {}: {}", db_path.display(), err);
         exit(1)
     });
+    // Tokens removed from the registry while still recorded can't be displayed or priced
+    let missing_mints = db
+        .registered_token_mints()
+        .into_iter()
+        .filter(|mint| token_registry::get(mint).is_none())
+        .map(|mint| mint.to_string())
+        .collect::<Vec<_>>();
+    if !missing_mints.is_empty() {
+        return Err(format!(
+            "Tokens recorded in the database are missing from {}: {}",
+            db_path.join("token-registry.json").display(),
+            missing_mints.join(", ")
+        )
+        .into());
+    }
+    // Unchanged copy to compare the dry run against
+    let dry_run_baseline = if dry_run {
+        Some(db::new_dry_run(&db_path)?)
+    } else {
+        None
+    };
 
     match app_matches.subcommand() {
+        ("price", Some(arg_matches)) if arg_matches.subcommand_name() == Some("history") => {
+            let arg_matches = arg_matches.subcommand_matches("history").unwrap();
+            let token = MaybeToken::from(value_t!(arg_matches, "token", Token).ok());
+            let to = value_t!(arg_matches, "to", String)
+                .map(|s| naivedate_of(&s).unwrap())
+                .unwrap_or_else(|_| db.get_reporting_timezone().today());
+            let from = value_t!(arg_matches, "from", String)
+                .map(|s| naivedate_of(&s).unwrap())
+                .unwrap_or_else(|_| to - chrono::Duration::days(30));
+            let chart = arg_matches.is_present("chart");
+            let csv = value_t!(arg_matches, "csv", String).ok();
+
+            process_price_history(&db, &rpc_client, token, from, to, chart, csv).await?;
+        }
         ("price", Some(arg_matches)) => {
             let when = value_t!(arg_matches, "when", String)
                 .map(|s| naivedate_of(&s).unwrap())
                 .ok();
             let token = MaybeToken::from(value_t!(arg_matches, "token", Token).ok());
+            let base_currency = db.get_base_currency();
 
             let (price, verbose_msg) = if let Some(when) = when {
                 (
-                    token.get_historical_price(&rpc_client, when).await?,
+                    token
+                        .get_historical_price(&rpc_client, when, base_currency)
+                        .await?,
                     format!("Historical {} price on {}", token, when),
                 )
             } else {
                 (
-                    token.get_current_price(&rpc_client).await?,
+                    token.get_current_price(&rpc_client, base_currency).await?,
                     format!("Current {} price", token),
                 )
             };
 
             if verbose {
-                println!("{}: ${:.2}", verbose_msg, price);
+                println!("{}: {}{:.2}", verbose_msg, base_currency.symbol(), price);
 
                 if let Some(liquidity_token) = token.liquidity_token() {
                     let rate = token.get_current_liquidity_token_rate(&rpc_client).await?;
@@ -4703,21 +8708,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
         ("sync", Some(_arg_matches)) => {
-            process_sync_swaps(&mut db, &rpc_client, &notifier).await?;
-            for (exchange, exchange_credentials) in db.get_configured_exchanges() {
-                println!("Synchronizing {:?}...", exchange);
-                let exchange_client = exchange_client_new(exchange, exchange_credentials)?;
-                process_sync_exchange(
-                    &mut db,
-                    exchange,
-                    exchange_client.as_ref(),
-                    &rpc_client,
-                    &notifier,
-                )
-                .await?
+            process_sync(&mut db, &rpc_client, &mut wallet_manager, &notifier).await?;
+        }
+        ("daemon", Some(arg_matches)) => {
+            if app_matches.is_present("sign_only") {
+                return Err("`sys daemon` cannot be combined with --sign-only".into());
             }
-            process_account_sync(&mut db, &rpc_client, None, &notifier).await?;
+            let config = value_t_or_exit!(arg_matches, "config", PathBuf);
+
+            process_daemon(
+                &mut db,
+                &rpc_client,
+                &config,
+                &mut wallet_manager,
+                &notifier,
+            )
+            .await?;
         }
+        ("report", Some(report_matches)) => match report_matches.subcommand() {
+            ("validator", Some(arg_matches)) => {
+                let vote_address = pubkey_of(arg_matches, "vote_address").unwrap();
+                let epochs = value_t_or_exit!(arg_matches, "epochs", u64);
+
+                process_report_validator(&db, &rpc_client, vote_address, epochs).await?;
+            }
+            _ => unreachable!(),
+        },
         ("db", Some(db_matches)) => match db_matches.subcommand() {
             ("import", Some(arg_matches)) => {
                 let other_db_path = value_t_or_exit!(arg_matches, "other_db_path", PathBuf);
@@ -4746,6 +8762,155 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("Importing {}", other_db_path.display());
                 db.import_db(other_db)?;
             }
+            ("price-precision", Some(arg_matches)) => {
+                if let Ok(price_precision) =
+                    value_t!(arg_matches, "price_precision", PricePrecision)
+                {
+                    db.set_price_precision(price_precision)?;
+                }
+                println!("Price precision: {}", db.get_price_precision());
+            }
+            ("transaction-settings", Some(arg_matches)) => {
+                let mut transaction_settings = db.get_transaction_settings();
+                if let Ok(priority_fee) = value_t!(arg_matches, "priority_fee", u64) {
+                    transaction_settings.compute_unit_price = priority_fee;
+                }
+                if let Ok(rebroadcast_interval) = value_t!(arg_matches, "rebroadcast_interval", u64)
+                {
+                    transaction_settings.rebroadcast_interval = rebroadcast_interval;
+                }
+                if let Ok(max_blockhash_refreshes) =
+                    value_t!(arg_matches, "max_blockhash_refreshes", u32)
+                {
+                    transaction_settings.max_blockhash_refreshes = max_blockhash_refreshes;
+                }
+                if transaction_settings != db.get_transaction_settings() {
+                    db.set_transaction_settings(transaction_settings)?;
+                }
+                println!("Transaction settings: {}", transaction_settings);
+            }
+            ("timezone", Some(arg_matches)) => {
+                if let Ok(reporting_timezone) = value_t!(arg_matches, "timezone", ReportingTimezone)
+                {
+                    process_db_timezone_migration_report(&db, &rpc_client, reporting_timezone)?;
+                    db.set_reporting_timezone(reporting_timezone)?;
+                }
+                println!("Reporting timezone: {}", db.get_reporting_timezone());
+            }
+            ("currency", Some(arg_matches)) => {
+                if let Ok(currency) = value_t!(arg_matches, "currency", Currency) {
+                    db.set_base_currency(currency)?;
+                }
+                if let Ok(fx_rate_source) = value_t!(arg_matches, "fx_rate_source", FxRateSource) {
+                    db.set_fx_rate_source(fx_rate_source)?;
+                }
+                println!(
+                    "Base currency: {} (FX rates: {})",
+                    db.get_base_currency(),
+                    db.get_fx_rate_source()
+                );
+            }
+            _ => unreachable!(),
+        },
+        ("run", Some(arg_matches)) => {
+            if app_matches.is_present("sign_only") {
+                return Err("`sys run` cannot be combined with --sign-only".into());
+            }
+            let plan = value_t_or_exit!(arg_matches, "plan", PathBuf);
+            let restart = arg_matches.is_present("restart");
+
+            process_run(
+                &mut db,
+                &rpc_client,
+                &plan,
+                restart,
+                &mut wallet_manager,
+                &notifier,
+            )
+            .await?;
+        }
+        ("tx", Some(tx_matches)) => match tx_matches.subcommand() {
+            ("sign", Some(arg_matches)) => {
+                let file = value_t_or_exit!(arg_matches, "file", PathBuf);
+                let mut offline_transaction = OfflineTransaction::load(&file)?;
+
+                let mut signers = vec![];
+                for path in arg_matches.values_of("signer").unwrap() {
+                    signers.push(solana_clap_utils::keypair::signer_from_path(
+                        arg_matches,
+                        path,
+                        "signer",
+                        &mut wallet_manager,
+                    )?);
+                }
+                let recent_blockhash = offline_transaction.transaction.message.recent_blockhash;
+                offline_transaction
+                    .transaction
+                    .try_partial_sign(&signers, recent_blockhash)?;
+                offline_transaction.save(&file)?;
+
+                let missing_signers = offline_transaction.missing_signers();
+                if missing_signers.is_empty() {
+                    println!("Transaction fully signed, submit it with `sys tx submit`");
+                } else {
+                    for signer in missing_signers {
+                        println!("Signature required from {}", signer);
+                    }
+                }
+            }
+            ("submit", Some(arg_matches)) => {
+                let file = value_t_or_exit!(arg_matches, "file", PathBuf);
+                let offline_transaction = OfflineTransaction::load(&file)?;
+
+                let transaction_pipeline = TransactionPipeline::new(&rpc_client, &db);
+                let signature = transaction_pipeline
+                    .submit(&mut db, offline_transaction)
+                    .await
+                    .map_err(|err| format!("Submit failed: {}", err))?;
+                println!("Transaction confirmed: {}", signature);
+            }
+            ("cancel", Some(arg_matches)) => {
+                let signature = value_t_or_exit!(arg_matches, "signature", Signature);
+                if db
+                    .get_pending_multisig_proposals()
+                    .iter()
+                    .any(|pmp| pmp.pending_signature == Some(signature))
+                {
+                    return Err(format!(
+                        "{} belongs to a multisig proposal, use `sys tx cancel-proposal`",
+                        signature
+                    )
+                    .into());
+                }
+                let pending_kind = PendingKind::of(&db, signature)
+                    .filter(|pending_kind| pending_kind.is_offline(&db, signature))
+                    .ok_or_else(|| {
+                        format!("{} is not awaiting an offline transaction", signature)
+                    })?;
+
+                pending_kind.cancel(&mut db, signature)?;
+                println!("Cancelled pending {:?}: {}", pending_kind, signature);
+                println!(
+                    "Note: Advance the nonce account if the transaction was signed, so that it \
+                     can no longer land"
+                );
+            }
+            ("cancel-proposal", Some(arg_matches)) => {
+                let proposal = pubkey_of(arg_matches, "proposal").unwrap();
+                let pending_multisig_proposal = db
+                    .get_pending_multisig_proposals()
+                    .into_iter()
+                    .find(|pmp| pmp.proposal == proposal)
+                    .ok_or_else(|| format!("Multisig proposal {} is not pending", proposal))?;
+
+                if let Some(pending_signature) = pending_multisig_proposal.pending_signature {
+                    if let Some(pending_kind) = PendingKind::of(&db, pending_signature) {
+                        pending_kind.cancel(&mut db, pending_signature)?;
+                    }
+                }
+                db.remove_pending_multisig_proposal(proposal)?;
+                println!("Cancelled multisig proposal {}", proposal);
+            }
             _ => unreachable!(),
         },
         ("account", Some(account_matches)) => match account_matches.subcommand() {
@@ -4788,6 +8953,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .ok()
                     .unwrap_or_default();
                 let no_sync = arg_matches.is_present("no_sync");
+                let token_account = pubkey_of(arg_matches, "token_account");
 
                 process_account_add(
                     &mut db,
@@ -4800,9 +8966,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     income,
                     signature,
                     no_sync,
+                    token_account,
+                )
+                .await?;
+                process_account_sync(
+                    &mut db,
+                    &rpc_client,
+                    Some(address),
+                    &mut wallet_manager,
+                    &notifier,
                 )
                 .await?;
-                process_account_sync(&mut db, &rpc_client, Some(address), &notifier).await?;
             }
             ("dispose", Some(arg_matches)) => {
                 let address = pubkey_of(arg_matches, "address").unwrap();
@@ -4840,6 +9014,78 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 process_account_list(&db, &rpc_client, account_filter, all, summary, &notifier)
                     .await?;
             }
+            ("validator-mode", Some(arg_matches)) => {
+                let address = pubkey_of(arg_matches, "address").unwrap();
+                let aggregation =
+                    value_t_or_exit!(arg_matches, "aggregate", BlockRewardAggregation);
+
+                if arg_matches.is_present("disable") {
+                    db.remove_validator_identity(address)?;
+                    println!("Block rewards of {} will no longer be recorded", address);
+                } else {
+                    // Rewards of prior blocks are assumed to already be accounted for
+                    let last_scanned_slot = match db
+                        .get_validator_identities()
+                        .into_iter()
+                        .find(|validator_identity| validator_identity.address == address)
+                    {
+                        Some(validator_identity) => validator_identity.last_scanned_slot,
+                        None => {
+                            rpc_client.get_slot_with_commitment(CommitmentConfig::finalized())?
+                        }
+                    };
+                    db.set_validator_identity(ValidatorIdentity {
+                        address,
+                        aggregation,
+                        last_scanned_slot,
+                    })?;
+                    println!(
+                        "Block rewards of {} will be recorded by {} from slot {}",
+                        address,
+                        aggregation,
+                        last_scanned_slot + 1
+                    );
+                }
+            }
+            ("fee-payer", Some(arg_matches)) => {
+                let address = pubkey_of(arg_matches, "address").unwrap();
+
+                if arg_matches.is_present("disable") {
+                    db.set_fee_payer(address, false)?;
+                    println!(
+                        "Balance decreases of {} will no longer be recorded as expenses",
+                        address
+                    );
+                } else {
+                    db.set_fee_payer(address, true)?;
+                    println!(
+                        "Balance decreases of {} will be recorded as fee expenses",
+                        address
+                    );
+                }
+            }
+            ("reprice", Some(arg_matches)) => {
+                let account_filter = pubkey_of(arg_matches, "account");
+                let from = value_t!(arg_matches, "from", String)
+                    .map(|s| naivedate_of(&s).unwrap())
+                    .ok();
+                let to = value_t!(arg_matches, "to", String)
+                    .map(|s| naivedate_of(&s).unwrap())
+                    .ok();
+                let price_source = value_t_or_exit!(arg_matches, "source", PriceSource);
+                let confirm = arg_matches.is_present("confirm");
+
+                process_account_reprice(
+                    &mut db,
+                    &rpc_client,
+                    account_filter,
+                    from,
+                    to,
+                    price_source,
+                    confirm,
+                )
+                .await?;
+            }
             ("xls", Some(arg_matches)) => {
                 let outfile = value_t_or_exit!(arg_matches, "outfile", String);
                 let filter_by_year = value_t!(arg_matches, "year", i32).ok();
@@ -4867,20 +9113,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
             ("set-sweep-stake-account", Some(arg_matches)) => {
                 let address = pubkey_of(arg_matches, "address").unwrap();
-                let stake_authority = std::fs::canonicalize(value_t_or_exit!(
-                    arg_matches,
-                    "stake_authority",
-                    PathBuf
-                ))?;
-
-                let sweep_stake_authority_keypair =
-                    read_keypair_file(&stake_authority).map_err(|err| {
-                        format!("Failed to read {}: {}", stake_authority.display(), err)
-                    })?;
+                let stake_authority =
+                    stored_signer_uri(arg_matches.value_of("stake_authority").unwrap())?;
+
+                let (_, sweep_stake_authority_address) =
+                    signer_from_uri(&stake_authority, &mut wallet_manager)?;
                 let (sweep_stake_authorized, _vote_account_address) =
                     rpc_client_utils::get_stake_authorized(&rpc_client, address)?;
 
-                if sweep_stake_authorized.staker != sweep_stake_authority_keypair.pubkey() {
+                if sweep_stake_authorized.staker != sweep_stake_authority_address {
                     return Err("Stake authority mismatch".into());
                 }
 
@@ -4939,6 +9180,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     from_authority_address,
                     vec![from_authority_signer],
                     to_address,
+                    &mut wallet_manager,
                     &notifier,
                 )
                 .await?;
@@ -4982,9 +9224,141 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 )
                 .await?;
             }
+            ("stake", Some(stake_matches)) => {
+                let (subcommand, arg_matches) = stake_matches.subcommand();
+                let arg_matches = arg_matches.unwrap();
+                let address = pubkey_of(arg_matches, "address").unwrap();
+
+                let (authority_signer, authority_address) = if arg_matches.is_present("by") {
+                    signer_of(arg_matches, "by", &mut wallet_manager)?
+                } else {
+                    signer_of(arg_matches, "address", &mut wallet_manager).map_err(|err| {
+                        format!(
+                            "Authority not found, consider using the `--by` argument): {}",
+                            err
+                        )
+                    })?
+                };
+
+                let authority_address = authority_address.expect("authority_address");
+                let authority_signer = authority_signer.expect("authority_signer");
+
+                match subcommand {
+                    "delegate" => {
+                        let vote_address = pubkey_of(arg_matches, "vote_address").unwrap();
+                        process_account_stake_delegate(
+                            &mut db,
+                            &rpc_client,
+                            address,
+                            vote_address,
+                            authority_address,
+                            vec![authority_signer],
+                        )
+                        .await?;
+                    }
+                    "deactivate" => {
+                        process_account_stake_deactivate(
+                            &mut db,
+                            &rpc_client,
+                            address,
+                            authority_address,
+                            vec![authority_signer],
+                        )
+                        .await?;
+                    }
+                    "withdraw" => {
+                        let to_address = pubkey_of(arg_matches, "to_address").unwrap();
+                        let amount = match arg_matches.value_of("amount").unwrap() {
+                            "ALL" => None,
+                            amount => Some(MaybeToken::SOL().amount(amount.parse().unwrap())),
+                        };
+                        let lot_numbers = lot_numbers_of(arg_matches, "lot_numbers");
+                        let lot_selection_method =
+                            value_t_or_exit!(arg_matches, "lot_selection", LotSelectionMethod);
+
+                        process_account_stake_withdraw(
+                            &mut db,
+                            &rpc_client,
+                            address,
+                            to_address,
+                            amount,
+                            lot_selection_method,
+                            lot_numbers,
+                            authority_address,
+                            vec![authority_signer],
+                        )
+                        .await?;
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            ("transfer", Some(arg_matches)) => {
+                let token = value_t_or_exit!(arg_matches, "token", Token);
+                let from_address = pubkey_of(arg_matches, "from_address").unwrap();
+                let to_address = pubkey_of(arg_matches, "to_address").unwrap();
+                let amount = match arg_matches.value_of("amount").unwrap() {
+                    "ALL" => None,
+                    amount => Some(token.amount(amount.parse().unwrap())),
+                };
+                let lot_numbers = lot_numbers_of(arg_matches, "lot_numbers");
+                let lot_selection_method =
+                    value_t_or_exit!(arg_matches, "lot_selection", LotSelectionMethod);
+
+                let (authority_signer, authority_address) = if arg_matches.is_present("by") {
+                    signer_of(arg_matches, "by", &mut wallet_manager)?
+                } else {
+                    signer_of(arg_matches, "from_address", &mut wallet_manager).map_err(|err| {
+                        format!(
+                            "Authority not found, consider using the `--by` argument): {}",
+                            err
+                        )
+                    })?
+                };
+                let authority_address = authority_address.expect("authority_address");
+                let authority_signer = authority_signer.expect("authority_signer");
+
+                process_account_transfer(
+                    &mut db,
+                    &rpc_client,
+                    token,
+                    from_address,
+                    to_address,
+                    amount,
+                    lot_selection_method,
+                    lot_numbers,
+                    authority_address,
+                    vec![authority_signer],
+                )
+                .await?;
+            }
+            ("redelegate", Some(arg_matches)) => {
+                let address = pubkey_of(arg_matches, "address").unwrap();
+                let vote_address = pubkey_of(arg_matches, "vote_address").unwrap();
+                let stake_authority =
+                    stored_signer_uri(arg_matches.value_of("stake_authority").unwrap())?;
+                let new_account = arg_matches.is_present("new_account");
+
+                process_account_redelegate(
+                    &mut db,
+                    &rpc_client,
+                    address,
+                    vote_address,
+                    stake_authority,
+                    new_account,
+                    &mut wallet_manager,
+                )
+                .await?;
+            }
             ("sync", Some(arg_matches)) => {
                 let address = pubkey_of(arg_matches, "address");
-                process_account_sync(&mut db, &rpc_client, address, &notifier).await?;
+                process_account_sync(
+                    &mut db,
+                    &rpc_client,
+                    address,
+                    &mut wallet_manager,
+                    &notifier,
+                )
+                .await?;
             }
             ("wrap", Some(arg_matches)) => {
                 let address = pubkey_of(arg_matches, "address").unwrap();
@@ -5175,7 +9549,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let exchange_credentials = db
                     .get_exchange_credentials(exchange)
                     .ok_or_else(|| format!("No API key set for {:?}", exchange))?;
-                exchange_client_new(exchange, exchange_credentials)
+                exchange_client_new(exchange, exchange_credentials, db.get_reporting_timezone())
             };
 
             match exchange_matches.subcommand() {
@@ -5694,5 +10068,136 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         _ => unreachable!(),
     };
 
+    if let Some(dry_run_baseline) = dry_run_baseline {
+        process_dry_run_report(&dry_run_baseline, &db);
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        pubsub::LocalPubsub,
+        test_utils::{add_test_account, temp_path, test_db},
+    };
+
+    #[test]
+    fn test_format_sparkline() {
+        assert_eq!(format_sparkline(&[]), "");
+        assert_eq!(format_sparkline(&[1., 1., 1.]), "▁▁▁");
+        assert_eq!(format_sparkline(&[1., 8., 4.5, 1.]), "▁█▅▁");
+        assert_eq!(format_sparkline(&[-2., 0., 2.]), "▁▅█");
+    }
+
+    #[test]
+    fn test_dry_run_changes() {
+        let db_path = temp_path();
+        let sol = MaybeToken::SOL();
+        let when = NaiveDate::from_ymd(2022, 3, 4);
+        let (a, b, c) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+
+        // Both start out with the same accounts and lot numbers, 0 and 1
+        let mut baseline = db::new_dry_run(&db_path).unwrap();
+        let mut db = db::new_dry_run(&db_path).unwrap();
+        for each_db in [&mut baseline, &mut db] {
+            add_test_account(each_db, a, sol.amount(100.));
+            add_test_account(each_db, b, sol.amount(50.));
+        }
+        assert!(dry_run_changes(&baseline, &db).is_empty());
+
+        // Lot 0 is split into lot 2 for the disposal
+        let mut account = db.get_account(a, sol).unwrap();
+        db.record_fee_disposal(
+            &mut account,
+            sol.amount(30.),
+            "Test".into(),
+            0,
+            when,
+            Decimal::from(100),
+        )
+        .unwrap();
+        db.update_account(account).unwrap();
+        db.remove_account(b, sol).unwrap();
+        add_test_account(&mut db, c, sol.amount(5.));
+
+        assert_eq!(
+            dry_run_changes(&baseline, &db),
+            vec![
+                format!("Lot 0: ◎100 in {} (SOL) -> ◎70 in {} (SOL)", a, a),
+                format!("Lot 1: ◎50 removed from {} (SOL)", b),
+                format!("Lot 3: ◎5 added to {} (SOL)", c),
+                "Lot 2: ◎30 disposed on 2022-03-04: Fee expense: Test".to_string(),
+            ]
+        );
+
+        std::fs::remove_dir_all(db_path).unwrap();
+    }
+
+    #[test]
+    fn test_change_watcher() {
+        let (mut db, db_path) = test_db();
+        let sol = MaybeToken::SOL();
+        let commitment = CommitmentConfig::confirmed();
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let signature = Signature::new_unique();
+
+        add_test_account(&mut db, a, sol.amount(10.));
+        add_test_account(&mut db, b, sol.amount(10.));
+        db.record_transfer(
+            signature,
+            1_000,
+            Some(sol.amount(1.)),
+            a,
+            sol,
+            b,
+            sol,
+            LotSelectionMethod::FirstInFirstOut,
+            None,
+        )
+        .unwrap();
+
+        let pubsub = LocalPubsub::default();
+        let mut change_watcher = ChangeWatcher::default();
+        change_watcher.refresh(&db, &pubsub, commitment);
+        assert!(!change_watcher.changed());
+
+        // Each notification is only reported once
+        pubsub.publish_account(&a);
+        assert!(change_watcher.changed());
+        assert!(!change_watcher.changed());
+        assert!(pubsub.publish_signature(&signature, Ok(())));
+        assert!(change_watcher.changed());
+
+        // Refreshing doesn't subscribe twice
+        change_watcher.refresh(&db, &pubsub, commitment);
+        pubsub.publish_account(&b);
+        assert!(change_watcher.changed());
+        assert!(!change_watcher.changed());
+
+        // Subscriptions to a completed transfer and a removed account are dropped
+        db.cancel_transfer(signature).unwrap();
+        db.remove_account(b, sol).unwrap();
+        change_watcher.refresh(&db, &pubsub, commitment);
+        assert!(change_watcher.signatures.is_empty());
+        assert_eq!(change_watcher.accounts.keys().collect::<Vec<_>>(), vec![&a]);
+        pubsub.publish_account(&b);
+        assert!(!change_watcher.changed());
+        pubsub.publish_account(&a);
+        assert!(change_watcher.changed());
+
+        // Fee payers aren't subscribed to
+        db.set_fee_payer(a, true).unwrap();
+        change_watcher.refresh(&db, &pubsub, commitment);
+        assert!(change_watcher.accounts.is_empty());
+        pubsub.publish_account(&a);
+        assert!(!change_watcher.changed());
+
+        std::fs::remove_dir_all(db_path).unwrap();
+    }
+}
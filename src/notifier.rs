@@ -14,6 +14,14 @@ impl Notifier {
         }
     }
 
+    // Drops all messages, for `--dry-run`
+    pub fn disabled() -> Self {
+        Notifier {
+            client: Client::new(),
+            slack_webhook: None,
+        }
+    }
+
     pub async fn send(&self, msg: &str) {
         if let Some(ref slack_webhook) = self.slack_webhook {
             let data = json!({ "text": msg });
@@ -0,0 +1,283 @@
+use {
+    crate::{
+        db::LotSelectionMethod, exchange::Exchange, field_as_string, option_field_as_string,
+        token::*,
+    },
+    serde::Deserialize,
+    solana_sdk::pubkey::Pubkey,
+    std::{fs, path::Path},
+};
+
+// A `sys run` plan file: the steps run in order, each as its `sys` command would
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    #[serde(rename = "step", default)]
+    pub steps: Vec<PlanStep>,
+}
+
+impl Plan {
+    pub fn load(path: &Path) -> Result<(Self, Vec<u8>), Box<dyn std::error::Error>> {
+        let contents = fs::read(path)?;
+        let plan = toml::from_slice(&contents)
+            .map_err(|err| format!("Invalid plan {}: {}", path.display(), err))?;
+        Ok((plan, contents))
+    }
+}
+
+// Either a token amount or "ALL"
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum PlanAmount {
+    Amount(f64),
+    All(String),
+}
+
+impl PlanAmount {
+    pub fn amount(&self, token: MaybeToken) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        match self {
+            PlanAmount::Amount(amount) => Ok(Some(token.amount(*amount))),
+            PlanAmount::All(all) if all == "ALL" => Ok(None),
+            PlanAmount::All(other) => Err(format!("Invalid amount: {}", other).into()),
+        }
+    }
+}
+
+pub fn default_lot_selection() -> LotSelectionMethod {
+    LotSelectionMethod::FirstInFirstOut
+}
+
+fn default_slippage() -> f64 {
+    1.
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum PlanStep {
+    Sweep {
+        #[serde(with = "field_as_string")]
+        address: Pubkey,
+        by: String,
+        #[serde(default)]
+        retain: f64,
+        #[serde(default, with = "option_field_as_string")]
+        to: Option<Pubkey>,
+        #[serde(default)]
+        no_sweep_ok: bool,
+        if_source_balance_exceeds: Option<f64>,
+    },
+    Transfer {
+        #[serde(with = "field_as_string")]
+        token: Token,
+        #[serde(with = "field_as_string")]
+        from: Pubkey,
+        #[serde(with = "field_as_string")]
+        to: Pubkey,
+        amount: PlanAmount,
+        by: String,
+        #[serde(default = "default_lot_selection", with = "field_as_string")]
+        lot_selection: LotSelectionMethod,
+        if_source_balance_exceeds: Option<f64>,
+    },
+    Wrap {
+        #[serde(with = "field_as_string")]
+        address: Pubkey,
+        amount: f64,
+        by: String,
+        #[serde(default = "default_lot_selection", with = "field_as_string")]
+        lot_selection: LotSelectionMethod,
+        if_source_balance_exceeds: Option<f64>,
+    },
+    Swap {
+        #[serde(with = "field_as_string")]
+        address: Pubkey,
+        #[serde(with = "field_as_string")]
+        from_token: Token,
+        #[serde(with = "field_as_string")]
+        to_token: Token,
+        amount: f64,
+        #[serde(default = "default_slippage")]
+        slippage: f64,
+        by: String,
+        #[serde(default = "default_lot_selection", with = "field_as_string")]
+        lot_selection: LotSelectionMethod,
+        if_source_balance_exceeds: Option<f64>,
+    },
+    Deposit {
+        #[serde(with = "field_as_string")]
+        exchange: Exchange,
+        #[serde(default, with = "option_field_as_string")]
+        token: Option<Token>,
+        #[serde(with = "field_as_string")]
+        from: Pubkey,
+        amount: PlanAmount,
+        by: String,
+        #[serde(default = "default_lot_selection", with = "field_as_string")]
+        lot_selection: LotSelectionMethod,
+        if_source_balance_exceeds: Option<f64>,
+        if_exchange_balance_less_than: Option<f64>,
+    },
+}
+
+impl PlanStep {
+    // The account and token whose balance `if_source_balance_exceeds` applies to
+    pub fn source(&self) -> (Pubkey, MaybeToken) {
+        match self {
+            PlanStep::Sweep { address, .. } | PlanStep::Wrap { address, .. } => {
+                (*address, MaybeToken::SOL())
+            }
+            PlanStep::Transfer { token, from, .. } => (*from, (*token).into()),
+            PlanStep::Swap {
+                address,
+                from_token,
+                ..
+            } => (*address, (*from_token).into()),
+            PlanStep::Deposit { token, from, .. } => (*from, MaybeToken::from(*token)),
+        }
+    }
+
+    pub fn if_source_balance_exceeds(&self) -> Option<f64> {
+        match self {
+            PlanStep::Sweep {
+                if_source_balance_exceeds,
+                ..
+            }
+            | PlanStep::Transfer {
+                if_source_balance_exceeds,
+                ..
+            }
+            | PlanStep::Wrap {
+                if_source_balance_exceeds,
+                ..
+            }
+            | PlanStep::Swap {
+                if_source_balance_exceeds,
+                ..
+            }
+            | PlanStep::Deposit {
+                if_source_balance_exceeds,
+                ..
+            } => *if_source_balance_exceeds,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            PlanStep::Sweep { address, .. } => format!("sweep {}", address),
+            PlanStep::Transfer {
+                token, from, to, ..
+            } => format!("transfer {} from {} to {}", token, from, to),
+            PlanStep::Wrap {
+                address, amount, ..
+            } => format!("wrap ◎{} in {}", amount, address),
+            PlanStep::Swap {
+                address,
+                from_token,
+                to_token,
+                ..
+            } => format!("swap {} to {} in {}", from_token, to_token, address),
+            PlanStep::Deposit {
+                exchange,
+                token,
+                from,
+                ..
+            } => format!(
+                "deposit {} from {} to {:?}",
+                MaybeToken::from(*token),
+                from,
+                exchange
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::test_utils::temp_path, std::path::PathBuf};
+
+    fn write_plan(contents: &str) -> PathBuf {
+        let path = temp_path().with_extension("toml");
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_plan_load() {
+        let address = Pubkey::new_unique();
+        let path = write_plan(&format!(
+            r#"
+            [[step]]
+            kind = "sweep"
+            address = "{address}"
+            by = "usb://ledger"
+            if_source_balance_exceeds = 10.0
+
+            [[step]]
+            kind = "transfer"
+            token = "USDC"
+            from = "{address}"
+            to = "{address}"
+            amount = "ALL"
+            by = "usb://ledger"
+            lot_selection = "lifo"
+            "#,
+            address = address
+        ));
+        let (plan, contents) = Plan::load(&path).unwrap();
+        assert_eq!(contents, fs::read(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(plan.steps.len(), 2);
+        assert!(matches!(
+            &plan.steps[0],
+            PlanStep::Sweep { retain, to: None, no_sweep_ok: false, .. } if *retain == 0.
+        ));
+        assert_eq!(plan.steps[0].source(), (address, MaybeToken::SOL()));
+        assert_eq!(plan.steps[0].if_source_balance_exceeds(), Some(10.));
+        assert_eq!(plan.steps[1].source(), (address, Token::USDC.into()));
+        assert_eq!(plan.steps[1].if_source_balance_exceeds(), None);
+        match &plan.steps[1] {
+            PlanStep::Transfer {
+                amount,
+                lot_selection,
+                ..
+            } => {
+                assert_eq!(amount.amount(Token::USDC.into()).unwrap(), None);
+                assert_eq!(*lot_selection, LotSelectionMethod::LastInFirstOut);
+            }
+            step => panic!("Unexpected step: {:?}", step),
+        }
+    }
+
+    #[test]
+    fn test_plan_load_rejects_unknown_fields() {
+        let path = write_plan(&format!(
+            r#"
+            [[step]]
+            kind = "sweep"
+            address = "{}"
+            by = "usb://ledger"
+            retian = 1.0
+            "#,
+            Pubkey::new_unique()
+        ));
+        let result = Plan::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_plan_amount() {
+        let sol = MaybeToken::SOL();
+        assert_eq!(
+            PlanAmount::Amount(1.5).amount(sol).unwrap(),
+            Some(1_500_000_000)
+        );
+        assert_eq!(
+            PlanAmount::Amount(1.5).amount(Token::USDC.into()).unwrap(),
+            Some(1_500_000)
+        );
+        assert_eq!(PlanAmount::All("ALL".into()).amount(sol).unwrap(), None);
+        assert!(PlanAmount::All("all".into()).amount(sol).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+use {
+    futures::{future::BoxFuture, stream::BoxStream, StreamExt},
+    once_cell::sync::OnceCell,
+    solana_account_decoder::UiAccountEncoding,
+    solana_client::{
+        nonblocking::pubsub_client::{PubsubClient, PubsubClientResult},
+        rpc_config::{RpcAccountInfoConfig, RpcSignatureSubscribeConfig},
+        rpc_response::RpcSignatureResult,
+    },
+    solana_sdk::{
+        commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature,
+        transaction::TransactionError,
+    },
+    std::{
+        sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        thread,
+        time::Duration,
+    },
+    tokio::sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        oneshot,
+    },
+};
+
+#[cfg(test)]
+use std::{collections::HashMap, sync::Mutex};
+
+// With `--ws`, confirmations arrive through subscriptions. Polling remains the fallback, so
+// everything still works without it
+static PUBSUB: OnceCell<Box<dyn Pubsub + Send + Sync>> = OnceCell::new();
+
+pub fn set(pubsub: Box<dyn Pubsub + Send + Sync>) {
+    if PUBSUB.set(pubsub).is_err() {
+        panic!("Bug: pubsub already set");
+    }
+}
+
+pub fn get() -> Option<&'static (dyn Pubsub + Send + Sync)> {
+    PUBSUB.get().map(|pubsub| pubsub.as_ref())
+}
+
+pub type SignatureResult = Result<(), TransactionError>;
+
+pub trait Pubsub {
+    // Notified once the transaction is confirmed, with its result
+    fn signature_subscribe(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> Result<Subscription<SignatureResult>, Box<dyn std::error::Error>>;
+
+    // Notified whenever the account changes
+    fn account_subscribe(
+        &self,
+        address: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<Subscription<()>, Box<dyn std::error::Error>>;
+}
+
+// Notifications are delivered until the subscription is dropped
+pub struct Subscription<T> {
+    receiver: Receiver<T>,
+    _unsubscribe: Option<oneshot::Sender<()>>, // Ends the websocket subscription when dropped
+}
+
+impl<T> Subscription<T> {
+    pub fn try_recv(&self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(notification) => Some(notification),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                // The subscription ended, don't let the caller spin in place of its usual sleep
+                thread::sleep(timeout);
+                None
+            }
+        }
+    }
+}
+
+type SubscribeRequest = Box<dyn FnOnce(&'static PubsubClient) -> BoxFuture<'static, ()> + Send>;
+type UnsubscribeFn = Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>;
+
+// Every subscription shares a single websocket connection, served by a thread of its own. Once
+// the connection drops, new subscriptions fail and existing ones end
+pub struct WebsocketPubsub {
+    requests: UnboundedSender<SubscribeRequest>,
+}
+
+impl WebsocketPubsub {
+    pub fn connect(url: String) -> Result<Self, Box<dyn std::error::Error>> {
+        let (requests, mut request_receiver) = unbounded_channel::<SubscribeRequest>();
+        let (connected_sender, connected) = channel();
+        thread::spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(err) => {
+                    let _ = connected_sender.send(Err(err.to_string()));
+                    return;
+                }
+            };
+            runtime.block_on(async move {
+                // Subscription streams borrow the client, which is kept for the life of the
+                // process
+                let client: &'static PubsubClient = match PubsubClient::new(&url).await {
+                    Ok(client) => Box::leak(Box::new(client)),
+                    Err(err) => {
+                        let _ = connected_sender.send(Err(err.to_string()));
+                        return;
+                    }
+                };
+                let _ = connected_sender.send(Ok(()));
+                while let Some(request) = request_receiver.recv().await {
+                    tokio::spawn(request(client));
+                }
+            });
+        });
+        connected
+            .recv()
+            .map_err(|_| "Websocket thread exited")?
+            .map_err(|err| format!("Unable to connect to websocket: {}", err))?;
+        Ok(Self { requests })
+    }
+
+    // Hands `serve` to the connection's thread and waits for the subscription to be confirmed
+    fn subscribe<T: Send + 'static>(
+        &self,
+        serve: impl FnOnce(
+                &'static PubsubClient,
+                Sender<Result<(), String>>,
+                oneshot::Receiver<()>,
+                Sender<T>,
+            ) -> BoxFuture<'static, ()>
+            + Send
+            + 'static,
+    ) -> Result<Subscription<T>, Box<dyn std::error::Error>> {
+        let (sender, receiver) = channel();
+        let (subscribed_sender, subscribed) = channel();
+        let (unsubscribe, unsubscribe_receiver) = oneshot::channel();
+        self.requests
+            .send(Box::new(move |client| {
+                serve(client, subscribed_sender, unsubscribe_receiver, sender)
+            }))
+            .map_err(|_| "Websocket connection closed")?;
+        subscribed
+            .recv()
+            .map_err(|_| "Websocket connection closed")??;
+        Ok(Subscription {
+            receiver,
+            _unsubscribe: Some(unsubscribe),
+        })
+    }
+}
+
+// Forwards the notifications of a new subscription until its stream ends or the `Subscription`
+// is dropped
+async fn forward<N, T>(
+    subscription: PubsubClientResult<(BoxStream<'static, N>, UnsubscribeFn)>,
+    subscribed: Sender<Result<(), String>>,
+    mut unsubscribe: oneshot::Receiver<()>,
+    sender: Sender<T>,
+    notification: impl Fn(N) -> Option<T>,
+) {
+    let (mut stream, unsubscribe_fn) = match subscription {
+        Ok(subscription) => subscription,
+        Err(err) => {
+            let _ = subscribed.send(Err(err.to_string()));
+            return;
+        }
+    };
+    let _ = subscribed.send(Ok(()));
+
+    loop {
+        tokio::select! {
+            _ = &mut unsubscribe => break,
+            response = stream.next() => match response {
+                Some(response) => {
+                    if let Some(notification) = notification(response) {
+                        if sender.send(notification).is_err() {
+                            break;
+                        }
+                    }
+                }
+                None => break,
+            },
+        }
+    }
+    unsubscribe_fn().await;
+}
+
+impl Pubsub for WebsocketPubsub {
+    fn signature_subscribe(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> Result<Subscription<SignatureResult>, Box<dyn std::error::Error>> {
+        let signature = *signature;
+        let config = RpcSignatureSubscribeConfig {
+            commitment: Some(commitment),
+            enable_received_notification: Some(false),
+        };
+        self.subscribe(move |client, subscribed, unsubscribe, sender| {
+            Box::pin(async move {
+                forward(
+                    client.signature_subscribe(&signature, Some(config)).await,
+                    subscribed,
+                    unsubscribe,
+                    sender,
+                    |response| match response.value {
+                        RpcSignatureResult::ProcessedSignature(processed) => {
+                            Some(match processed.err {
+                                Some(err) => Err(err),
+                                None => Ok(()),
+                            })
+                        }
+                        _ => None,
+                    },
+                )
+                .await
+            })
+        })
+    }
+
+    fn account_subscribe(
+        &self,
+        address: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<Subscription<()>, Box<dyn std::error::Error>> {
+        let address = *address;
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(commitment),
+            ..RpcAccountInfoConfig::default()
+        };
+        self.subscribe(move |client, subscribed, unsubscribe, sender| {
+            Box::pin(async move {
+                forward(
+                    client.account_subscribe(&address, Some(config)).await,
+                    subscribed,
+                    unsubscribe,
+                    sender,
+                    |_| Some(()),
+                )
+                .await
+            })
+        })
+    }
+}
+
+// In-process stand-in for a websocket endpoint, for tests. Notifications are published by the
+// caller rather than a cluster
+#[cfg(test)]
+#[derive(Default)]
+pub struct LocalPubsub {
+    signature_senders: Mutex<HashMap<Signature, Vec<Sender<SignatureResult>>>>,
+    account_senders: Mutex<HashMap<Pubkey, Vec<Sender<()>>>>,
+}
+
+#[cfg(test)]
+impl LocalPubsub {
+    // Like the cluster, a signature is only notified once. Returns false if nothing subscribed
+    // to it
+    pub fn publish_signature(&self, signature: &Signature, result: SignatureResult) -> bool {
+        match self.signature_senders.lock().unwrap().remove(signature) {
+            Some(senders) => {
+                for sender in senders {
+                    let _ = sender.send(result.clone());
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn publish_account(&self, address: &Pubkey) {
+        if let Some(senders) = self.account_senders.lock().unwrap().get_mut(address) {
+            senders.retain(|sender| sender.send(()).is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+impl Pubsub for LocalPubsub {
+    fn signature_subscribe(
+        &self,
+        signature: &Signature,
+        _commitment: CommitmentConfig,
+    ) -> Result<Subscription<SignatureResult>, Box<dyn std::error::Error>> {
+        let (sender, receiver) = channel();
+        self.signature_senders
+            .lock()
+            .unwrap()
+            .entry(*signature)
+            .or_default()
+            .push(sender);
+        Ok(Subscription {
+            receiver,
+            _unsubscribe: None,
+        })
+    }
+
+    fn account_subscribe(
+        &self,
+        address: &Pubkey,
+        _commitment: CommitmentConfig,
+    ) -> Result<Subscription<()>, Box<dyn std::error::Error>> {
+        let (sender, receiver) = channel();
+        self.account_senders
+            .lock()
+            .unwrap()
+            .entry(*address)
+            .or_default()
+            .push(sender);
+        Ok(Subscription {
+            receiver,
+            _unsubscribe: None,
+        })
+    }
+}
@@ -1,7 +1,24 @@
 use {
-    solana_client::{rpc_client::RpcClient, rpc_response::StakeActivationState},
-    solana_sdk::{account::Account, account_utils::StateMut, pubkey::Pubkey},
+    serde::Deserialize,
+    serde_json::json,
+    solana_client::{
+        rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient},
+        rpc_request::RpcRequest,
+        rpc_response::StakeActivationState,
+    },
+    solana_sdk::{
+        account::Account,
+        account_utils::StateMut,
+        bs58,
+        clock::{Slot, UnixTimestamp},
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::Signature,
+        transaction::{TransactionError, VersionedTransaction},
+    },
     solana_stake_program::stake_state::{Authorized, StakeState},
+    solana_transaction_status::{UiInnerInstructions, UiInstruction, UiTransactionTokenBalance},
+    std::str::FromStr,
 };
 
 pub fn get_stake_authorized(
@@ -62,3 +79,209 @@ pub fn stake_accounts_have_same_credits_observed(
     }
     Ok(false)
 }
+
+pub fn get_latest_signature_for_address(
+    rpc_client: &RpcClient,
+    address: &Pubkey,
+) -> Result<Option<Signature>, Box<dyn std::error::Error>> {
+    Ok(rpc_client
+        .get_signatures_for_address_with_config(
+            address,
+            GetConfirmedSignaturesForAddress2Config {
+                limit: Some(1),
+                commitment: Some(rpc_client.commitment()),
+                ..GetConfirmedSignaturesForAddress2Config::default()
+            },
+        )?
+        .first()
+        .map(|status| Signature::from_str(&status.signature))
+        .transpose()?)
+}
+
+// Returns the signatures of the successful transactions that reference `address` after `until`,
+// oldest first. At most the `limit` latest transactions are walked, so that a `until` missing
+// from the history doesn't walk it all. The flag is set if the limit was reached
+pub fn get_signatures_for_address_since(
+    rpc_client: &RpcClient,
+    address: &Pubkey,
+    until: Signature,
+    limit: usize,
+) -> Result<(Vec<Signature>, bool), Box<dyn std::error::Error>> {
+    let mut signatures = vec![];
+    let mut walked = 0;
+    let mut before = None;
+    let limit_reached = loop {
+        if walked >= limit {
+            break true;
+        }
+        let statuses = rpc_client.get_signatures_for_address_with_config(
+            address,
+            GetConfirmedSignaturesForAddress2Config {
+                before,
+                until: Some(until),
+                limit: Some((limit - walked).min(1_000)),
+                commitment: Some(rpc_client.commitment()),
+            },
+        )?;
+        if statuses.is_empty() {
+            break false;
+        }
+        walked += statuses.len();
+        before = Some(Signature::from_str(&statuses.last().unwrap().signature)?);
+
+        for status in statuses {
+            if status.err.is_none() {
+                signatures.push(Signature::from_str(&status.signature)?);
+            }
+        }
+    };
+    signatures.reverse();
+    Ok((signatures, limit_reached))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LoadedAddresses {
+    pub writable: Vec<String>,
+    pub readonly: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionMeta {
+    pub err: Option<TransactionError>,
+    pub pre_balances: Vec<u64>,
+    pub post_balances: Vec<u64>,
+    #[serde(default)]
+    pub pre_token_balances: Option<Vec<UiTransactionTokenBalance>>,
+    #[serde(default)]
+    pub post_token_balances: Option<Vec<UiTransactionTokenBalance>>,
+    #[serde(default)]
+    pub inner_instructions: Option<Vec<UiInnerInstructions>>,
+    #[serde(default)]
+    pub loaded_addresses: Option<LoadedAddresses>, // Addresses loaded from lookup tables
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcVersionedConfirmedTransaction {
+    slot: Slot,
+    block_time: Option<UnixTimestamp>,
+    transaction: (String, String), // base64 encoded transaction, encoding
+    meta: Option<TransactionMeta>,
+}
+
+#[derive(Debug)]
+pub struct VersionedConfirmedTransaction {
+    pub slot: Slot,
+    pub block_time: Option<UnixTimestamp>,
+    pub transaction: VersionedTransaction,
+    pub meta: TransactionMeta,
+}
+
+impl VersionedConfirmedTransaction {
+    // Static account keys followed by the writable then readonly addresses loaded from lookup
+    // tables, the order in which instructions and balances index accounts
+    pub fn account_keys(&self) -> Result<Vec<Pubkey>, Box<dyn std::error::Error>> {
+        let mut account_keys = self.transaction.message.static_account_keys().to_vec();
+        if let Some(ref loaded_addresses) = self.meta.loaded_addresses {
+            for address in loaded_addresses
+                .writable
+                .iter()
+                .chain(loaded_addresses.readonly.iter())
+            {
+                account_keys.push(Pubkey::from_str(address)?);
+            }
+        }
+        Ok(account_keys)
+    }
+}
+
+// `RpcClient::get_transaction()` of this solana-client predates versioned transactions, which the
+// RPC node only returns when `maxSupportedTransactionVersion` is given
+pub fn get_transaction(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+) -> Result<VersionedConfirmedTransaction, Box<dyn std::error::Error>> {
+    let confirmed_transaction: Option<RpcVersionedConfirmedTransaction> = rpc_client.send(
+        RpcRequest::GetTransaction,
+        json!([
+            signature.to_string(),
+            {
+                "encoding": "base64",
+                "commitment": rpc_client.commitment().commitment,
+                "maxSupportedTransactionVersion": 0,
+            }
+        ]),
+    )?;
+    let confirmed_transaction =
+        confirmed_transaction.ok_or_else(|| format!("Transaction {} not found", signature))?;
+
+    let transaction = bincode::deserialize(&base64::decode(&confirmed_transaction.transaction.0)?)
+        .map_err(|err| format!("Unable to decode transaction {}: {}", signature, err))?;
+
+    Ok(VersionedConfirmedTransaction {
+        slot: confirmed_transaction.slot,
+        block_time: confirmed_transaction.block_time,
+        transaction,
+        meta: confirmed_transaction
+            .meta
+            .ok_or("Transaction metadata not available")?,
+    })
+}
+
+// Returns the top-level and inner instructions executed by a successful transaction. Account
+// metas only carry the account addresses
+pub fn get_executed_instructions(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+) -> Result<Vec<Instruction>, Box<dyn std::error::Error>> {
+    let confirmed_transaction = get_transaction(rpc_client, signature)?;
+    if confirmed_transaction.meta.err.is_some() {
+        return Err("Transaction was not successful".into());
+    }
+
+    let account_keys = confirmed_transaction.account_keys()?;
+    let account_key = |index: u8| {
+        account_keys
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| format!("Malformed instruction in {}", signature))
+    };
+    let instruction = |program_id_index: u8, accounts: &[u8], data: Vec<u8>| {
+        Ok::<_, String>(Instruction {
+            program_id: account_key(program_id_index)?,
+            accounts: accounts
+                .iter()
+                .map(|index| {
+                    account_key(*index).map(|pubkey| AccountMeta::new_readonly(pubkey, false))
+                })
+                .collect::<Result<_, _>>()?,
+            data,
+        })
+    };
+
+    let mut instructions = vec![];
+    for compiled_instruction in confirmed_transaction.transaction.message.instructions() {
+        instructions.push(instruction(
+            compiled_instruction.program_id_index,
+            &compiled_instruction.accounts,
+            compiled_instruction.data.clone(),
+        )?);
+    }
+    for inner_instructions in confirmed_transaction
+        .meta
+        .inner_instructions
+        .unwrap_or_default()
+    {
+        for ui_instruction in inner_instructions.instructions {
+            if let UiInstruction::Compiled(compiled_instruction) = ui_instruction {
+                instructions.push(instruction(
+                    compiled_instruction.program_id_index,
+                    &compiled_instruction.accounts,
+                    bs58::decode(&compiled_instruction.data).into_vec()?,
+                )?);
+            }
+        }
+    }
+    Ok(instructions)
+}
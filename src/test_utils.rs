@@ -0,0 +1,48 @@
+// Fixtures shared by the unit tests
+use {
+    crate::{
+        db::{self, Db, Lot, LotAcquistion, LotAcquistionKind, TrackedAccount},
+        token::MaybeToken,
+    },
+    chrono::NaiveDate,
+    rust_decimal::Decimal,
+    solana_sdk::pubkey::Pubkey,
+    std::path::PathBuf,
+};
+
+// A path of its own under the system temporary directory, which the test removes
+pub fn temp_path() -> PathBuf {
+    std::env::temp_dir().join(format!("sys-test-{}", Pubkey::new_unique()))
+}
+
+// A dry run database is never written, only its directory is created
+pub fn test_db() -> (Db, PathBuf) {
+    let db_path = temp_path();
+    (db::new_dry_run(&db_path).unwrap(), db_path)
+}
+
+// A SOL account holding `amount` lamports in a single lot
+pub fn add_test_account(db: &mut Db, address: Pubkey, amount: u64) {
+    let lot = Lot {
+        lot_number: db.next_lot_number(),
+        acquisition: LotAcquistion::new(
+            NaiveDate::from_ymd(2022, 3, 4),
+            Decimal::from(100),
+            LotAcquistionKind::NotAvailable,
+        ),
+        amount,
+    };
+    db.add_account(TrackedAccount {
+        address,
+        token: MaybeToken::SOL(),
+        description: String::default(),
+        last_update_epoch: 0,
+        last_update_balance: amount,
+        lots: vec![lot],
+        no_sync: None,
+        last_update_signature: None,
+        last_stake_change_signature: None,
+        token_account: None,
+    })
+    .unwrap();
+}
@@ -1,31 +1,30 @@
 use {
-    crate::coin_gecko,
+    crate::{
+        coin_gecko,
+        currency::Currency,
+        field_as_string,
+        token_registry::{self, TokenRegistryEntry},
+    },
     chrono::prelude::*,
     rust_decimal::prelude::*,
     serde::{Deserialize, Serialize},
     solana_client::rpc_client::RpcClient,
     solana_sdk::{
+        instruction::{AccountMeta, Instruction},
         native_token::{lamports_to_sol, sol_to_lamports},
+        program_pack::Pack,
         pubkey,
         pubkey::Pubkey,
+        system_program, sysvar,
     },
     std::str::FromStr,
     strum::{EnumString, IntoStaticStr},
 };
 
+pub const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PQnG3kzwSwu5ksg");
+
 #[derive(
-    Debug,
-    PartialEq,
-    Eq,
-    Hash,
-    Copy,
-    Clone,
-    Serialize,
-    Deserialize,
-    EnumString,
-    IntoStaticStr,
-    PartialOrd,
-    Ord,
+    Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize, IntoStaticStr, PartialOrd, Ord,
 )]
 #[allow(clippy::upper_case_acronyms)]
 #[allow(non_camel_case_types)]
@@ -38,9 +37,31 @@ pub enum Token {
     tumSOL,
     tustSOL,
     wSOL,
+    Registered(#[serde(with = "field_as_string")] Pubkey), // Mint of a token registry entry
 }
 
+pub const BUILTIN_TOKENS: &[Token] = &[
+    Token::USDC,
+    Token::tuUSDC,
+    Token::mSOL,
+    Token::stSOL,
+    Token::tuSOL,
+    Token::tumSOL,
+    Token::tustSOL,
+    Token::wSOL,
+];
+
 impl Token {
+    pub fn registry_entry(&self) -> Option<&'static TokenRegistryEntry> {
+        match self {
+            Token::Registered(mint) => Some(
+                token_registry::get(mint)
+                    .unwrap_or_else(|| panic!("Token {} is missing from the token registry", mint)),
+            ),
+            _ => None,
+        }
+    }
+
     pub fn mint(&self) -> Pubkey {
         match self {
             Token::USDC => pubkey!("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v"),
@@ -51,11 +72,114 @@ impl Token {
             Token::tumSOL => pubkey!("8cn7JcYVjDZesLa3RTt3NXne4WcDw9PdUneQWuByehwW"),
             Token::tustSOL => pubkey!("27CaAiuFW3EwLcTCaiBnexqm5pxht845AHgSuq36byKX"),
             Token::wSOL => spl_token::native_mint::id(),
+            Token::Registered(mint) => *mint,
+        }
+    }
+
+    pub fn token_program(&self) -> Pubkey {
+        match self.registry_entry() {
+            Some(entry) if entry.token_2022 => TOKEN_2022_PROGRAM_ID,
+            _ => spl_token::id(),
         }
     }
 
     pub fn ata(&self, wallet_address: &Pubkey) -> Pubkey {
-        spl_associated_token_account::get_associated_token_address(wallet_address, &self.mint())
+        Pubkey::find_program_address(
+            &[
+                &wallet_address.to_bytes(),
+                &self.token_program().to_bytes(),
+                &self.mint().to_bytes(),
+            ],
+            &spl_associated_token_account::id(),
+        )
+        .0
+    }
+
+    // `spl_associated_token_account::create_associated_token_account()` only supports the
+    // classic token program
+    pub fn create_ata_instruction(
+        &self,
+        funding_address: &Pubkey,
+        wallet_address: &Pubkey,
+    ) -> Instruction {
+        Instruction {
+            program_id: spl_associated_token_account::id(),
+            accounts: vec![
+                AccountMeta::new(*funding_address, true),
+                AccountMeta::new(self.ata(wallet_address), false),
+                AccountMeta::new_readonly(*wallet_address, false),
+                AccountMeta::new_readonly(self.mint(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(self.token_program(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+            ],
+            data: vec![],
+        }
+    }
+
+    // The `TransferChecked` instruction is encoded identically by both token programs
+    pub fn transfer_checked_instruction(
+        &self,
+        source: &Pubkey,
+        destination: &Pubkey,
+        authority: &Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        let mut instruction = spl_token::instruction::transfer_checked(
+            &spl_token::id(),
+            source,
+            &self.mint(),
+            destination,
+            authority,
+            &[],
+            amount,
+            self.decimals(),
+        )
+        .unwrap();
+        instruction.program_id = self.token_program();
+        instruction
+    }
+
+    // Returns the owner of `token_account` after confirming that it holds this token
+    pub fn get_token_account_owner(
+        &self,
+        rpc_client: &RpcClient,
+        token_account: &Pubkey,
+    ) -> Result<Pubkey, Box<dyn std::error::Error>> {
+        let account = rpc_client
+            .get_account_with_commitment(token_account, rpc_client.commitment())?
+            .value
+            .ok_or_else(|| format!("Token account {} does not exist", token_account))?;
+
+        if account.owner != self.token_program() {
+            return Err(format!(
+                "Token account {} is owned by {}, expected {}",
+                token_account,
+                account.owner,
+                self.token_program()
+            )
+            .into());
+        }
+
+        // Token-2022 accounts share the classic layout, followed by any extensions
+        if account.data.len() < spl_token::state::Account::LEN {
+            return Err(format!("{} is not a token account", token_account).into());
+        }
+        let token_account_state = spl_token::state::Account::unpack_from_slice(
+            &account.data[..spl_token::state::Account::LEN],
+        )
+        .map_err(|err| format!("{} is not a token account: {}", token_account, err))?;
+
+        if token_account_state.mint != self.mint() {
+            return Err(format!(
+                "Token account {} holds mint {}, not {}",
+                token_account,
+                token_account_state.mint,
+                self.mint()
+            )
+            .into());
+        }
+        Ok(token_account_state.owner)
     }
 
     pub fn symbol(&self) -> &'static str {
@@ -68,6 +192,7 @@ impl Token {
             Token::tumSOL => "🌷m◎",
             Token::tustSOL => "🌷st◎",
             Token::wSOL => "(◎)",
+            Token::Registered(_) => &self.registry_entry().unwrap().symbol,
         }
     }
 
@@ -80,6 +205,7 @@ impl Token {
             | Token::tumSOL
             | Token::tustSOL
             | Token::wSOL => 9,
+            Token::Registered(_) => self.registry_entry().unwrap().decimals,
         }
     }
 
@@ -92,7 +218,21 @@ impl Token {
     }
 
     pub fn name(&self) -> &'static str {
-        self.into()
+        match self {
+            Token::Registered(_) => &self.registry_entry().unwrap().symbol,
+            _ => self.into(),
+        }
+    }
+
+    pub fn coin_gecko_id(&self) -> Option<&'static str> {
+        match self {
+            Token::USDC => Some("usd-coin"),
+            Token::mSOL => Some("msol"),
+            Token::stSOL => Some("lido-staked-sol"),
+            Token::wSOL => Some("solana"),
+            Token::tuUSDC | Token::tuSOL | Token::tumSOL | Token::tustSOL => None,
+            Token::Registered(_) => self.registry_entry().unwrap().coin_gecko_id.as_deref(),
+        }
     }
 
     pub fn fiat_fungible(&self) -> bool {
@@ -106,6 +246,16 @@ impl Token {
             Token::tuUSDC | Token::tuSOL | Token::tumSOL | Token::tustSOL => {
                 Some(crate::tulip::liquidity_token(self))
             }
+            Token::Registered(_) => {
+                self.registry_entry()
+                    .unwrap()
+                    .liquidity_token
+                    .as_ref()
+                    .map(|liquidity_token| match liquidity_token.as_str() {
+                        "SOL" => MaybeToken::SOL(),
+                        liquidity_token => Token::from_str(liquidity_token).unwrap().into(),
+                    })
+            }
         }
     }
 
@@ -115,7 +265,11 @@ impl Token {
     ) -> Result<Decimal, Box<dyn std::error::Error>> {
         match self {
             Token::USDC | Token::mSOL | Token::stSOL | Token::wSOL => unreachable!(),
-            Token::tuUSDC | Token::tuSOL | Token::tumSOL | Token::tustSOL => {
+            Token::tuUSDC
+            | Token::tuSOL
+            | Token::tumSOL
+            | Token::tustSOL
+            | Token::Registered(_) => {
                 crate::tulip::get_current_liquidity_token_rate(rpc_client, self).await
             }
         }
@@ -125,14 +279,22 @@ impl Token {
         &self,
         rpc_client: &RpcClient,
         address: &Pubkey,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        self.token_account_balance(rpc_client, &self.ata(address))
+    }
+
+    pub fn token_account_balance(
+        &self,
+        rpc_client: &RpcClient,
+        token_account: &Pubkey,
     ) -> Result<u64, Box<dyn std::error::Error>> {
         Ok(u64::from_str(
             &rpc_client
-                .get_token_account_balance(&self.ata(address))
+                .get_token_account_balance(token_account)
                 .map_err(|_| {
                     format!(
-                        "Could not get balance for account {}, token {}",
-                        address,
+                        "Could not get balance for token account {}, token {}",
+                        token_account,
                         self.name(),
                     )
                 })?
@@ -145,16 +307,24 @@ impl Token {
     pub async fn get_current_price(
         &self,
         rpc_client: &RpcClient,
+        currency: Currency,
     ) -> Result<Decimal, Box<dyn std::error::Error>> {
-        if self.fiat_fungible() {
+        if self.fiat_fungible() && currency == Currency::USD {
             return Ok(Decimal::from_f64(1.).unwrap());
         }
         match self {
             Token::USDC | Token::mSOL | Token::stSOL | Token::wSOL => {
-                coin_gecko::get_current_price(&MaybeToken(Some(*self))).await
+                coin_gecko::get_current_price(&MaybeToken(Some(*self)), currency).await
             }
             Token::tuUSDC | Token::tuSOL | Token::tumSOL | Token::tustSOL => {
-                crate::tulip::get_current_price(rpc_client, self).await
+                crate::tulip::get_current_price(rpc_client, self, currency).await
+            }
+            Token::Registered(_) => {
+                if self.liquidity_token().is_some() {
+                    crate::tulip::get_current_price(rpc_client, self, currency).await
+                } else {
+                    coin_gecko::get_current_price(&MaybeToken(Some(*self)), currency).await
+                }
             }
         }
     }
@@ -163,12 +333,18 @@ impl Token {
         &self,
         _rpc_client: &RpcClient,
         when: NaiveDate,
+        currency: Currency,
     ) -> Result<Decimal, Box<dyn std::error::Error>> {
-        if self.fiat_fungible() {
+        if self.fiat_fungible() && currency == Currency::USD {
             return Ok(Decimal::from_f64(1.).unwrap());
         }
         match self {
-            Token::USDC => coin_gecko::get_historical_price(when, &MaybeToken(Some(*self))).await,
+            Token::USDC => {
+                coin_gecko::get_historical_price(when, &MaybeToken(Some(*self)), currency).await
+            }
+            Token::Registered(_) if self.coin_gecko_id().is_some() => {
+                coin_gecko::get_historical_price(when, &MaybeToken(Some(*self)), currency).await
+            }
             unsupported_token => Err(format!(
                 "Historical price data is not available for {}",
                 unsupported_token.name()
@@ -176,6 +352,44 @@ impl Token {
             .into()),
         }
     }
+
+    pub async fn get_historical_price_at(
+        &self,
+        _rpc_client: &RpcClient,
+        when: NaiveDateTime,
+        currency: Currency,
+    ) -> Result<Decimal, Box<dyn std::error::Error>> {
+        if self.fiat_fungible() && currency == Currency::USD {
+            return Ok(Decimal::from_f64(1.).unwrap());
+        }
+        match self {
+            Token::USDC => {
+                coin_gecko::get_historical_price_at(when, &MaybeToken(Some(*self)), currency).await
+            }
+            Token::Registered(_) if self.coin_gecko_id().is_some() => {
+                coin_gecko::get_historical_price_at(when, &MaybeToken(Some(*self)), currency).await
+            }
+            unsupported_token => Err(format!(
+                "Intraday historical price data is not available for {}",
+                unsupported_token.name()
+            )
+            .into()),
+        }
+    }
+}
+
+// Accepts a built-in token name, or the symbol or mint address of a token registry entry
+impl FromStr for Token {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BUILTIN_TOKENS
+            .iter()
+            .find(|token| token.name() == s || Pubkey::from_str(s) == Ok(token.mint()))
+            .copied()
+            .or_else(|| token_registry::find(s).map(|entry| Token::Registered(entry.mint)))
+            .ok_or_else(|| format!("Unknown token: {}", s))
+    }
 }
 
 pub fn is_valid_token(value: String) -> Result<(), String> {
@@ -273,13 +487,26 @@ impl MaybeToken {
         }
     }
 
+    // Like `balance()`, but `balance_address` is the token account itself rather than its owner
+    pub fn balance_at(
+        &self,
+        rpc_client: &RpcClient,
+        balance_address: &Pubkey,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        match self.0 {
+            None => self.balance(rpc_client, balance_address),
+            Some(token) => token.token_account_balance(rpc_client, balance_address),
+        }
+    }
+
     pub async fn get_current_price(
         &self,
         rpc_client: &RpcClient,
+        currency: Currency,
     ) -> Result<Decimal, Box<dyn std::error::Error>> {
         match self.0 {
-            None => coin_gecko::get_current_price(self).await,
-            Some(token) => token.get_current_price(rpc_client).await,
+            None => coin_gecko::get_current_price(self, currency).await,
+            Some(token) => token.get_current_price(rpc_client, currency).await,
         }
     }
 
@@ -287,10 +514,28 @@ impl MaybeToken {
         &self,
         rpc_client: &RpcClient,
         when: NaiveDate,
+        currency: Currency,
     ) -> Result<Decimal, Box<dyn std::error::Error>> {
         match self.0 {
-            None => coin_gecko::get_historical_price(when, self).await,
-            Some(token) => token.get_historical_price(rpc_client, when).await,
+            None => coin_gecko::get_historical_price(when, self, currency).await,
+            Some(token) => token.get_historical_price(rpc_client, when, currency).await,
+        }
+    }
+
+    // `when` is in UTC
+    pub async fn get_historical_price_at(
+        &self,
+        rpc_client: &RpcClient,
+        when: NaiveDateTime,
+        currency: Currency,
+    ) -> Result<Decimal, Box<dyn std::error::Error>> {
+        match self.0 {
+            None => coin_gecko::get_historical_price_at(when, self, currency).await,
+            Some(token) => {
+                token
+                    .get_historical_price_at(rpc_client, when, currency)
+                    .await
+            }
         }
     }
 }
@@ -321,3 +566,26 @@ impl std::fmt::Display for Token {
         write!(f, "{}", self.name())
     }
 }
+
+// Source of historical prices when re-pricing existing lots
+#[derive(Debug, PartialEq, Eq, Copy, Clone, EnumString, IntoStaticStr)]
+pub enum PriceSource {
+    #[strum(serialize = "coingecko")]
+    CoinGecko, // Daily price
+    #[strum(serialize = "coingecko-intraday")]
+    CoinGeckoIntraday, // Price nearest to the acquisition time, when known
+}
+
+pub const POSSIBLE_PRICE_SOURCE_VALUES: &[&str] = &["coingecko", "coingecko-intraday"];
+
+impl Default for PriceSource {
+    fn default() -> Self {
+        Self::CoinGecko
+    }
+}
+
+impl std::fmt::Display for PriceSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", <&str>::from(self))
+    }
+}
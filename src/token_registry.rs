@@ -0,0 +1,186 @@
+use {
+    crate::{field_as_string, option_field_as_string},
+    once_cell::sync::OnceCell,
+    serde::{Deserialize, Serialize},
+    solana_sdk::pubkey::Pubkey,
+    std::{collections::HashSet, fs, path::Path, str::FromStr},
+};
+
+// A user-defined SPL token, in addition to the built-in `Token` variants
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenRegistryEntry {
+    #[serde(with = "field_as_string")]
+    pub mint: Pubkey,
+    pub symbol: String,
+    pub decimals: u8,
+    #[serde(default)]
+    pub coin_gecko_id: Option<String>,
+    #[serde(default)]
+    pub liquidity_token: Option<String>, // "SOL" or a token name
+    #[serde(default, with = "option_field_as_string")]
+    pub tulip_reserve: Option<Pubkey>, // Lending reserve providing the liquidity token rate
+    #[serde(default)]
+    pub token_2022: bool, // Mint is owned by the Token-2022 program rather than the token program
+}
+
+static TOKEN_REGISTRY: OnceCell<Vec<TokenRegistryEntry>> = OnceCell::new();
+
+// Loads the registry from a JSON array of entries. A missing file is an empty registry
+pub fn load(
+    path: &Path,
+    reserved_names: &[&str],
+    reserved_mints: &[Pubkey],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = if path.exists() {
+        parse(&fs::read_to_string(path)?, reserved_names, reserved_mints)
+            .map_err(|err| format!("Invalid token registry {}: {}", path.display(), err))?
+    } else {
+        vec![]
+    };
+
+    TOKEN_REGISTRY
+        .set(entries)
+        .map_err(|_| "Token registry already loaded".into())
+}
+
+fn parse(
+    contents: &str,
+    reserved_names: &[&str],
+    reserved_mints: &[Pubkey],
+) -> Result<Vec<TokenRegistryEntry>, Box<dyn std::error::Error>> {
+    let entries: Vec<TokenRegistryEntry> = serde_json::from_str(contents)?;
+
+    let mut names = reserved_names.iter().copied().collect::<HashSet<_>>();
+    let mut mints = reserved_mints.iter().copied().collect::<HashSet<_>>();
+    for entry in &entries {
+        if !names.insert(entry.symbol.as_str()) || Pubkey::from_str(&entry.symbol).is_ok() {
+            return Err(format!("Token registry symbol is not unique: {}", entry.symbol).into());
+        }
+        if !mints.insert(entry.mint) {
+            return Err(format!("Token registry mint is not unique: {}", entry.mint).into());
+        }
+        if entry.liquidity_token.is_some() != entry.tulip_reserve.is_some() {
+            return Err(format!(
+                "Token registry entry {} requires both a liquidity token and a Tulip reserve",
+                entry.symbol
+            )
+            .into());
+        }
+    }
+    for entry in &entries {
+        if let Some(ref liquidity_token) = entry.liquidity_token {
+            if liquidity_token != "SOL" && !names.contains(liquidity_token.as_str()) {
+                return Err(format!(
+                    "Token registry entry {} has an unknown liquidity token: {}",
+                    entry.symbol, liquidity_token
+                )
+                .into());
+            }
+        }
+    }
+    Ok(entries)
+}
+
+pub fn entries() -> &'static [TokenRegistryEntry] {
+    TOKEN_REGISTRY
+        .get()
+        .map(|entries| &entries[..])
+        .unwrap_or(&[])
+}
+
+pub fn get(mint: &Pubkey) -> Option<&'static TokenRegistryEntry> {
+    entries().iter().find(|entry| entry.mint == *mint)
+}
+
+// Finds an entry by symbol or mint address
+pub fn find(name: &str) -> Option<&'static TokenRegistryEntry> {
+    entries().iter().find(|entry| {
+        entry.symbol == name || Pubkey::from_str(name).map_or(false, |mint| entry.mint == mint)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINT: &str = "7dHbWXmci3dT8UFYWYZweBLXgycu7Y3iL6trKn1Y7ARj";
+    const OTHER_MINT: &str = "So11111111111111111111111111111111111111112";
+
+    fn entry(mint: &str, symbol: &str) -> String {
+        format!(
+            r#"{{"mint": "{}", "symbol": "{}", "decimals": 9}}"#,
+            mint, symbol
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        let entries = parse(
+            &format!(
+                r#"[{}, {{"mint": "{}", "symbol": "xSTSOL", "decimals": 9,
+                    "liquidity_token": "STSOL", "tulip_reserve": "{}", "token_2022": true}}]"#,
+                entry(MINT, "STSOL"),
+                OTHER_MINT,
+                MINT
+            ),
+            &["USDC"],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].mint, Pubkey::from_str(MINT).unwrap());
+        assert_eq!(entries[0].coin_gecko_id, None);
+        assert!(!entries[0].token_2022);
+        assert_eq!(entries[1].liquidity_token.as_deref(), Some("STSOL"));
+        assert_eq!(
+            entries[1].tulip_reserve,
+            Some(Pubkey::from_str(MINT).unwrap())
+        );
+        assert!(entries[1].token_2022);
+
+        assert!(parse("[]", &[], &[]).unwrap().is_empty());
+        assert!(parse("{}", &[], &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_duplicates() {
+        // Symbols and mints must not collide with each other or the built-in tokens
+        let two_entries = |a: String, b: String| format!("[{}, {}]", a, b);
+        assert!(parse(
+            &two_entries(entry(MINT, "A"), entry(OTHER_MINT, "A")),
+            &[],
+            &[]
+        )
+        .is_err());
+        assert!(parse(&two_entries(entry(MINT, "A"), entry(MINT, "B")), &[], &[]).is_err());
+        assert!(parse(&format!("[{}]", entry(MINT, "USDC")), &["USDC"], &[]).is_err());
+        assert!(parse(
+            &format!("[{}]", entry(MINT, "A")),
+            &[],
+            &[Pubkey::from_str(MINT).unwrap()]
+        )
+        .is_err());
+
+        // A symbol that parses as an address would be ambiguous with `--token <MINT>`
+        assert!(parse(&format!("[{}]", entry(MINT, OTHER_MINT)), &[], &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_liquidity_token() {
+        let with_liquidity = |liquidity_token: &str, tulip_reserve: Option<&str>| {
+            let mut entry = format!(
+                r#"{{"mint": "{}", "symbol": "A", "decimals": 6, "liquidity_token": "{}""#,
+                MINT, liquidity_token
+            );
+            if let Some(tulip_reserve) = tulip_reserve {
+                entry += &format!(r#", "tulip_reserve": "{}""#, tulip_reserve);
+            }
+            format!("[{}}}]", entry)
+        };
+
+        assert!(parse(&with_liquidity("SOL", Some(OTHER_MINT)), &[], &[]).is_ok());
+        assert!(parse(&with_liquidity("USDC", Some(OTHER_MINT)), &["USDC"], &[]).is_ok());
+        assert!(parse(&with_liquidity("SOL", None), &[], &[]).is_err());
+        assert!(parse(&with_liquidity("NOPE", Some(OTHER_MINT)), &[], &[]).is_err());
+    }
+}
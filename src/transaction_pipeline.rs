@@ -0,0 +1,1094 @@
+use {
+    crate::{
+        db::{
+            Db, DbResult, PendingMultisigProposal, ProposalInstruction, TransactionSettings,
+            OFFLINE_BLOCK_HEIGHT,
+        },
+        dry_run, option_field_as_string,
+        pubsub::{self, Pubsub},
+    },
+    once_cell::sync::OnceCell,
+    serde::{Deserialize, Serialize},
+    serde_json::json,
+    solana_client::{
+        nonce_utils,
+        rpc_client::RpcClient,
+        rpc_request::RpcRequest,
+        rpc_response::{Response, RpcSimulateTransactionResult},
+    },
+    solana_sdk::{
+        hash::{hash, Hash},
+        instruction::Instruction,
+        message::{Message, VersionedMessage},
+        native_token::lamports_to_sol,
+        pubkey,
+        pubkey::Pubkey,
+        signature::Signature,
+        signer::SignerError,
+        signers::Signers,
+        system_instruction::{self, SystemInstruction},
+        system_program,
+        transaction::{Transaction, VersionedTransaction},
+    },
+    std::{
+        fs,
+        path::{Path, PathBuf},
+        thread::sleep,
+        time::{Duration, Instant},
+    },
+};
+
+const COMPUTE_BUDGET_PROGRAM_ID: Pubkey = pubkey!("ComputeBudget111111111111111111111111111111");
+const MAX_COMPUTE_UNIT_LIMIT: u64 = 1_400_000;
+
+// The solana-sdk in use predates `ComputeBudgetInstruction::set_compute_unit_limit()` and
+// `ComputeBudgetInstruction::set_compute_unit_price()`, so they are encoded by hand
+fn set_compute_unit_limit(units: u32) -> Instruction {
+    let mut data = vec![2];
+    data.extend_from_slice(&units.to_le_bytes());
+    Instruction::new_with_bytes(COMPUTE_BUDGET_PROGRAM_ID, &data, vec![])
+}
+
+fn set_compute_unit_price(micro_lamports: u64) -> Instruction {
+    let mut data = vec![3];
+    data.extend_from_slice(&micro_lamports.to_le_bytes());
+    Instruction::new_with_bytes(COMPUTE_BUDGET_PROGRAM_ID, &data, vec![])
+}
+
+// Messages compiled elsewhere, such as a Jupiter swap, go through `execute_versioned()` instead
+pub enum TransactionPayload<'a> {
+    Instructions {
+        instructions: &'a [Instruction],
+        payer: &'a Pubkey,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PendingKind {
+    Deposit,
+    Swap,
+    Transfer,
+}
+
+impl PendingKind {
+    pub fn of(db: &Db, signature: Signature) -> Option<Self> {
+        if db
+            .pending_deposits(None)
+            .iter()
+            .any(|pd| pd.transfer.signature == signature)
+        {
+            Some(Self::Deposit)
+        } else if db
+            .pending_swaps()
+            .iter()
+            .any(|ps| ps.signature == signature)
+        {
+            Some(Self::Swap)
+        } else if db
+            .pending_transfers()
+            .iter()
+            .any(|pt| pt.signature == signature)
+        {
+            Some(Self::Transfer)
+        } else {
+            None
+        }
+    }
+
+    // Recorded for a transaction signed outside of `sys`, which only `sys tx submit` or a
+    // multisig proposal completes
+    pub fn is_offline(&self, db: &Db, signature: Signature) -> bool {
+        let last_valid_block_height = match self {
+            Self::Deposit => db
+                .pending_deposits(None)
+                .into_iter()
+                .find(|pd| pd.transfer.signature == signature)
+                .map(|pd| pd.transfer.last_valid_block_height),
+            Self::Swap => db
+                .pending_swaps()
+                .into_iter()
+                .find(|ps| ps.signature == signature)
+                .map(|ps| ps.last_valid_block_height),
+            Self::Transfer => db
+                .pending_transfers()
+                .into_iter()
+                .find(|pt| pt.signature == signature)
+                .map(|pt| pt.last_valid_block_height),
+        };
+        last_valid_block_height == Some(OFFLINE_BLOCK_HEIGHT)
+    }
+
+    pub fn cancel(&self, db: &mut Db, signature: Signature) -> DbResult<()> {
+        match self {
+            Self::Deposit => db.cancel_deposit(signature),
+            Self::Swap => db.cancel_swap(signature),
+            Self::Transfer => db.cancel_transfer(signature),
+        }
+    }
+}
+
+// How a transaction is reflected in `Db` while in flight. `record` is invoked with the signature
+// and last valid block height of each attempt, which is cancelled again if the attempt fails
+pub struct Pending<'a> {
+    pub kind: PendingKind,
+    #[allow(clippy::type_complexity)]
+    pub record: Box<dyn FnMut(&mut Db, Signature, u64) -> DbResult<()> + 'a>,
+}
+
+impl<'a> Pending<'a> {
+    pub fn new<F>(kind: PendingKind, record: F) -> Self
+    where
+        F: FnMut(&mut Db, Signature, u64) -> DbResult<()> + 'a,
+    {
+        Self {
+            kind,
+            record: Box::new(record),
+        }
+    }
+}
+
+// A nonce account whose stored blockhash stands in for a recent blockhash, so that a transaction
+// signed offline doesn't expire before it's submitted
+struct DurableNonce {
+    account: Pubkey,
+    authority: Pubkey,
+}
+
+pub enum SignOnlyMode {
+    DurableNonce(Pubkey),     // Unsigned transaction using the durable nonce account
+    MultisigProposal(Pubkey), // Instructions for the multisig proposal account to execute
+}
+
+struct SignOnly {
+    mode: SignOnlyMode,
+    output: PathBuf,
+}
+
+static SIGN_ONLY: OnceCell<SignOnly> = OnceCell::new();
+
+// With `--sign-only`, the transaction of the command is written to `output` for offline signing
+// or multisig execution rather than sent
+pub fn set_sign_only(mode: SignOnlyMode, output: PathBuf) {
+    let _ = SIGN_ONLY.set(SignOnly { mode, output });
+}
+
+pub fn multisig_proposal() -> Option<Pubkey> {
+    match SIGN_ONLY.get()?.mode {
+        SignOnlyMode::MultisigProposal(proposal) => Some(proposal),
+        SignOnlyMode::DurableNonce(_) => None,
+    }
+}
+
+// Placeholder recorded for a pending operation until the signature of the transaction that
+// carries it out is known
+fn provisional_signature(message: &Message) -> Signature {
+    let message_hash = hash(&message.serialize());
+    Signature::new(&[message_hash.as_ref(), message_hash.as_ref()].concat())
+}
+
+// A transaction awaiting its offline signatures. The pending deposit, swap or transfer of the
+// transaction is recorded under `pending_signature` until it's submitted
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OfflineTransaction {
+    pub transaction: Transaction,
+    #[serde(default, with = "option_field_as_string")]
+    pub pending_signature: Option<Signature>,
+}
+
+impl OfflineTransaction {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| format!("Invalid transaction file {}: {}", path.display(), err).into())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn missing_signers(&self) -> Vec<Pubkey> {
+        self.transaction
+            .signatures
+            .iter()
+            .zip(self.transaction.message.account_keys.iter())
+            .filter(|(signature, _)| **signature == Signature::default())
+            .map(|(_, signer)| *signer)
+            .collect()
+    }
+
+    // The nonce account advanced by the first instruction of the transaction
+    fn durable_nonce_account(&self) -> Option<Pubkey> {
+        let message = &self.transaction.message;
+        let instruction = message.instructions.get(0)?;
+        if message
+            .account_keys
+            .get(instruction.program_id_index as usize)
+            != Some(&system_program::id())
+        {
+            return None;
+        }
+        match bincode::deserialize::<SystemInstruction>(&instruction.data) {
+            Ok(SystemInstruction::AdvanceNonceAccount) => instruction
+                .accounts
+                .get(0)
+                .and_then(|index| message.account_keys.get(*index as usize))
+                .copied(),
+            _ => None,
+        }
+    }
+}
+
+enum Expiry {
+    BlockHeight(u64),
+    DurableNonce { account: Pubkey, blockhash: Hash },
+}
+
+fn static_account_keys(message: &VersionedMessage) -> &[Pubkey] {
+    match message {
+        VersionedMessage::Legacy(message) => &message.account_keys,
+        VersionedMessage::V0(message) => &message.account_keys,
+    }
+}
+
+// Each signature goes in the position of its signer among the message's required signers
+fn sign_versioned<T: Signers>(
+    message: VersionedMessage,
+    signers: &T,
+) -> Result<VersionedTransaction, SignerError> {
+    let num_required_signatures = match &message {
+        VersionedMessage::Legacy(message) => message.header.num_required_signatures,
+        VersionedMessage::V0(message) => message.header.num_required_signatures,
+    } as usize;
+    let message_data = message.serialize();
+
+    let mut signatures = vec![Signature::default(); num_required_signatures];
+    for (pubkey, signature) in signers
+        .pubkeys()
+        .into_iter()
+        .zip(signers.try_sign_message(&message_data)?)
+    {
+        let position = static_account_keys(&message)[..num_required_signatures]
+            .iter()
+            .position(|key| *key == pubkey)
+            .ok_or(SignerError::KeypairPubkeyMismatch)?;
+        signatures[position] = signature;
+    }
+    if signatures
+        .iter()
+        .any(|signature| *signature == Signature::default())
+    {
+        return Err(SignerError::NotEnoughSigners);
+    }
+
+    Ok(VersionedTransaction {
+        signatures,
+        message,
+    })
+}
+
+// Shared by every command that submits a transaction: compute unit estimation, priority fees,
+// periodic rebroadcast and a fresh blockhash should an attempt expire
+pub struct TransactionPipeline<'a> {
+    rpc_client: &'a RpcClient,
+    settings: TransactionSettings,
+    pubsub: Option<&'a dyn Pubsub>,
+}
+
+impl<'a> TransactionPipeline<'a> {
+    pub fn new(rpc_client: &'a RpcClient, db: &Db) -> Self {
+        Self {
+            rpc_client,
+            settings: db.get_transaction_settings(),
+            pubsub: pubsub::get().map(|pubsub| pubsub as &dyn Pubsub),
+        }
+    }
+
+    // Base fee of a transaction with a single signature
+    pub fn get_lamports_per_signature(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut message = Message::new(&[], Some(&Pubkey::new_unique()));
+        message.recent_blockhash = self.rpc_client.get_latest_blockhash()?;
+        Ok(self.rpc_client.get_fee_for_message(&message)?)
+    }
+
+    // Upper bound of the fee for a transaction with `num_signatures` signatures, including any
+    // priority fee
+    pub fn get_max_fee(&self, num_signatures: u64) -> Result<u64, Box<dyn std::error::Error>> {
+        let max_priority_fee = (u128::from(self.settings.compute_unit_price)
+            * u128::from(MAX_COMPUTE_UNIT_LIMIT)
+            + 999_999)
+            / 1_000_000;
+        Ok(num_signatures * self.get_lamports_per_signature()? + max_priority_fee as u64)
+    }
+
+    fn compile(
+        &self,
+        payload: &TransactionPayload,
+        compute_unit_limit: u64,
+        durable_nonce: Option<&DurableNonce>,
+    ) -> Message {
+        match payload {
+            TransactionPayload::Instructions {
+                instructions,
+                payer,
+            } => {
+                let mut all_instructions = vec![];
+                if let Some(durable_nonce) = durable_nonce {
+                    // Must be the first instruction for the runtime to accept the nonce blockhash
+                    all_instructions.push(system_instruction::advance_nonce_account(
+                        &durable_nonce.account,
+                        &durable_nonce.authority,
+                    ));
+                }
+                if self.settings.compute_unit_price > 0 {
+                    all_instructions.push(set_compute_unit_limit(compute_unit_limit as u32));
+                    all_instructions.push(set_compute_unit_price(self.settings.compute_unit_price));
+                }
+                all_instructions.extend_from_slice(instructions);
+                Message::new(&all_instructions, Some(payer))
+            }
+        }
+    }
+
+    // Returns an unsigned transaction with a fresh blockhash, and its last valid block height.
+    // The compute unit limit is sized from a simulation of the transaction
+    pub fn prepare(
+        &self,
+        payload: &TransactionPayload,
+    ) -> Result<(Transaction, u64), Box<dyn std::error::Error>> {
+        let (recent_blockhash, last_valid_block_height) = self
+            .rpc_client
+            .get_latest_blockhash_with_commitment(self.rpc_client.commitment())?;
+        Ok((
+            self.prepare_with_blockhash(payload, recent_blockhash, None)?,
+            last_valid_block_height,
+        ))
+    }
+
+    fn get_durable_nonce_blockhash(
+        &self,
+        durable_nonce_account: &Pubkey,
+    ) -> Result<(Hash, Pubkey), Box<dyn std::error::Error>> {
+        let account = nonce_utils::get_account_with_commitment(
+            self.rpc_client,
+            durable_nonce_account,
+            self.rpc_client.commitment(),
+        )?;
+        let data = nonce_utils::data_from_account(&account)?;
+        Ok((data.blockhash, data.authority))
+    }
+
+    fn prepare_with_blockhash(
+        &self,
+        payload: &TransactionPayload,
+        recent_blockhash: Hash,
+        durable_nonce: Option<&DurableNonce>,
+    ) -> Result<Transaction, Box<dyn std::error::Error>> {
+        let mut transaction =
+            Transaction::new_unsigned(self.compile(payload, MAX_COMPUTE_UNIT_LIMIT, durable_nonce));
+        transaction.message.recent_blockhash = recent_blockhash;
+
+        let simulation_result = self.rpc_client.simulate_transaction(&transaction)?.value;
+        if simulation_result.err.is_some() {
+            return Err(format!("Simulation failure: {:?}", simulation_result).into());
+        }
+
+        if let Some(units_consumed) = simulation_result.units_consumed {
+            // Headroom for account state changing between simulation and execution
+            let compute_unit_limit =
+                (units_consumed + units_consumed / 5 + 1_000).min(MAX_COMPUTE_UNIT_LIMIT);
+            transaction =
+                Transaction::new_unsigned(self.compile(payload, compute_unit_limit, durable_nonce));
+            transaction.message.recent_blockhash = recent_blockhash;
+        }
+
+        let fee = self.rpc_client.get_fee_for_message(&transaction.message)?;
+        let fee_payer = transaction.message.account_keys[0];
+        if self.rpc_client.get_balance(&fee_payer)? < fee {
+            return Err(format!(
+                "Fee payer {} has insufficient funds for the transaction fee of ◎{}",
+                fee_payer,
+                lamports_to_sol(fee)
+            )
+            .into());
+        }
+
+        Ok(transaction)
+    }
+
+    // Final status of a transaction that can no longer land, searched for in the full history
+    fn landed(&self, signature: &Signature) -> Result<bool, Box<dyn std::error::Error>> {
+        match self
+            .rpc_client
+            .get_signature_status_with_commitment_and_history(
+                signature,
+                self.rpc_client.commitment(),
+                true,
+            )? {
+            Some(Ok(())) => Ok(true),
+            Some(Err(err)) => Err(format!("Transaction {} failed: {}", signature, err).into()),
+            None => Ok(false),
+        }
+    }
+
+    // Broadcasts `transaction` every `rebroadcast_interval` seconds until it's confirmed. Returns
+    // false if the transaction expired first.
+    //
+    // With `--ws` the confirmation is received through a signature subscription, and the status
+    // is only polled on each broadcast in case the subscription misses it
+    fn send_until_expired(
+        &self,
+        transaction: &VersionedTransaction,
+        expiry: &Expiry,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let signature = transaction.signatures[0];
+        let rebroadcast_interval = Duration::from_secs(self.settings.rebroadcast_interval.max(1));
+        let mut last_broadcast: Option<Instant> = None;
+
+        let subscription = self.pubsub.and_then(|pubsub| {
+            pubsub
+                .signature_subscribe(&signature, self.rpc_client.commitment())
+                .map_err(|err| println!("Signature subscription failed, polling instead: {}", err))
+                .ok()
+        });
+
+        loop {
+            let broadcast = last_broadcast.map_or(true, |last_broadcast| {
+                last_broadcast.elapsed() >= rebroadcast_interval
+            });
+            if broadcast {
+                match expiry {
+                    Expiry::BlockHeight(last_valid_block_height) => {
+                        match self.rpc_client.get_epoch_info() {
+                            Ok(epoch_info) => {
+                                if epoch_info.block_height > *last_valid_block_height {
+                                    // The transaction may have landed since the last status
+                                    // check, and have already dropped out of the recent
+                                    // status cache
+                                    return self.landed(&signature);
+                                }
+                                println!(
+                                    "Transaction pending for at most {} blocks",
+                                    last_valid_block_height.saturating_sub(epoch_info.block_height),
+                                );
+                            }
+                            Err(err) => {
+                                println!("Failed to get epoch info: {:?}", err);
+                            }
+                        }
+                    }
+                    Expiry::DurableNonce { account, blockhash } => {
+                        match self.get_durable_nonce_blockhash(account) {
+                            Ok((durable_nonce_blockhash, _)) => {
+                                if durable_nonce_blockhash != *blockhash {
+                                    // The nonce also advances when the transaction itself lands
+                                    return self.landed(&signature);
+                                }
+                                println!("Transaction pending until nonce {} advances", account);
+                            }
+                            Err(err) => {
+                                println!("Failed to get nonce account {}: {}", account, err);
+                            }
+                        }
+                    }
+                }
+
+                if let Err(err) = self.rpc_client.send::<String>(
+                    RpcRequest::SendTransaction,
+                    json!([
+                        base64::encode(bincode::serialize(transaction)?),
+                        { "skipPreflight": true, "encoding": "base64" }
+                    ]),
+                ) {
+                    println!("Transaction failed to send: {:?}", err);
+                }
+                last_broadcast = Some(Instant::now());
+            }
+
+            if broadcast || subscription.is_none() {
+                match self.rpc_client.get_signature_status(&signature) {
+                    Ok(Some(Ok(()))) => return Ok(true),
+                    Ok(Some(Err(err))) => {
+                        return Err(format!("Transaction {} failed: {}", signature, err).into())
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        println!("Failed to get signature status: {:?}", err);
+                    }
+                }
+            }
+
+            match &subscription {
+                Some(subscription) => match subscription.recv_timeout(Duration::from_millis(500)) {
+                    Some(Ok(())) => return Ok(true),
+                    Some(Err(err)) => {
+                        return Err(format!("Transaction {} failed: {}", signature, err).into())
+                    }
+                    None => {}
+                },
+                None => sleep(Duration::from_millis(500)),
+            }
+        }
+    }
+
+    // A confirmed transfer is dated and confirmed in `db`, deposits and swaps are left for
+    // `sys sync` to complete
+    async fn confirm(
+        &self,
+        db: &mut Db,
+        pending_kind: Option<PendingKind>,
+        signature: Signature,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if pending_kind == Some(PendingKind::Transfer) {
+            let when =
+                crate::get_signature_date(self.rpc_client, signature, db.get_reporting_timezone())
+                    .await?;
+            db.confirm_transfer(signature, when)?;
+        }
+        Ok(())
+    }
+
+    // Prepares, signs, records and sends the transaction, retrying with a fresh blockhash up to
+    // `max_blockhash_refreshes` times should an attempt expire. A confirmed transfer is dated
+    // and confirmed in `db`, deposits and swaps are left for `sys sync` to complete
+    //
+    // With `--sign-only` the transaction is instead written out for offline signing or multisig
+    // execution and `None` is returned. Anything that depends on the transaction's confirmation
+    // must then wait for its pending record to complete
+    pub async fn execute<S>(
+        &self,
+        db: &mut Db,
+        payload: TransactionPayload<'_>,
+        mut sign: S,
+        mut pending: Option<Pending<'_>>,
+    ) -> Result<Option<Signature>, Box<dyn std::error::Error>>
+    where
+        S: FnMut(&mut Transaction, Hash) -> Result<(), SignerError>,
+    {
+        if let Some(sign_only) = SIGN_ONLY.get() {
+            match sign_only.mode {
+                SignOnlyMode::DurableNonce(durable_nonce_account) => self.write_sign_only(
+                    db,
+                    &payload,
+                    sign,
+                    pending,
+                    durable_nonce_account,
+                    &sign_only.output,
+                )?,
+                SignOnlyMode::MultisigProposal(proposal) => self.write_multisig_proposal(
+                    db,
+                    &payload,
+                    sign,
+                    pending,
+                    proposal,
+                    &sign_only.output,
+                )?,
+            }
+            return Ok(None);
+        }
+
+        if dry_run::enabled() {
+            return self.dry_run(db, &payload, sign, pending).map(Some);
+        }
+
+        for attempt in 0..=self.settings.max_blockhash_refreshes {
+            if attempt > 0 {
+                println!("Transaction expired, retrying with a new blockhash");
+            }
+
+            let (mut transaction, last_valid_block_height) = self.prepare(&payload)?;
+            let recent_blockhash = transaction.message.recent_blockhash;
+            sign(&mut transaction, recent_blockhash)?;
+
+            let signature = transaction.signatures[0];
+            println!("Transaction signature: {}", signature);
+
+            if let Some(ref mut pending) = pending {
+                (pending.record)(db, signature, last_valid_block_height)?;
+            }
+
+            let result = self.send_until_expired(
+                &transaction.into(),
+                &Expiry::BlockHeight(last_valid_block_height),
+            );
+            if let Ok(true) = result {
+                self.confirm(db, pending.as_ref().map(|pending| pending.kind), signature)
+                    .await?;
+                return Ok(Some(signature));
+            }
+
+            if let Some(ref pending) = pending {
+                pending.kind.cancel(db, signature)?;
+            }
+            result?;
+        }
+        Err("Transaction expired".into())
+    }
+
+    // Simulates and signs the transaction, then proceeds as if it had been confirmed today
+    fn dry_run<S>(
+        &self,
+        db: &mut Db,
+        payload: &TransactionPayload,
+        mut sign: S,
+        pending: Option<Pending>,
+    ) -> Result<Signature, Box<dyn std::error::Error>>
+    where
+        S: FnMut(&mut Transaction, Hash) -> Result<(), SignerError>,
+    {
+        let (mut transaction, last_valid_block_height) = self.prepare(payload)?;
+        let recent_blockhash = transaction.message.recent_blockhash;
+        sign(&mut transaction, recent_blockhash)?;
+
+        let signature = transaction.signatures[0];
+        println!("Dry run, transaction not sent: {}", signature);
+        let message = &transaction.message;
+        for instruction in &message.instructions {
+            println!(
+                "  Program {}: {}",
+                message.account_keys[instruction.program_id_index as usize],
+                instruction
+                    .accounts
+                    .iter()
+                    .map(|index| message.account_keys[*index as usize].to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        if let Some(mut pending) = pending {
+            (pending.record)(db, signature, last_valid_block_height)?;
+            if pending.kind == PendingKind::Transfer {
+                db.confirm_transfer(signature, db.get_reporting_timezone().today())?;
+            }
+        }
+        Ok(signature)
+    }
+
+    // `RpcClient` only handles legacy transactions, so versioned ones are simulated over raw RPC
+    fn simulate_versioned(
+        &self,
+        transaction: &VersionedTransaction,
+    ) -> Result<RpcSimulateTransactionResult, Box<dyn std::error::Error>> {
+        let response: Response<RpcSimulateTransactionResult> = self.rpc_client.send(
+            RpcRequest::SimulateTransaction,
+            json!([
+                base64::encode(bincode::serialize(transaction)?),
+                {
+                    "sigVerify": false,
+                    "encoding": "base64",
+                    "commitment": self.rpc_client.commitment().commitment,
+                }
+            ]),
+        )?;
+        Ok(response.value)
+    }
+
+    fn get_fee_for_versioned_message(
+        &self,
+        message: &VersionedMessage,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let response: Response<Option<u64>> = self.rpc_client.send(
+            RpcRequest::GetFeeForMessage,
+            json!([
+                base64::encode(bincode::serialize(message)?),
+                { "commitment": self.rpc_client.commitment().commitment }
+            ]),
+        )?;
+        response
+            .value
+            .ok_or_else(|| "Blockhash expired while estimating the fee".into())
+    }
+
+    // Like `execute()`, for a message compiled elsewhere that may use address lookup tables, such
+    // as a Jupiter swap. Only its blockhash is refreshed
+    pub async fn execute_versioned<T: Signers>(
+        &self,
+        db: &mut Db,
+        mut message: VersionedMessage,
+        signers: &T,
+        mut pending: Option<Pending<'_>>,
+    ) -> Result<Signature, Box<dyn std::error::Error>> {
+        if SIGN_ONLY.get().is_some() {
+            return Err("Versioned transactions cannot be signed offline or proposed".into());
+        }
+
+        for attempt in 0..=self.settings.max_blockhash_refreshes {
+            if attempt > 0 {
+                println!("Transaction expired, retrying with a new blockhash");
+            }
+
+            let (recent_blockhash, last_valid_block_height) = self
+                .rpc_client
+                .get_latest_blockhash_with_commitment(self.rpc_client.commitment())?;
+            match message {
+                VersionedMessage::Legacy(ref mut message) => {
+                    message.recent_blockhash = recent_blockhash
+                }
+                VersionedMessage::V0(ref mut message) => {
+                    message.recent_blockhash = recent_blockhash
+                }
+            }
+            let transaction = sign_versioned(message.clone(), signers)?;
+
+            let simulation_result = self.simulate_versioned(&transaction)?;
+            if simulation_result.err.is_some() {
+                return Err(format!("Simulation failure: {:?}", simulation_result).into());
+            }
+
+            let fee = self.get_fee_for_versioned_message(&transaction.message)?;
+            let fee_payer = static_account_keys(&transaction.message)[0];
+            if self.rpc_client.get_balance(&fee_payer)? < fee {
+                return Err(format!(
+                    "Fee payer {} has insufficient funds for the transaction fee of ◎{}",
+                    fee_payer,
+                    lamports_to_sol(fee)
+                )
+                .into());
+            }
+
+            let signature = transaction.signatures[0];
+            if dry_run::enabled() {
+                println!("Dry run, transaction not sent: {}", signature);
+                if let Some(mut pending) = pending {
+                    (pending.record)(db, signature, last_valid_block_height)?;
+                    if pending.kind == PendingKind::Transfer {
+                        db.confirm_transfer(signature, db.get_reporting_timezone().today())?;
+                    }
+                }
+                return Ok(signature);
+            }
+            println!("Transaction signature: {}", signature);
+
+            if let Some(ref mut pending) = pending {
+                (pending.record)(db, signature, last_valid_block_height)?;
+            }
+
+            let result = self
+                .send_until_expired(&transaction, &Expiry::BlockHeight(last_valid_block_height));
+            if let Ok(true) = result {
+                self.confirm(db, pending.as_ref().map(|pending| pending.kind), signature)
+                    .await?;
+                return Ok(signature);
+            }
+
+            if let Some(ref pending) = pending {
+                pending.kind.cancel(db, signature)?;
+            }
+            result?;
+        }
+        Err("Transaction expired".into())
+    }
+
+    fn write_sign_only<S>(
+        &self,
+        db: &mut Db,
+        payload: &TransactionPayload,
+        mut sign: S,
+        pending: Option<Pending>,
+        durable_nonce_account: Pubkey,
+        output: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        S: FnMut(&mut Transaction, Hash) -> Result<(), SignerError>,
+    {
+        let (durable_nonce_blockhash, authority) =
+            self.get_durable_nonce_blockhash(&durable_nonce_account)?;
+        let durable_nonce = DurableNonce {
+            account: durable_nonce_account,
+            authority,
+        };
+        println!("Nonce authority: {}", authority);
+
+        let mut transaction =
+            self.prepare_with_blockhash(payload, durable_nonce_blockhash, Some(&durable_nonce))?;
+
+        // Keypairs available on this machine, such as a new stake account, sign now. The
+        // remaining signatures are added offline
+        match sign(&mut transaction, durable_nonce_blockhash) {
+            Ok(()) | Err(SignerError::NotEnoughSigners) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        // The fee payer signature isn't known yet, so the pending record is keyed by the message
+        let pending_signature = pending.map(|mut pending| {
+            let pending_signature = provisional_signature(&transaction.message);
+            (pending.record)(db, pending_signature, OFFLINE_BLOCK_HEIGHT)
+                .map(|()| pending_signature)
+        });
+
+        let offline_transaction = OfflineTransaction {
+            transaction,
+            pending_signature: pending_signature.transpose()?,
+        };
+        offline_transaction.save(output)?;
+
+        println!("Unsigned transaction written to {}", output.display());
+        for signer in offline_transaction.missing_signers() {
+            println!("  Signature required from {}", signer);
+        }
+        println!("Sign it with `sys tx sign` and broadcast it with `sys tx submit`");
+        Ok(())
+    }
+
+    fn write_multisig_proposal<S>(
+        &self,
+        db: &mut Db,
+        payload: &TransactionPayload,
+        mut sign: S,
+        pending: Option<Pending>,
+        proposal: Pubkey,
+        output: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        S: FnMut(&mut Transaction, Hash) -> Result<(), SignerError>,
+    {
+        let TransactionPayload::Instructions { instructions, .. } = payload;
+        if db
+            .get_pending_multisig_proposals()
+            .iter()
+            .any(|pmp| pmp.proposal == proposal)
+        {
+            return Err(format!("Multisig proposal {} is already pending", proposal).into());
+        }
+
+        // Simulation doesn't verify signatures, so the multisig need not sign
+        let (mut transaction, _) = self.prepare(payload)?;
+
+        // The multisig can only sign for itself when executing the proposal
+        let recent_blockhash = transaction.message.recent_blockhash;
+        match sign(&mut transaction, recent_blockhash) {
+            Ok(()) | Err(SignerError::NotEnoughSigners) => {}
+            Err(err) => return Err(err.into()),
+        }
+        if let Some((_, signer)) = transaction
+            .signatures
+            .iter()
+            .zip(transaction.message.account_keys.iter())
+            .find(|(signature, _)| **signature != Signature::default())
+        {
+            return Err(format!(
+                "Multisig proposals cannot include a signature from {}",
+                signer
+            )
+            .into());
+        }
+
+        let pending_signature = pending.map(|mut pending| {
+            let pending_signature = provisional_signature(&transaction.message);
+            (pending.record)(db, pending_signature, OFFLINE_BLOCK_HEIGHT)
+                .map(|()| pending_signature)
+        });
+
+        let pending_multisig_proposal = PendingMultisigProposal {
+            proposal,
+            pending_signature: pending_signature.transpose()?,
+            instructions: instructions.iter().map(ProposalInstruction::from).collect(),
+        };
+        fs::write(
+            output,
+            serde_json::to_string_pretty(&serde_json::json!({
+                "proposal": proposal.to_string(),
+                "instructions": pending_multisig_proposal.instructions,
+            }))?,
+        )?;
+        db.add_pending_multisig_proposal(pending_multisig_proposal)?;
+
+        println!("Multisig proposal payload written to {}", output.display());
+        println!("`sys sync` will complete the operation once the proposal executes");
+        Ok(())
+    }
+
+    // Broadcasts a transaction signed offline and completes its pending deposit, swap or
+    // transfer. Should its nonce advance without the transaction landing, the pending record is
+    // cancelled
+    pub async fn submit(
+        &self,
+        db: &mut Db,
+        offline_transaction: OfflineTransaction,
+    ) -> Result<Signature, Box<dyn std::error::Error>> {
+        let missing_signers = offline_transaction.missing_signers();
+        if !missing_signers.is_empty() {
+            return Err(format!(
+                "Transaction is missing signatures from {}",
+                missing_signers
+                    .iter()
+                    .map(|signer| signer.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into());
+        }
+        offline_transaction
+            .transaction
+            .verify()
+            .map_err(|err| format!("Invalid transaction signature: {}", err))?;
+
+        let durable_nonce_account = offline_transaction
+            .durable_nonce_account()
+            .ok_or("Transaction does not use a durable nonce")?;
+        let transaction = offline_transaction.transaction;
+        let signature = transaction.signatures[0];
+        println!("Transaction signature: {}", signature);
+        if dry_run::enabled() {
+            println!("Dry run, transaction not sent");
+            return Ok(signature);
+        }
+
+        let pending_kind = match offline_transaction.pending_signature {
+            Some(pending_signature) => {
+                db.update_pending_signature(pending_signature, signature)?;
+                PendingKind::of(db, signature)
+            }
+            None => None,
+        };
+
+        let blockhash = transaction.message.recent_blockhash;
+        let result = self.send_until_expired(
+            &transaction.into(),
+            &Expiry::DurableNonce {
+                account: durable_nonce_account,
+                blockhash,
+            },
+        );
+        if let Ok(true) = result {
+            self.confirm(db, pending_kind, signature).await?;
+            return Ok(signature);
+        }
+
+        if let Some(pending_kind) = pending_kind {
+            pending_kind.cancel(db, signature)?;
+        }
+        result?;
+        Err(format!(
+            "Nonce account {} advanced without the transaction",
+            durable_nonce_account
+        )
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::pubsub::LocalPubsub,
+        solana_sdk::{
+            signature::{Keypair, Signer},
+            transaction::TransactionError,
+        },
+        std::{sync::Arc, thread},
+    };
+
+    // The mock RPC client reports block height 34, and with "sig_not_found" never finds a
+    // signature
+    fn test_pipeline<'a>(
+        rpc_client: &'a RpcClient,
+        pubsub: Option<&'a dyn Pubsub>,
+    ) -> TransactionPipeline<'a> {
+        TransactionPipeline {
+            rpc_client,
+            settings: TransactionSettings::default(),
+            pubsub,
+        }
+    }
+
+    fn new_transaction() -> VersionedTransaction {
+        let payer = Keypair::new();
+        let transfer = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 42);
+        Transaction::new(
+            &[&payer],
+            Message::new(&[transfer], Some(&payer.pubkey())),
+            Hash::default(),
+        )
+        .into()
+    }
+
+    fn new_offline_transaction(instructions: &[Instruction], payer: &Pubkey) -> OfflineTransaction {
+        OfflineTransaction {
+            transaction: Transaction::new_unsigned(Message::new(instructions, Some(payer))),
+            pending_signature: None,
+        }
+    }
+
+    #[test]
+    fn test_durable_nonce_account() {
+        let payer = Pubkey::new_unique();
+        let nonce_account = Pubkey::new_unique();
+        let advance_nonce = system_instruction::advance_nonce_account(&nonce_account, &payer);
+        let transfer = system_instruction::transfer(&payer, &Pubkey::new_unique(), 42);
+
+        let offline_transaction =
+            new_offline_transaction(&[advance_nonce.clone(), transfer.clone()], &payer);
+        assert_eq!(
+            offline_transaction.durable_nonce_account(),
+            Some(nonce_account)
+        );
+        assert_eq!(offline_transaction.missing_signers(), vec![payer]);
+
+        // The runtime only honors the nonce when it's advanced by the first instruction
+        assert_eq!(
+            new_offline_transaction(&[transfer.clone(), advance_nonce.clone()], &payer)
+                .durable_nonce_account(),
+            None
+        );
+        assert_eq!(
+            new_offline_transaction(&[set_compute_unit_limit(1_000), advance_nonce], &payer)
+                .durable_nonce_account(),
+            None
+        );
+        assert_eq!(
+            new_offline_transaction(&[transfer], &payer).durable_nonce_account(),
+            None
+        );
+        assert_eq!(
+            new_offline_transaction(&[], &payer).durable_nonce_account(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_send_until_expired_subscription() {
+        // Polling never finds the signature, so only the subscription can confirm it
+        let rpc_client = RpcClient::new_mock("sig_not_found".to_string());
+        let pubsub = Arc::new(LocalPubsub::default());
+        let pipeline = test_pipeline(&rpc_client, Some(pubsub.as_ref()));
+
+        for result in [Ok(()), Err(TransactionError::AccountInUse)] {
+            let transaction = new_transaction();
+            let signature = transaction.signatures[0];
+            let publisher = {
+                let pubsub = pubsub.clone();
+                let result = result.clone();
+                thread::spawn(move || {
+                    while !pubsub.publish_signature(&signature, result.clone()) {
+                        sleep(Duration::from_millis(10));
+                    }
+                })
+            };
+            let sent = pipeline.send_until_expired(&transaction, &Expiry::BlockHeight(1_000));
+            publisher.join().unwrap();
+            match result {
+                Ok(()) => assert!(sent.unwrap()),
+                Err(_) => assert!(sent.is_err()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_send_until_expired_expiry() {
+        let transaction = new_transaction();
+        let pubsub = LocalPubsub::default();
+
+        // Nothing is published and the status history doesn't have it either
+        let rpc_client = RpcClient::new_mock("sig_not_found".to_string());
+        assert!(!test_pipeline(&rpc_client, Some(&pubsub))
+            .send_until_expired(&transaction, &Expiry::BlockHeight(0))
+            .unwrap());
+
+        // Landed before the last status check
+        let rpc_client = RpcClient::new_mock("succeeds".to_string());
+        assert!(test_pipeline(&rpc_client, None)
+            .send_until_expired(&transaction, &Expiry::BlockHeight(0))
+            .unwrap());
+    }
+}
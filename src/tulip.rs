@@ -3,7 +3,10 @@ Ref: https://github.com/sol-farm/tulipv2-sdk/tree/main/common/src/lending
 */
 
 use {
-    crate::token::{MaybeToken, Token},
+    crate::{
+        currency::Currency,
+        token::{MaybeToken, Token},
+    },
     rust_decimal::prelude::*,
     solana_client::rpc_client::RpcClient,
     solana_sdk::{
@@ -34,6 +37,10 @@ impl From<&Token> for TulipLending {
             Token::tuSOL | Token::wSOL => TulipLending::sol(),
             Token::mSOL | Token::tumSOL => TulipLending::msol(),
             Token::stSOL | Token::tustSOL => TulipLending::stsol(),
+            Token::Registered(_) => panic!(
+                "Tulip deposits and withdrawals support built-in tokens only, not {}",
+                token
+            ),
         }
     }
 }
@@ -130,14 +137,24 @@ pub fn liquidity_token(token: &Token) -> MaybeToken {
     TulipLending::from(token).liquidity_token
 }
 
+// Token registry entries may name their own Tulip reserve
+fn reserve_address(token: &MaybeToken) -> Pubkey {
+    match token.token().and_then(|token| token.registry_entry()) {
+        Some(entry) => entry
+            .tulip_reserve
+            .unwrap_or_else(|| panic!("{} has no Tulip reserve", entry.symbol)),
+        None => TulipLending::from(token).reserve,
+    }
+}
+
 // Current `ui_amount` conversion rate back to the liquidity token
 pub async fn get_current_liquidity_token_rate(
     rpc_client: &RpcClient,
     token: &Token,
 ) -> Result<Decimal, Box<dyn std::error::Error>> {
-    let tulip_lending = TulipLending::from(token);
+    let reserve_address = reserve_address(&MaybeToken::from(*token));
     let reserve_account = rpc_client
-        .get_account_with_commitment(&tulip_lending.reserve, rpc_client.commitment())?
+        .get_account_with_commitment(&reserve_address, rpc_client.commitment())?
         .value
         .expect("reserve_account");
 
@@ -171,9 +188,9 @@ pub async fn get_current_lending_apr(
     rpc_client: &RpcClient,
     token: &MaybeToken,
 ) -> Result<f64, Box<dyn std::error::Error>> {
-    let tulip_lending = TulipLending::from(token);
+    let reserve_address = reserve_address(token);
     let reserve_account = rpc_client
-        .get_account_with_commitment(&tulip_lending.reserve, rpc_client.commitment())?
+        .get_account_with_commitment(&reserve_address, rpc_client.commitment())?
         .value
         .expect("reserve_account");
 
@@ -190,11 +207,13 @@ pub async fn get_current_lending_apr(
 pub async fn get_current_price(
     rpc_client: &RpcClient,
     token: &Token,
+    currency: Currency,
 ) -> Result<Decimal, Box<dyn std::error::Error>> {
-    let tulip_lending = TulipLending::from(token);
-    Ok(tulip_lending
-        .liquidity_token
-        .get_current_price(rpc_client)
+    let liquidity_token = token
+        .liquidity_token()
+        .ok_or_else(|| format!("{} has no liquidity token", token))?;
+    Ok(liquidity_token
+        .get_current_price(rpc_client, currency)
         .await?
         * get_current_liquidity_token_rate(rpc_client, token).await?)
 }